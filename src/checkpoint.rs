@@ -0,0 +1,148 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::output::AtomicFile;
+
+/// How far a decode had got, written every
+/// [`DecodeOptions::checkpoint_interval`](crate::DecodeOptions::checkpoint_interval) main
+/// frames to [`DecodeOptions::checkpoint_out`](crate::DecodeOptions::checkpoint_out) so a
+/// decode stopped by a crash can pick up from there with
+/// [`DecodeOptions::resume`](crate::DecodeOptions::resume).
+///
+/// Checkpoints are taken at keyframes, which are predicted from nothing before them, so
+/// the offset of the keyframe is all the predictor state there is to restore.
+///
+/// ```
+/// use bbe_reader::Checkpoint;
+///
+/// let checkpoint = Checkpoint {
+///     session: 1,
+///     session_offset: 4096,
+///     offset: 5120,
+///     rows_written: 300,
+/// };
+/// let text = checkpoint.to_string();
+/// assert_eq!(text, "session=1\nsession_offset=4096\noffset=5120\nrows_written=300\n");
+/// assert_eq!(text.parse::<Checkpoint>().ok(), Some(checkpoint));
+/// assert!("offset=5120\n".parse::<Checkpoint>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// The session the keyframe belongs to, counting from 0
+    pub session: usize,
+    /// Offset in the input of the session's `H Product:` line, 0 for the first session
+    pub session_offset: usize,
+    /// Offset in the input of the keyframe the rows after the checkpoint start at
+    pub offset: usize,
+    /// Rows written before the keyframe
+    pub rows_written: usize,
+}
+
+impl Checkpoint {
+    /// Reads a checkpoint file.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        std::fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Writes the checkpoint to `path`, replacing the one before only once it is
+    /// complete, so a crash while writing leaves the previous checkpoint.
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = AtomicFile::create(path)?;
+        write!(file, "{}", self)?;
+        file.commit()
+    }
+
+    /// Cuts `output`, the CSV or JSON Lines file the stopped decode was writing, back to
+    /// its first `header_rows` lines and the rows written before the checkpoint, and
+    /// leaves it positioned at the end for the resumed decode to append to. Rows written
+    /// after the checkpoint, and any line cut off by the crash, are dropped.
+    ///
+    /// ```
+    /// use std::io::{Read, Seek, SeekFrom, Write};
+    ///
+    /// use bbe_reader::Checkpoint;
+    ///
+    /// let path = std::env::temp_dir().join(format!("bbe_truncate_{}.csv", std::process::id()));
+    /// let mut output = std::fs::File::options()
+    ///     .create(true)
+    ///     .truncate(true)
+    ///     .read(true)
+    ///     .write(true)
+    ///     .open(&path)?;
+    /// output.write_all(b"loopIteration,time\n0,100\n1,200\n2,300\n3,4")?;
+    ///
+    /// let checkpoint = Checkpoint { session: 0, session_offset: 0, offset: 512, rows_written: 2 };
+    /// checkpoint.truncate_output(&mut output, 1)?;
+    /// output.write_all(b"2,300\n")?;
+    ///
+    /// let mut text = String::new();
+    /// output.seek(SeekFrom::Start(0))?;
+    /// output.read_to_string(&mut text)?;
+    /// assert_eq!(text, "loopIteration,time\n0,100\n1,200\n2,300\n");
+    ///
+    /// // The output must hold every row the checkpoint counts
+    /// let ahead = Checkpoint { rows_written: 5, ..checkpoint };
+    /// assert!(ahead.truncate_output(&mut output, 1).is_err());
+    /// std::fs::remove_file(&path)?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn truncate_output(&self, output: &mut File, header_rows: usize) -> io::Result<()> {
+        output.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(&mut *output);
+        let mut len = 0;
+        let mut line = Vec::new();
+        for _ in 0..header_rows + self.rows_written {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            if line.last() != Some(&b'\n') {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "the output holds fewer than the {} rows the checkpoint counts",
+                        self.rows_written
+                    ),
+                ));
+            }
+            len += read as u64;
+        }
+        drop(reader);
+        output.set_len(len)?;
+        output.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "session={}", self.session)?;
+        writeln!(f, "session_offset={}", self.session_offset)?;
+        writeln!(f, "offset={}", self.offset)?;
+        writeln!(f, "rows_written={}", self.rows_written)
+    }
+}
+
+impl std::str::FromStr for Checkpoint {
+    type Err = String;
+
+    /// Parses the `key=value` lines [`Checkpoint`] is written as.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let value = |key: &str| {
+            text.lines()
+                .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+                .ok_or_else(|| format!("the checkpoint has no {}", key))?
+                .trim()
+                .parse::<usize>()
+                .map_err(|e| format!("invalid checkpoint {}: {}", key, e))
+        };
+        Ok(Checkpoint {
+            session: value("session")?,
+            session_offset: value("session_offset")?,
+            offset: value("offset")?,
+            rows_written: value("rows_written")?,
+        })
+    }
+}
@@ -0,0 +1,110 @@
+use std::fmt;
+use std::io::Read;
+
+use csv::{ReaderBuilder, StringRecord, StringRecordsIntoIter};
+
+use crate::error::BblError;
+
+/// The first cell where a decoded CSV differs from a reference CSV.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// 0-based record number, counting the header row
+    pub row: usize,
+    /// 0-based position of the cell in its record
+    pub column: usize,
+    /// Cell in the reference, `None` if the reference record or file ends before it
+    pub expected: Option<String>,
+    /// Cell in the decoded output, `None` if the decoded record or file ends before it
+    pub actual: Option<String>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cell = |value: &Option<String>| match value {
+            Some(value) => format!("{:?}", value),
+            None => "nothing".to_string(),
+        };
+        write!(
+            f,
+            "row {}, column {}: expected {}, got {}",
+            self.row,
+            self.column,
+            cell(&self.expected),
+            cell(&self.actual)
+        )
+    }
+}
+
+/// Compares a decoded CSV against a known-good reference, cell by cell and including the
+/// header row, and returns the first difference.
+///
+/// ```
+/// use bbe_reader::{first_mismatch, DecodeOptions};
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration,time\n");
+/// log.extend_from_slice(b"H Field I encoding:1,1\n");
+/// log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01]);
+///
+/// let options = DecodeOptions {
+///     fields: vec!["loopIteration".to_string(), "time".to_string()],
+///     ..DecodeOptions::default()
+/// };
+/// let mut csv = Vec::new();
+/// bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+///
+/// let reference = "loopIteration,time\n0,100\n1,200\n";
+/// assert_eq!(first_mismatch(&csv[..], reference.as_bytes())?, None);
+///
+/// let mismatch = first_mismatch(&csv[..], "loopIteration,time\n0,100\n1,201\n".as_bytes())?
+///     .expect("the changed cell is found");
+/// assert_eq!((mismatch.row, mismatch.column), (2, 1));
+/// assert_eq!(mismatch.to_string(), r#"row 2, column 1: expected "201", got "200""#);
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+pub fn first_mismatch<A: Read, E: Read>(
+    actual: A,
+    reference: E,
+) -> Result<Option<Mismatch>, BblError> {
+    let mut actual = csv_records(actual);
+    let mut reference = csv_records(reference);
+
+    for row in 0.. {
+        let (actual_record, expected_record) = match (actual.next(), reference.next()) {
+            (None, None) => break,
+            (actual_record, expected_record) => {
+                (actual_record.transpose()?, expected_record.transpose()?)
+            }
+        };
+        let width = |record: &Option<StringRecord>| record.as_ref().map_or(0, |r| r.len());
+
+        for column in 0..width(&actual_record).max(width(&expected_record)) {
+            let cell = |record: &Option<StringRecord>| {
+                record
+                    .as_ref()
+                    .and_then(|record| record.get(column))
+                    .map(str::to_string)
+            };
+            let (actual_cell, expected_cell) = (cell(&actual_record), cell(&expected_record));
+            if actual_cell != expected_cell {
+                return Ok(Some(Mismatch {
+                    row,
+                    column,
+                    expected: expected_cell,
+                    actual: actual_cell,
+                }));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Reads every record of a CSV, the header row included, allowing ragged rows.
+fn csv_records<R: Read>(input: R) -> StringRecordsIntoIter<R> {
+    ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(input)
+        .into_records()
+}
@@ -0,0 +1,179 @@
+use crate::header::{
+    header_value, is_digital_motor_protocol, motor_range, parse_throttle_range, parse_vbatref,
+};
+use crate::units::parse_gyro_scale;
+
+/// The system configuration a log's headers record, with each value parsed into its
+/// type. Headers the log lacks, or whose value cannot be parsed, leave their field
+/// `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SystemConfig {
+    /// `Firmware type`, e.g. `Cleanflight` for Betaflight and INAV logs
+    pub firmware_type: Option<String>,
+    /// `Firmware revision`, e.g. `Betaflight 4.5.1 (77d01ba3b) STM32F405`
+    pub firmware_revision: Option<String>,
+    /// `Board information`, the flight controller board
+    pub board_information: Option<String>,
+    /// `Craft name`
+    pub craft_name: Option<String>,
+    /// `Data version` of the log format
+    pub data_version: Option<u32>,
+    /// Factor from raw gyro readings to deg/s (rad/µs for Baseflight), from `gyro_scale`
+    pub gyro_scale: Option<f64>,
+    /// Raw accelerometer reading of 1 g, from `acc_1G`
+    pub acc_1g: Option<u32>,
+    /// Battery voltage reference `vbatref`, the baseline of vbat fields
+    pub vbat_reference: Option<i64>,
+    /// Battery voltage divider scale `vbatscale`
+    pub vbat_scale: Option<u32>,
+    /// Lowest and highest motor output, from `motorOutput`, or 48 and 2047 for digital
+    /// motors logged without it
+    pub motor_output: Option<(i64, i64)>,
+    /// The motors are driven by DShot or ProShot, whose motor values are 0 for a stopped
+    /// motor and otherwise in `motor_output`, from `motor_pwm_protocol` or a
+    /// `motorOutput` range ending at 2047
+    pub digital_motors: bool,
+    /// Lowest and highest throttle a motor is driven at, from `minthrottle` and
+    /// `maxthrottle`
+    pub throttle_range: Option<(i64, i64)>,
+    /// Gyro loop period in µs, from `looptime`
+    pub looptime: Option<u32>,
+    /// Storage the log was recorded to, from `Blackbox device`
+    pub blackbox_device: Option<BlackboxDevice>,
+}
+
+/// Storage a log was recorded to. The log format is the same on all of them, but how
+/// many frames the firmware drops when the storage falls behind differs.
+///
+/// ```
+/// use bbe_reader::{BblLog, BlackboxDevice};
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Blackbox device:SPIFLASH\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration\n");
+///
+/// let config = bbe_reader::parse_system_config(&BblLog::from_bytes(&log)?.headers);
+///
+/// assert_eq!(config.blackbox_device, Some(BlackboxDevice::Flash));
+/// assert_eq!(BlackboxDevice::Flash.to_string(), "onboard flash");
+/// // Betaflight's device numbers are accepted too
+/// assert_eq!(BlackboxDevice::parse("2"), Some(BlackboxDevice::SdCard));
+/// assert_eq!(BlackboxDevice::parse("OpenLager"), Some(BlackboxDevice::Serial));
+/// assert!(BlackboxDevice::Flash.expected_missing_fraction()
+///     > BlackboxDevice::SdCard.expected_missing_fraction());
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlackboxDevice {
+    /// SPI flash chip on the flight controller
+    Flash,
+    /// SD card slot on the flight controller
+    SdCard,
+    /// Serial port to an external logger such as an OpenLog or OpenLager
+    Serial,
+}
+
+impl BlackboxDevice {
+    /// Parses a `Blackbox device` value: a device name such as `SDCARD` or `SPIFLASH`, a
+    /// serial logger's name, or Betaflight's device number (1 flash, 2 SD card, 3 serial).
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().to_ascii_uppercase();
+        match value.as_str() {
+            "1" => Some(BlackboxDevice::Flash),
+            "2" => Some(BlackboxDevice::SdCard),
+            "3" => Some(BlackboxDevice::Serial),
+            _ if value.contains("FLASH") => Some(BlackboxDevice::Flash),
+            _ if value.starts_with("SD") => Some(BlackboxDevice::SdCard),
+            _ if ["SERIAL", "OPENLOG", "OPENLAGER"]
+                .iter()
+                .any(|name| value.contains(name)) =>
+            {
+                Some(BlackboxDevice::Serial)
+            }
+            _ => None,
+        }
+    }
+
+    /// Fraction of main frames a healthy log on this device may be missing. Flash
+    /// chips stall on page and sector erases, so flash logs drop the most.
+    pub fn expected_missing_fraction(self) -> f64 {
+        match self {
+            BlackboxDevice::Flash => 0.02,
+            BlackboxDevice::SdCard => 0.005,
+            BlackboxDevice::Serial => 0.01,
+        }
+    }
+}
+
+impl std::fmt::Display for BlackboxDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BlackboxDevice::Flash => "onboard flash",
+            BlackboxDevice::SdCard => "SD card",
+            BlackboxDevice::Serial => "serial logger",
+        })
+    }
+}
+
+/// Parses the system configuration headers of a log into a [`SystemConfig`].
+///
+/// ```
+/// use bbe_reader::BblLog;
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Data version:2\n");
+/// log.extend_from_slice(b"H Firmware type:Cleanflight\n");
+/// log.extend_from_slice(b"H Firmware revision:Betaflight 4.5.1 (77d01ba3b) STM32F405\n");
+/// log.extend_from_slice(b"H looptime:125\n");
+/// log.extend_from_slice(b"H gyro_scale:0x3f800000\n");
+/// log.extend_from_slice(b"H acc_1G:2048\n");
+/// log.extend_from_slice(b"H motorOutput:48,2047\n");
+/// log.extend_from_slice(b"H minthrottle:1070\n");
+/// log.extend_from_slice(b"H maxthrottle:2000\n");
+/// log.extend_from_slice(b"H vbatscale:110\n");
+/// log.extend_from_slice(b"H vbatref:not a number\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration\n");
+///
+/// let config = bbe_reader::parse_system_config(&BblLog::from_bytes(&log)?.headers);
+///
+/// assert_eq!(config.data_version, Some(2));
+/// assert_eq!(
+///     config.firmware_revision.as_deref(),
+///     Some("Betaflight 4.5.1 (77d01ba3b) STM32F405")
+/// );
+/// assert_eq!(config.looptime, Some(125));
+/// assert_eq!(config.gyro_scale, Some(1.0));
+/// assert_eq!(config.acc_1g, Some(2048));
+/// assert_eq!(config.motor_output, Some((48, 2047)));
+/// assert!(config.digital_motors);
+/// assert_eq!(config.throttle_range, Some((1070, 2000)));
+/// assert_eq!(config.vbat_scale, Some(110));
+/// // Unparseable and missing headers are left out
+/// assert_eq!(config.vbat_reference, None);
+/// assert_eq!(config.craft_name, None);
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+pub fn parse_system_config(headers: &[String]) -> SystemConfig {
+    let text = |key| header_value(headers, key).map(str::to_string);
+    let number = |key| header_value(headers, key).and_then(|value| value.parse().ok());
+    SystemConfig {
+        firmware_type: text("Firmware type"),
+        firmware_revision: text("Firmware revision"),
+        board_information: text("Board information"),
+        craft_name: text("Craft name"),
+        data_version: number("Data version"),
+        gyro_scale: header_value(headers, "gyro_scale")
+            .and_then(parse_gyro_scale)
+            .map(f64::from),
+        acc_1g: number("acc_1G"),
+        vbat_reference: parse_vbatref(headers),
+        vbat_scale: number("vbatscale"),
+        motor_output: motor_range(headers),
+        digital_motors: is_digital_motor_protocol(headers),
+        throttle_range: parse_throttle_range(headers),
+        looptime: number("looptime"),
+        blackbox_device: header_value(headers, "Blackbox device").and_then(BlackboxDevice::parse),
+    }
+}
@@ -0,0 +1,2217 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+
+use crate::checkpoint::Checkpoint;
+use crate::encoding::{
+    bytes_needed, group_len, max_encoded_len, read_field_value, read_signed_vlq, read_tag2_3s32,
+    read_tag8_4s16, read_tag8_8svb, read_unsigned_vlq, TAG2_3S32, TAG8_4S16, TAG8_8SVB,
+};
+use crate::flags::flag_names;
+use crate::header::{
+    find_product_header, header_block_len, is_digital_motor_protocol, motor_range,
+    parse_frame_intervals, parse_loop_period, parse_min_throttle, parse_motor_output,
+    parse_slow_interval, parse_throttle_range, parse_vbatref, BblLog, FieldDefinition,
+    FrameIntervals, HeaderOptions, PRODUCT_HEADER,
+};
+use crate::output::{push_empty, push_value, ColumnFormat, GapThreshold, RecordSink, SlowMaxAge};
+use crate::track::{TrackFormat, TrackWriter};
+use crate::{BblError, DecodeOptions};
+
+/// Default longest frame, in bytes including its marker, before it is taken as corrupt.
+pub const MAX_FRAME_SIZE: usize = 256;
+
+/// Frame types that can appear in the binary section of a log.
+pub const FRAME_TYPES: [char; 6] = ['I', 'P', 'S', 'G', 'H', 'E'];
+
+/// Names of the events [`DecodeOptions::stop_at_event`] can stop at. `failsafe` is a
+/// flight mode change that enters failsafe mode.
+pub const EVENT_NAMES: [&str; 7] = [
+    "arm",
+    "inflight-adjustment",
+    "logging-resume",
+    "disarm",
+    "flight-mode",
+    "failsafe",
+    "log-end",
+];
+
+/// Bit of `FAILSAFE_MODE` in the flight mode flags.
+const FAILSAFE_MODE: u32 = 1 << 10;
+
+/// Counters collected while decoding, reported by `--stats`.
+#[derive(Debug, Default, Clone)]
+pub struct DecodeStats {
+    /// Number of frames decoded per frame type
+    pub frame_counts: BTreeMap<char, usize>,
+    /// Rows written to the output
+    pub rows_written: usize,
+    /// Keyframes that were missing or early compared to the `I interval` header
+    pub i_interval_deviations: usize,
+    /// Main frames expected per `I interval`, if the headers declare one
+    pub frames_per_interval: Option<usize>,
+    /// Spans where the firmware paused logging, in log order
+    pub paused_spans: Vec<PausedSpan>,
+    /// Bytes skipped while searching for a keyframe to start or resume decoding at
+    pub skipped_bytes: usize,
+    /// Frames longer than [`DecodeOptions::max_frame_size`], skipped as corrupt
+    pub oversized_frames: usize,
+    /// Times decoding skipped ahead to the next I-frame after a frame failed to decode,
+    /// was oversized or had an unknown marker. An I-frame predicts only from itself and
+    /// the headers, so decoding can safely resume there.
+    pub resyncs: usize,
+    /// P-frames skipped because no I-frame came before them in their session, as in a
+    /// log cut mid-stream.
+    pub unanchored_frames: usize,
+    /// Event frames in log order
+    pub events: Vec<LoggedEvent>,
+    /// Main frames missing where `loopIteration` skips ahead, outside logging pauses
+    pub missing_frames: usize,
+    /// Places where `loopIteration` skips ahead and main frames are missing
+    pub gaps: usize,
+    /// The event decoding stopped at, with [`DecodeOptions::stop_at_event`]
+    pub stopped_at: Option<LoggedEvent>,
+    /// Intervals between consecutive main frames whose loop time budget is known, when
+    /// loop overruns are detected
+    pub timed_intervals: usize,
+    /// Timed intervals that took longer than their loop time budget
+    pub overrun_intervals: usize,
+    /// µs between the first and last main frame of each session, summed over the
+    /// sessions, from the `time` field
+    pub logged_time: u64,
+    /// Number of timed intervals by their time per loop iteration, rounded to µs
+    pub iteration_times: BTreeMap<u64, usize>,
+    /// Loop period in µs the headers of the first timed session declare, from `looptime`
+    /// and `pid_process_denom`
+    pub declared_loop_period: Option<f64>,
+    /// Vibration of each whole window of [`DecodeOptions::vibration_window`] main frames,
+    /// in raw accelerometer units
+    pub vibration: Vec<f64>,
+    /// Main frames whose motor outputs were checked for clipping or anomalies
+    pub motor_samples: usize,
+    /// Main frames with each motor, by index, at or near the top of the `motorOutput`
+    /// range, when motor clipping is detected
+    pub clipped_samples: Vec<usize>,
+    /// Main frames with each motor, by index, below `minthrottle` or above
+    /// `maxthrottle`, or for digital motors outside the `motorOutput` range and not
+    /// stopped, when anomalies are reported
+    pub anomalous_samples: Vec<usize>,
+    /// Fields read and bytes consumed per encoding, when encoding usage is counted
+    pub encodings: BTreeMap<u8, EncodingUsage>,
+    /// Aggregates of each output column, in column order, when column statistics are
+    /// collected
+    pub columns: Vec<ColumnSummary>,
+}
+
+/// How much of a log one field encoding stores, counted with
+/// [`DecodeOptions::encoding_stats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EncodingUsage {
+    /// Field values read with the encoding
+    pub fields: usize,
+    /// Bytes those values took, including the tag bytes of grouped encodings
+    pub bytes: usize,
+}
+
+/// Aggregates of the numeric cells written to one output column, collected with
+/// [`DecodeOptions::column_stats`]. Empty and non-numeric cells are not counted.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ColumnSummary {
+    /// Name of the column, as in the header row
+    pub name: String,
+    /// Numeric cells counted
+    pub count: usize,
+    /// Smallest value counted, 0 if there are none
+    pub min: f64,
+    /// Largest value counted, 0 if there are none
+    pub max: f64,
+    /// Mean of the values counted, 0 if there are none
+    pub mean: f64,
+    /// Sum of the squared differences from the mean, updated with Welford's method
+    squares: f64,
+}
+
+impl ColumnSummary {
+    pub(crate) fn new(name: &str) -> Self {
+        ColumnSummary {
+            name: name.to_string(),
+            ..ColumnSummary::default()
+        }
+    }
+
+    pub(crate) fn add(&mut self, value: f64) {
+        if self.count == 0 {
+            (self.min, self.max) = (value, value);
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.squares += delta * (value - self.mean);
+    }
+
+    /// Population standard deviation of the counted cells, 0 if there are none.
+    pub fn std_dev(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        (self.squares / self.count as f64).sqrt()
+    }
+
+    /// No cell was numeric, or every one was zero.
+    pub fn is_blank(&self) -> bool {
+        self.count == 0 || (self.min == 0.0 && self.max == 0.0)
+    }
+}
+
+impl DecodeStats {
+    /// Main frames decoded, keyframes and interframes together.
+    pub fn main_frames(&self) -> usize {
+        ['I', 'P']
+            .iter()
+            .filter_map(|frame_type| self.frame_counts.get(frame_type))
+            .sum()
+    }
+
+    /// Fraction of the main frames the log should have held that are missing, `None` if
+    /// no main frame was decoded.
+    ///
+    /// ```
+    /// let mut log = Vec::new();
+    /// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+    /// log.extend_from_slice(b"H Field I name:loopIteration,time\n");
+    /// log.extend_from_slice(b"H Field I encoding:1,1\n");
+    /// // Iteration 2 is missing, then 4 to 6
+    /// log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01, b'I', 3, 0x90, 0x03]);
+    /// log.extend_from_slice(&[b'I', 7, 0xa0, 0x06]);
+    ///
+    /// let stats = bbe_reader::decode_to_writer(&log[..], std::io::sink(), Default::default())?;
+    ///
+    /// assert_eq!(stats.main_frames(), 4);
+    /// assert_eq!((stats.gaps, stats.missing_frames), (2, 4));
+    /// assert_eq!(stats.missing_fraction(), Some(0.5));
+    /// # Ok::<(), bbe_reader::BblError>(())
+    /// ```
+    pub fn missing_fraction(&self) -> Option<f64> {
+        let decoded = self.main_frames();
+        (decoded > 0).then(|| self.missing_frames as f64 / (decoded + self.missing_frames) as f64)
+    }
+
+    /// Fraction of timed intervals that overran their loop time budget, `None` if no
+    /// interval could be timed.
+    pub fn overrun_fraction(&self) -> Option<f64> {
+        (self.timed_intervals > 0)
+            .then(|| self.overrun_intervals as f64 / self.timed_intervals as f64)
+    }
+
+    /// Median time per loop iteration in µs over the timed intervals, `None` if no
+    /// interval could be timed.
+    pub fn median_iteration_time(&self) -> Option<u64> {
+        let middle = self.timed_intervals.checked_sub(1)? / 2;
+        let mut seen = 0;
+        self.iteration_times.iter().find_map(|(&time, &count)| {
+            seen += count;
+            (seen > middle).then_some(time)
+        })
+    }
+
+    /// How far the median loop iteration time is from the loop period the headers
+    /// declare, as a fraction of the declared period. A logged loop much slower than
+    /// declared usually means the flight controller was overloaded.
+    ///
+    /// ```
+    /// use bbe_reader::DecodeOptions;
+    ///
+    /// let mut log = Vec::new();
+    /// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+    /// log.extend_from_slice(b"H looptime:125\n");
+    /// log.extend_from_slice(b"H Field I name:loopIteration,time\n");
+    /// log.extend_from_slice(b"H Field I encoding:1,1\n");
+    /// // Loops every 250 µs instead of 125 µs
+    /// log.extend_from_slice(&[b'I', 0, 0, b'I', 1, 0xfa, 0x01]);
+    /// log.extend_from_slice(&[b'I', 2, 0xf4, 0x03, b'I', 3, 0xee, 0x05]);
+    ///
+    /// let options = DecodeOptions {
+    ///     detect_loop_overrun: true,
+    ///     ..DecodeOptions::default()
+    /// };
+    /// let stats = bbe_reader::decode_to_writer(&log[..], std::io::sink(), options)?;
+    ///
+    /// assert_eq!(stats.median_iteration_time(), Some(250));
+    /// assert_eq!(stats.loop_period_deviation(), Some(1.0));
+    /// # Ok::<(), bbe_reader::BblError>(())
+    /// ```
+    pub fn loop_period_deviation(&self) -> Option<f64> {
+        let declared = self.declared_loop_period?;
+        let median = self.median_iteration_time()? as f64;
+        Some((median - declared) / declared)
+    }
+
+    /// Fraction of the checked main frames each motor, by index, spent clipped. Empty if
+    /// no frame could be checked.
+    pub fn clipping_fractions(&self) -> Vec<f64> {
+        if self.motor_samples == 0 {
+            return Vec::new();
+        }
+        self.clipped_samples
+            .iter()
+            .map(|&clipped| clipped as f64 / self.motor_samples as f64)
+            .collect()
+    }
+}
+
+/// A gap in the log where logging was paused, ended by a logging resume event.
+///
+/// Frames are missing over the span on purpose, so it is not counted as dropped frames.
+///
+/// ```
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration,time\n");
+/// log.extend_from_slice(b"H Field I encoding:1,1\n");
+/// log.extend_from_slice(&[b'I', 0, 100]);
+/// // Logging resumed at iteration 50, 600 µs
+/// log.extend_from_slice(&[b'E', 14, 50, 0xd8, 0x04]);
+/// log.extend_from_slice(&[b'I', 50, 0xd8, 0x04]);
+///
+/// let mut csv = Vec::new();
+/// let stats = bbe_reader::decode_to_writer(&log[..], &mut csv, Default::default())?;
+///
+/// let span = &stats.paused_spans[0];
+/// assert_eq!((span.last_time, span.resume_time), (Some(100), 600));
+/// assert_eq!(span.duration(), Some(500));
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PausedSpan {
+    /// `time` of the last main frame before the pause, if one was decoded
+    pub last_time: Option<i64>,
+    /// Loop iteration logging resumed at
+    pub resume_iteration: u32,
+    /// Time in µs logging resumed at
+    pub resume_time: u32,
+}
+
+impl PausedSpan {
+    /// Length of the pause in µs, if the time it started is known.
+    pub fn duration(&self) -> Option<i64> {
+        self.last_time
+            .map(|last_time| self.resume_time as i64 - last_time)
+    }
+}
+
+/// The payload of an event frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// Beep the firmware logs when the craft arms, at `time` µs
+    SyncBeep { time: u32 },
+    /// A setting was changed in flight through an adjustment channel
+    InflightAdjustment { function: u8, value: f64 },
+    /// Logging restarted after a pause
+    LoggingResume { iteration: u32, time: u32 },
+    /// The craft disarmed
+    Disarm { reason: u32 },
+    /// The active flight mode flags changed, e.g. on entering failsafe
+    FlightMode { flags: u32, last_flags: u32 },
+    /// The firmware stopped logging; nothing valid follows
+    LogEnd,
+}
+
+impl Event {
+    /// Time in µs the event carries itself, if any.
+    fn time(&self) -> Option<i64> {
+        match *self {
+            Event::SyncBeep { time } | Event::LoggingResume { time, .. } => Some(time as i64),
+            _ => None,
+        }
+    }
+
+    /// Whether the event is of the kind named by one of [`EVENT_NAMES`].
+    pub fn is(&self, name: &str) -> bool {
+        match *self {
+            Event::SyncBeep { .. } => name == "arm",
+            Event::InflightAdjustment { .. } => name == "inflight-adjustment",
+            Event::LoggingResume { .. } => name == "logging-resume",
+            Event::Disarm { .. } => name == "disarm",
+            Event::FlightMode { flags, last_flags } => {
+                name == "flight-mode"
+                    || (name == "failsafe"
+                        && flags & FAILSAFE_MODE != 0
+                        && last_flags & FAILSAFE_MODE == 0)
+            }
+            Event::LogEnd => name == "log-end",
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::SyncBeep { .. } => write!(f, "arm (sync beep)"),
+            Event::InflightAdjustment { function, value } => {
+                write!(f, "in-flight adjustment {} set to {}", function, value)
+            }
+            Event::LoggingResume { iteration, .. } => {
+                write!(f, "logging resumed at iteration {}", iteration)
+            }
+            Event::Disarm { reason } => write!(f, "disarm (reason {})", reason),
+            Event::FlightMode { flags, last_flags } => write!(
+                f,
+                "flight mode flags 0x{:x} (were 0x{:x})",
+                flags, last_flags
+            ),
+            Event::LogEnd => write!(f, "log end"),
+        }
+    }
+}
+
+/// An event frame with the time it happened at.
+///
+/// ```
+/// use bbe_reader::Event;
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration,time\n");
+/// log.extend_from_slice(b"H Field I encoding:1,1\n");
+/// // Armed at 50 µs, disarmed after the frame at 200 µs
+/// log.extend_from_slice(&[b'E', 0, 50, b'I', 0, 100, b'I', 1, 0xc8, 0x01]);
+/// log.extend_from_slice(&[b'E', 15, 4, b'E', 255]);
+/// log.extend_from_slice(b"End of log\0");
+///
+/// let stats = bbe_reader::decode_to_writer(&log[..], std::io::sink(), Default::default())?;
+///
+/// let events: Vec<_> = stats.events.iter().map(|e| (e.time, e.event)).collect();
+/// assert_eq!(
+///     events,
+///     [
+///         (Some(50), Event::SyncBeep { time: 50 }),
+///         (Some(200), Event::Disarm { reason: 4 }),
+///         (Some(200), Event::LogEnd),
+///     ]
+/// );
+/// assert_eq!(stats.events[1].to_string(), "200 µs: disarm (reason 4)");
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoggedEvent {
+    /// Time in µs: the event's own if it has one, otherwise the `time` of the latest main
+    /// frame, `None` if neither is known
+    pub time: Option<i64>,
+    pub event: Event,
+}
+
+impl fmt::Display for LoggedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.time {
+            Some(time) => write!(f, "{} µs: {}", time, self.event),
+            None => write!(f, "?: {}", self.event),
+        }
+    }
+}
+
+/// An output column: where its value comes from and how to format it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Column {
+    pub(crate) source: ColumnSource,
+    pub(crate) format: ColumnFormat,
+}
+
+/// Where the values of an output column come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ColumnSource {
+    /// A field of the main frame layout, by position
+    Field(usize),
+    /// A timestamp computed from the loop iteration and the loop period
+    SynthesizedTime,
+    /// The 0-based position of the row in the output
+    SampleIndex,
+    /// The mean of `count` consecutive fields starting at `first`, e.g. the motor outputs
+    FieldAverage { first: usize, count: usize },
+    /// A field of the latest slow (S) frame, by position
+    SlowField(usize),
+    /// Altitude in cm from the `baroAlt` main frame field if set, or else from the
+    /// `GPS_altitude` field of the latest GPS frame, in dm
+    Altitude {
+        baro: Option<usize>,
+        gps: Option<usize>,
+    },
+    /// Time in µs since the first written main frame, from `time` or the synthesized
+    /// time, written in seconds
+    RelativeTime,
+    /// A motor output field, by position, as its offset from the low end of the
+    /// `motorOutput` range, written scaled to a percentage of the range. A digital
+    /// motor's 0, which stops it, is the low end.
+    MotorOutput {
+        index: usize,
+        min: i64,
+        digital: bool,
+    },
+    /// 1 for a row decoded from a clean frame, 0 for a recovered frame or a gap marker
+    Validity,
+    /// A field the session does not log, which is left empty
+    Absent,
+}
+
+/// Time elapsed since the first written main frame, carried across logging pauses and
+/// sessions for [`ColumnSource::RelativeTime`].
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct RelativeClock {
+    /// Time of the latest written main frame and the time elapsed at it
+    last: Option<(i64, i64)>,
+}
+
+impl RelativeClock {
+    /// Advances to a main frame at `time`, returning the µs elapsed since the first. A
+    /// time running backwards, as when the clock resets, continues from the frame before.
+    fn advance(&mut self, time: i64) -> i64 {
+        let elapsed = match self.last {
+            Some((last_time, elapsed)) => elapsed + (time - last_time).max(0),
+            None => 0,
+        };
+        self.last = Some((time, elapsed));
+        elapsed
+    }
+}
+
+/// One logging session: a header block and the frames following it, with the output
+/// columns resolved against its own field layout.
+pub(crate) struct Session<'a> {
+    pub(crate) log: &'a BblLog,
+    pub(crate) data: &'a [u8],
+    pub(crate) columns: Vec<Column>,
+}
+
+/// Decodes the binary data of each session frame by frame and writes the selected
+/// fields of each main (I/P) frame to CSV. Slow, GPS and event frames are parsed to keep
+/// the stream aligned.
+pub(crate) fn decode_binary_data(
+    sessions: &[Session],
+    writer: &mut dyn RecordSink,
+    options: &DecodeOptions,
+) -> io::Result<DecodeStats> {
+    let mut stats = DecodeStats::default();
+    let mut outputs = SideOutputs::create(options)?;
+    let mut clock = RelativeClock::default();
+
+    for session in sessions {
+        decode_session(
+            session,
+            writer,
+            options,
+            &mut stats,
+            &mut outputs,
+            &mut clock,
+        )?;
+        if stats.stopped_at.is_some() {
+            break;
+        }
+    }
+
+    outputs.finish()?;
+    Ok(stats)
+}
+
+/// Files written next to the main output while decoding.
+pub(crate) struct SideOutputs {
+    /// The bytes of every frame, for `dump_frame_bytes`
+    frame_dump: Option<BufWriter<File>>,
+    /// The GPS frames, for `gps_out`
+    gps: Option<GpsWriter>,
+    /// The GPS tracks, for `gpx_out` and `kml_out`
+    tracks: Vec<TrackWriter>,
+}
+
+impl SideOutputs {
+    fn create(options: &DecodeOptions) -> io::Result<Self> {
+        let frame_dump = match &options.dump_frame_bytes {
+            Some(path) => Some(BufWriter::new(File::create(path)?)),
+            None => None,
+        };
+        let gps = match &options.gps_out {
+            Some(path) => Some(GpsWriter {
+                csv: csv::Writer::from_writer(BufWriter::new(File::create(path)?)),
+                columns: None,
+            }),
+            None => None,
+        };
+        let mut tracks = Vec::new();
+        for (path, format) in [
+            (&options.gpx_out, TrackFormat::Gpx),
+            (&options.kml_out, TrackFormat::Kml),
+        ] {
+            if let Some(path) = path {
+                tracks.push(TrackWriter::create(File::create(path)?, format)?);
+            }
+        }
+        Ok(SideOutputs {
+            frame_dump,
+            gps,
+            tracks,
+        })
+    }
+
+    /// Completes and flushes the files after the last frame.
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(dump) = self.frame_dump.as_mut() {
+            dump.flush()?;
+        }
+        if let Some(gps) = self.gps.as_mut() {
+            gps.csv.flush()?;
+        }
+        for track in &mut self.tracks {
+            track.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes GPS frames to a CSV of their own, with a column per field of the first
+/// session's GPS layout. Later sessions fill the columns by field name.
+struct GpsWriter {
+    csv: csv::Writer<BufWriter<File>>,
+    /// The header row, once written
+    columns: Option<Vec<String>>,
+}
+
+impl GpsWriter {
+    /// Writes the header row from the GPS layout of `log`, unless one was written.
+    fn start_session(&mut self, log: &BblLog) -> io::Result<()> {
+        if self.columns.is_none() {
+            let columns: Vec<String> = log
+                .layouts
+                .get(&b'G')
+                .map(Vec::as_slice)
+                .unwrap_or_default()
+                .iter()
+                .map(|field| field.name.clone())
+                .collect();
+            self.csv.write_record(&columns)?;
+            self.columns = Some(columns);
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, fields: &[FieldDefinition], values: &[i64]) -> io::Result<()> {
+        let columns = self.columns.as_deref().unwrap_or_default();
+        let record = columns.iter().map(|name| {
+            fields
+                .iter()
+                .position(|field| &field.name == name)
+                .map_or(String::new(), |index| values[index].to_string())
+        });
+        self.csv.write_record(record)?;
+        Ok(())
+    }
+}
+
+/// Decodes one session, adding to `stats` and writing its frames to `outputs`.
+fn decode_session(
+    session: &Session,
+    writer: &mut dyn RecordSink,
+    options: &DecodeOptions,
+    stats: &mut DecodeStats,
+    outputs: &mut SideOutputs,
+    clock: &mut RelativeClock,
+) -> io::Result<()> {
+    let Session { log, data, columns } = session;
+    let mut decoder = SessionDecoder::new(log, columns, options, stats, outputs, *clock)?;
+
+    // A byte range may start mid-frame, so decoding starts at its first keyframe
+    let data = match &options.byte_range {
+        Some(range) => {
+            let end = range.end.min(data.len());
+            decoder.resync = range.start > 0;
+            &data[range.start.min(end)..end]
+        }
+        None => *data,
+    };
+
+    let cursor = decoder.decode(data, 0, true, writer, stats, outputs)?;
+    let last_percent = decoder.last_percent;
+    *clock = decoder.clock;
+    decoder.finish(writer)?;
+
+    if options.progress_json && last_percent < 100 {
+        emit_progress_json(cursor, stats.main_frames(), data.len());
+    }
+    Ok(())
+}
+
+/// Decodes the sessions of a log read from `reader`, which is positioned at the first
+/// frame after the headers of `log`, holding only a window of the input and the two
+/// previous main frames the predictors refer to in memory, however long the log.
+/// `session_columns` gives the output columns of each session's header block.
+pub(crate) fn decode_stream<R: Read>(
+    log: &BblLog,
+    reader: R,
+    mut session_columns: impl FnMut(&BblLog) -> Vec<Column>,
+    writer: &mut dyn RecordSink,
+    options: &DecodeOptions,
+) -> Result<DecodeStats, BblError> {
+    let mut stats = DecodeStats::default();
+    let mut outputs = SideOutputs::create(options)?;
+    let mut buffer = StreamBuffer::new(reader);
+    let mut clock = RelativeClock::default();
+
+    let mut session_log = log.clone();
+    let mut number = 1;
+    let mut session_offset = 0;
+    if let Some(checkpoint) = options.resume {
+        stats.rows_written = checkpoint.rows_written;
+        if checkpoint.session > 0 {
+            buffer.skip(checkpoint.session_offset.saturating_sub(log.data_offset))?;
+            session_log = buffer.read_headers(&options.header)??;
+            number = checkpoint.session + 1;
+            session_offset = checkpoint.session_offset;
+        }
+        let position = log.data_offset + buffer.offset();
+        buffer.skip(checkpoint.offset.saturating_sub(position))?;
+        buffer.fill(1)?;
+        // A checkpoint of another log would quietly decode from the wrong place
+        let position = log.data_offset + buffer.offset();
+        if position != checkpoint.offset || buffer.data().first() != Some(&b'I') {
+            return Err(BblError::CheckpointMismatch {
+                offset: checkpoint.offset,
+            });
+        }
+    }
+    loop {
+        let columns = session_columns(&session_log);
+        let mut decoder = SessionDecoder::new(
+            &session_log,
+            &columns,
+            options,
+            &mut stats,
+            &mut outputs,
+            clock,
+        )?;
+        decoder.position = Some(InputPosition {
+            session: number - 1,
+            session_offset,
+            stream_start: log.data_offset,
+            data_offset: log.data_offset + buffer.offset(),
+        });
+        clock = decode_streamed_session(decoder, &mut buffer, writer, &mut stats, &mut outputs)?;
+        if stats.stopped_at.is_some() || buffer.data().is_empty() {
+            break;
+        }
+
+        // The buffer is now at the `H Product:` line of the next session
+        number += 1;
+        session_offset = log.data_offset + buffer.offset();
+        session_log = match buffer.read_headers(&options.header)? {
+            Ok(next) => next,
+            Err(e) => {
+                eprintln!(
+                    "Warning: cannot read the headers of session {}: {}, stopping",
+                    number, e
+                );
+                break;
+            }
+        };
+    }
+
+    outputs.finish()?;
+    Ok(stats)
+}
+
+/// Decodes one session of a stream up to the next `H Product:` line or the end of the
+/// input, refilling `buffer` as frames are consumed. Returns the relative clock for the
+/// next session to continue from.
+fn decode_streamed_session<R: Read>(
+    mut decoder: SessionDecoder,
+    buffer: &mut StreamBuffer<R>,
+    writer: &mut dyn RecordSink,
+    stats: &mut DecodeStats,
+    outputs: &mut SideOutputs,
+) -> io::Result<RelativeClock> {
+    // Frames are only decoded while the longest frame that could start there fits in
+    // the buffer, so a frame never straddles a refill
+    let lookahead = frame_lookahead(decoder.log, decoder.options.max_frame_size);
+
+    loop {
+        buffer.fill(STREAM_BUFFER_LEN.max(2 * lookahead))?;
+        let data = buffer.data();
+        let (end, at_end) = match find_product_header(data) {
+            Some(start) => (start, true),
+            None => (data.len(), buffer.at_eof()),
+        };
+
+        if let Some(position) = decoder.position.as_mut() {
+            position.data_offset = position.stream_start + buffer.offset();
+        }
+        let cursor = decoder.decode(&data[..end], 0, at_end, writer, stats, outputs)?;
+        // Anything left before the next header block cannot be decoded
+        if at_end {
+            buffer.consume(end);
+            break;
+        }
+        if decoder.ended {
+            buffer.skip_to_product_header()?;
+            break;
+        }
+        buffer.consume(cursor);
+    }
+    let clock = decoder.clock;
+    decoder.finish(writer)?;
+    Ok(clock)
+}
+
+/// Bytes read from a streamed input at a time.
+pub(crate) const STREAM_BUFFER_LEN: usize = 64 * 1024;
+
+/// Returns how many bytes must follow the start of a frame of `log` for it to decode
+/// whole: the marker, then the longest encoding of every field, and at least enough to
+/// tell that a frame is longer than `max_frame_size`.
+pub(crate) fn frame_lookahead(log: &BblLog, max_frame_size: usize) -> usize {
+    let longest_layout = log
+        .layouts
+        .values()
+        .map(|fields| max_frame_len(fields))
+        .max();
+    // An event frame holds at most a type, a function and a 4-byte float, or two
+    // variable-byte numbers and the end-of-log message
+    const LONGEST_EVENT: usize = 16;
+    (1 + longest_layout.unwrap_or(0))
+        .max(LONGEST_EVENT)
+        .max(max_frame_size + 1)
+}
+
+/// Returns the most bytes the fields of a frame with layout `fields` can take.
+fn max_frame_len(fields: &[FieldDefinition]) -> usize {
+    let mut len = 0;
+    let mut index = 0;
+    while let Some(field) = fields.get(index) {
+        let following = fields[index + 1..].iter().map(|f| f.encoding);
+        let count = group_len(field.encoding, following);
+        len += max_encoded_len(field.encoding, count);
+        index += count;
+    }
+    len
+}
+
+/// A window onto an input that is read in chunks, dropping bytes once they are consumed.
+pub(crate) struct StreamBuffer<R> {
+    reader: R,
+    bytes: Vec<u8>,
+    /// Start of the unconsumed bytes
+    start: usize,
+    /// Bytes consumed since the start of the input
+    consumed: usize,
+    eof: bool,
+}
+
+impl<R: Read> StreamBuffer<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        StreamBuffer {
+            reader,
+            bytes: Vec::new(),
+            start: 0,
+            consumed: 0,
+            eof: false,
+        }
+    }
+
+    /// The unconsumed bytes.
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.bytes[self.start..]
+    }
+
+    /// Whether everything has been read from the input.
+    pub(crate) fn at_eof(&self) -> bool {
+        self.eof
+    }
+
+    pub(crate) fn consume(&mut self, len: usize) {
+        let start = (self.start + len).min(self.bytes.len());
+        self.consumed += start - self.start;
+        self.start = start;
+    }
+
+    /// Offset of the first unconsumed byte in the input.
+    pub(crate) fn offset(&self) -> usize {
+        self.consumed
+    }
+
+    /// Reads until at least `len` bytes are unconsumed or the input ends.
+    pub(crate) fn fill(&mut self, len: usize) -> io::Result<()> {
+        if self.start > 0 {
+            self.bytes.drain(..self.start);
+            self.start = 0;
+        }
+        let mut filled = self.bytes.len();
+        if filled >= len || self.eof {
+            return Ok(());
+        }
+        // Zeroed once, as a reader may return a few bytes at a time
+        self.bytes.resize(len, 0);
+        let result = loop {
+            if filled == len {
+                break Ok(());
+            }
+            match self.reader.read(&mut self.bytes[filled..]) {
+                Ok(0) => {
+                    self.eof = true;
+                    break Ok(());
+                }
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.bytes.truncate(filled);
+        result
+    }
+
+    /// Drops the next `len` bytes, or the rest of the input if it is shorter.
+    pub(crate) fn skip(&mut self, mut len: usize) -> io::Result<()> {
+        loop {
+            let skipped = len.min(self.data().len());
+            self.consume(skipped);
+            len -= skipped;
+            if len == 0 || self.eof {
+                return Ok(());
+            }
+            self.fill(STREAM_BUFFER_LEN)?;
+        }
+    }
+
+    /// Drops bytes up to the next `H Product:` line, or to the end of the input.
+    pub(crate) fn skip_to_product_header(&mut self) -> io::Result<()> {
+        loop {
+            self.fill(STREAM_BUFFER_LEN)?;
+            let data = self.data();
+            match find_product_header(data) {
+                Some(start) => {
+                    self.consume(start);
+                    return Ok(());
+                }
+                None if self.eof => {
+                    self.consume(data.len());
+                    return Ok(());
+                }
+                // Keep a partial marker at the end of the buffer
+                None => self.consume(data.len().saturating_sub(PRODUCT_HEADER.len())),
+            }
+        }
+    }
+
+    /// Parses the header block at the start of the buffer and consumes it. The outer
+    /// result is an I/O error, the inner one a header block that cannot be parsed.
+    pub(crate) fn read_headers(
+        &mut self,
+        options: &HeaderOptions,
+    ) -> io::Result<Result<BblLog, BblError>> {
+        // Read until the end of the header block is buffered
+        let mut len = STREAM_BUFFER_LEN;
+        loop {
+            self.fill(len)?;
+            let data = self.data();
+            let complete = header_block_len(data).is_some_and(|headers| headers < data.len());
+            if complete || self.eof {
+                break;
+            }
+            len *= 2;
+        }
+        let log = BblLog::from_bytes_with(self.data(), options);
+        if let Ok(log) = &log {
+            self.consume(log.data_offset);
+        }
+        Ok(log)
+    }
+}
+
+/// Where the data a [`SessionDecoder`] is given lies in a streamed input, for the
+/// checkpoints of `checkpoint_out`.
+#[derive(Debug, Clone, Copy)]
+struct InputPosition {
+    /// The session, counting from 0, and the offset of its `H Product:` line
+    session: usize,
+    session_offset: usize,
+    /// Offset in the input of the first byte after the headers of the first session
+    stream_start: usize,
+    /// Offset in the input of the first byte of the data being decoded
+    data_offset: usize,
+}
+
+/// Decoding state of one session, kept between the calls that decode its frames.
+struct SessionDecoder<'a> {
+    log: &'a BblLog,
+    columns: &'a [Column],
+    options: &'a DecodeOptions,
+    intervals: Option<FrameIntervals>,
+    frames_per_interval: Option<usize>,
+    time_index: Option<usize>,
+    iteration_index: Option<usize>,
+    acc_indices: Option<[usize; 3]>,
+    /// Indices of `motor[0]`, `motor[1]`, ... in the main frame
+    motor_indices: Vec<usize>,
+    /// Motor outputs at or above this value are clipped
+    clip_threshold: Option<i64>,
+    /// Motor outputs outside this range are anomalous
+    throttle_range: Option<(i64, i64)>,
+    /// The motors are digital, and a motor output of 0 is a stopped motor
+    digital_motors: bool,
+    loop_period: Option<f64>,
+    vbatref: Option<i64>,
+    min_throttle: Option<i64>,
+    min_motor: Option<i64>,
+
+    /// Main frames seen since the last I-frame, `None` until the first one
+    frames_since_keyframe: Option<usize>,
+    /// `time` of the latest main frame, to find where a logging pause began
+    last_time: Option<i64>,
+    /// `time` of the first main frame, when the session's `Log start datetime` was taken
+    first_time: Option<i64>,
+    /// `loopIteration` of the latest main frame, to find gaps from missing frames
+    last_iteration: Option<i64>,
+    /// `accSmooth[0..3]` of the main frames of the current vibration window
+    acc_window: Vec<[i64; 3]>,
+    /// Synthesized time counts loop periods from the first main frame. Without
+    /// loopIteration, frames are assumed to be spaced as the P ratio logs them.
+    time_anchor: Option<(i64, i64)>,
+    estimated_iteration: Option<i64>,
+    /// Values of the latest main frame, the baseline of the next P-frame
+    previous: Option<Vec<i64>>,
+    /// Values of the main frame before it, for the predictors of two previous frames
+    previous2: Option<Vec<i64>>,
+    /// Predictors already warned about
+    unknown_predictors: BTreeSet<u8>,
+    /// Values of the latest slow frame, carried into every main frame row after it
+    slow: Option<Vec<i64>>,
+    /// Loop iteration the latest slow frame was logged at, `None` until the main frame
+    /// after it if it came first
+    slow_iteration: Option<i64>,
+    /// Loop iterations after which the latest slow frame's values are stale
+    slow_max_age: Option<i64>,
+    /// Values of the latest GPS frame, carried forward the same way
+    gps: Option<Vec<i64>>,
+    /// Values of the latest GPS home (H) frame, which GPS coordinates are predicted from
+    home: Option<Vec<i64>>,
+    /// Position of `GPS_numSat` in a GPS frame, for `min_satellites`
+    satellites_index: Option<usize>,
+    last_written_iteration: Option<i64>,
+    /// Values of the last written row apart from its time, for `only_changed_rows`
+    last_written_values: Option<Vec<Option<i64>>>,
+    /// Write only every this many main frames, for `decimate` and `decimate_to`
+    decimation: Option<usize>,
+    /// Main frames decoded in the session, for `decimate`
+    main_frames: usize,
+    /// Time of the last written main frame, for `export_interval`
+    last_written_time: Option<i64>,
+    window: Option<FlightWindow>,
+    time_offset: i64,
+    clock: RelativeClock,
+    last_percent: usize,
+
+    /// Decoding resumes at the next keyframe, after a corrupt frame or at the start of
+    /// a byte range
+    resync: bool,
+    /// The next keyframe is where decoding resumes after a corrupt frame
+    recovering: bool,
+    /// The main frames since the latest keyframe were decoded after a corrupt frame, for
+    /// `validity_column`
+    recovered: bool,
+    /// Where the data being decoded lies in the input, `None` unless it is streamed
+    position: Option<InputPosition>,
+    /// Main frames decoded since the latest checkpoint, for `checkpoint_interval`
+    frames_since_checkpoint: usize,
+    /// No more frames of the session can be decoded
+    ended: bool,
+}
+
+impl<'a> SessionDecoder<'a> {
+    fn new(
+        log: &'a BblLog,
+        columns: &'a [Column],
+        options: &'a DecodeOptions,
+        stats: &mut DecodeStats,
+        outputs: &mut SideOutputs,
+        clock: RelativeClock,
+    ) -> io::Result<Self> {
+        if let Some(gps) = outputs.gps.as_mut() {
+            gps.start_session(log)?;
+        }
+        for track in &mut outputs.tracks {
+            track.start_session(log)?;
+        }
+        let intervals = parse_frame_intervals(&log.headers);
+        let frames_per_interval = intervals.map(|intervals| intervals.frames_per_interval());
+        if stats.frames_per_interval.is_none() {
+            stats.frames_per_interval = frames_per_interval;
+        }
+        let position = |name| log.main_fields().iter().position(|f| f.name == name);
+        let acc_indices = match ["accSmooth[0]", "accSmooth[1]", "accSmooth[2]"].map(position) {
+            [Some(x), Some(y), Some(z)] => Some([x, y, z]),
+            _ => None,
+        };
+        let motor_indices: Vec<usize> = (0..)
+            .map_while(|motor| {
+                let name = format!("motor[{}]", motor);
+                log.main_fields().iter().position(|f| f.name == name)
+            })
+            .collect();
+        let clip_threshold = motor_range(&log.headers)
+            .map(|(min, max)| max - ((max - min) as f64 * CLIPPING_MARGIN).round() as i64);
+        // Digital motor values are kept in the motor range, not between minthrottle and
+        // maxthrottle, which only apply to analog protocols
+        let digital_motors = is_digital_motor_protocol(&log.headers);
+        let throttle_range = match digital_motors {
+            true => motor_range(&log.headers),
+            false => parse_throttle_range(&log.headers),
+        };
+        let time_index = position("time");
+        if time_index.is_none() && (options.start_time.is_some() || options.end_time.is_some()) {
+            eprintln!("Warning: the log has no time field, ignoring the time range");
+        }
+        let untimed = time_index.is_none() && parse_loop_period(&log.headers).is_none();
+        if untimed && options.export_interval.is_some() {
+            eprintln!(
+                "Warning: the log has no time field or looptime, ignoring the export interval"
+            );
+        }
+        let decimation = match options.decimate_to {
+            Some(rate) => match parse_loop_period(&log.headers) {
+                Some(period) => {
+                    let logged = intervals.map_or(1.0, |intervals| intervals.logged_fraction());
+                    Some(((1e6 / period * logged / rate).round() as usize).max(1))
+                }
+                None => {
+                    eprintln!("Warning: the log has no looptime header, writing every frame");
+                    None
+                }
+            },
+            None => options.decimate,
+        };
+        let gps_fields = log.layouts.get(&b'G').map(Vec::as_slice);
+        let satellites_index =
+            gps_fields.and_then(|fields| fields.iter().position(|f| f.name == "GPS_numSat"));
+        if options.min_satellites.is_some() && gps_fields.is_some() && satellites_index.is_none() {
+            eprintln!("Warning: the GPS frames have no GPS_numSat field, keeping every fix");
+        }
+        let slow_max_age = match options.slow_max_age {
+            Some(SlowMaxAge::Iterations(iterations)) => Some(iterations as i64),
+            Some(SlowMaxAge::Auto) => {
+                parse_slow_interval(&log.headers).map(|interval| 2 * interval as i64)
+            }
+            None => None,
+        };
+
+        Ok(SessionDecoder {
+            log,
+            columns,
+            options,
+            intervals,
+            frames_per_interval,
+            time_index,
+            iteration_index: position("loopIteration"),
+            acc_indices,
+            motor_indices,
+            clip_threshold,
+            throttle_range,
+            digital_motors,
+            loop_period: parse_loop_period(&log.headers),
+            vbatref: parse_vbatref(&log.headers),
+            min_throttle: parse_min_throttle(&log.headers),
+            min_motor: parse_motor_output(&log.headers).map(|(min, _)| min),
+            frames_since_keyframe: None,
+            last_time: None,
+            first_time: None,
+            last_iteration: None,
+            acc_window: Vec::new(),
+            time_anchor: None,
+            estimated_iteration: None,
+            previous: None,
+            previous2: None,
+            unknown_predictors: BTreeSet::new(),
+            slow: None,
+            slow_iteration: None,
+            slow_max_age,
+            gps: None,
+            home: None,
+            satellites_index,
+            last_written_iteration: None,
+            last_written_values: None,
+            decimation,
+            main_frames: 0,
+            last_written_time: None,
+            window: options.flight_only.then(FlightWindow::default),
+            time_offset: 0,
+            clock,
+            last_percent: 0,
+            resync: false,
+            recovering: false,
+            recovered: false,
+            position: None,
+            frames_since_checkpoint: 0,
+            ended: false,
+        })
+    }
+
+    /// Decodes the frames of `data` from `cursor` and returns where decoding stopped.
+    /// Unless `at_end`, more of the session follows `data`, and frames starting in its
+    /// last [`frame_lookahead`] bytes are left for the next call.
+    fn decode(
+        &mut self,
+        data: &[u8],
+        mut cursor: usize,
+        at_end: bool,
+        writer: &mut dyn RecordSink,
+        stats: &mut DecodeStats,
+        outputs: &mut SideOutputs,
+    ) -> io::Result<usize> {
+        let (log, columns, options) = (self.log, self.columns, self.options);
+        let intervals = self.intervals;
+        let (time_index, iteration_index) = (self.time_index, self.iteration_index);
+        let limit = if at_end {
+            data.len()
+        } else {
+            data.len()
+                .saturating_sub(frame_lookahead(log, options.max_frame_size))
+        };
+
+        while cursor < limit && !self.ended {
+            if self.resync {
+                match find_keyframe_before(data, cursor, limit, log) {
+                    Some(next) => {
+                        stats.skipped_bytes += next - cursor;
+                        cursor = next;
+                        self.resync = false;
+                    }
+                    None => {
+                        stats.skipped_bytes += limit - cursor;
+                        cursor = limit;
+                        self.ended = at_end;
+                        break;
+                    }
+                }
+            }
+
+            let frame_start = cursor;
+            let marker = data[cursor];
+            cursor += 1;
+
+            let values = if marker == b'E' {
+                let event = read_event(data, &mut cursor);
+                if let Some(event) = event {
+                    let logged = LoggedEvent {
+                        time: event.time().or(self.last_time),
+                        event,
+                    };
+                    stats.events.push(logged);
+                    if options
+                        .stop_at_event
+                        .as_deref()
+                        .is_some_and(|name| event.is(name))
+                    {
+                        stats.stopped_at = Some(logged);
+                        self.ended = true;
+                    }
+                }
+                match event {
+                    Some(Event::LoggingResume { iteration, time }) => {
+                        stats.paused_spans.push(PausedSpan {
+                            last_time: self.last_time,
+                            resume_iteration: iteration,
+                            resume_time: time,
+                        });
+                        // Logging restarts with a fresh keyframe, which is not a deviation
+                        self.frames_since_keyframe = None;
+                        // Nor is the jump in loopIteration a gap
+                        self.last_iteration = None;
+                    }
+                    Some(Event::SyncBeep { .. }) => {
+                        if let Some(window) = self.window.as_mut() {
+                            stats.rows_written -= window.arm();
+                        }
+                    }
+                    Some(Event::Disarm { .. }) => {
+                        if let Some(window) = self.window.as_mut() {
+                            window.disarmed = true;
+                        }
+                    }
+                    Some(Event::LogEnd) => self.ended = true,
+                    Some(_) => {}
+                    None => {
+                        self.ended = true;
+                        break;
+                    }
+                }
+                None
+            } else if let Some(fields) = log.layouts.get(&marker) {
+                let usage = options.encoding_stats.then_some(&mut stats.encodings);
+                match read_frame(data, &mut cursor, fields, usage) {
+                    // A corrupt frame can run on through the following frames, so
+                    // decoding resumes at the next keyframe after its marker
+                    Some(_) if cursor - frame_start > options.max_frame_size => {
+                        stats.oversized_frames += 1;
+                        cursor = self.start_resync(frame_start, stats);
+                        continue;
+                    }
+                    // Without a keyframe before it, a P-frame's deltas have nothing to
+                    // apply to
+                    Some(_) if marker == b'P' && self.previous.is_none() => {
+                        if stats.unanchored_frames == 0 {
+                            eprintln!(
+                                "Warning: skipping P-frames before the first I-frame, they have no values to predict from"
+                            );
+                        }
+                        stats.unanchored_frames += 1;
+                        None
+                    }
+                    Some(mut values) => {
+                        if let b'I' | b'P' | b'G' = marker {
+                            // A keyframe only refers to itself and the headers, and
+                            // P-frames before the first keyframe have no baseline. GPS
+                            // frames refer to the GPS home and the latest main frame.
+                            let context = PredictionContext {
+                                previous: self.previous.as_deref().filter(|_| marker == b'P'),
+                                previous2: self.previous2.as_deref().filter(|_| marker == b'P'),
+                                iteration_step: self
+                                    .estimated_iteration
+                                    .map_or(1, |last| next_iteration(intervals, last) - last),
+                                vbatref: self.vbatref,
+                                min_throttle: self.min_throttle,
+                                min_motor: self.min_motor,
+                                home: self.home.as_deref(),
+                                last_main_time: self.last_time,
+                            };
+                            apply_predictors(
+                                &mut values,
+                                fields,
+                                &context,
+                                &mut self.unknown_predictors,
+                            );
+                            if marker == b'I' {
+                                self.recovered = std::mem::take(&mut self.recovering);
+                            }
+                            if marker != b'G' {
+                                // A keyframe is both baselines of the P-frame after it
+                                self.previous2 = match marker {
+                                    b'P' => self.previous.take(),
+                                    _ => Some(values.clone()),
+                                };
+                                self.previous = Some(values.clone());
+                            }
+                        }
+                        Some(values)
+                    }
+                    // A corrupt or truncated frame, or an encoding we cannot decode yet
+                    None => {
+                        if stats.resyncs == 0 {
+                            eprintln!(
+                                "Warning: the {} frame at offset {} does not decode, resuming at the next I-frame",
+                                marker as char,
+                                frame_start
+                            );
+                        }
+                        cursor = self.start_resync(frame_start, stats);
+                        continue;
+                    }
+                }
+            } else {
+                if stats.resyncs == 0 {
+                    eprintln!(
+                        "Warning: unknown frame marker 0x{:02x} at offset {}, resuming at the next I-frame",
+                        marker, frame_start
+                    );
+                }
+                cursor = self.start_resync(frame_start, stats);
+                continue;
+            };
+
+            if values.is_some() || marker == b'E' {
+                *stats.frame_counts.entry(marker as char).or_default() += 1;
+            }
+            if let (Some(_), b'I' | b'P') = (&values, marker) {
+                // A keyframe recovered after a corrupt frame may be a false match
+                let due = self.frames_since_checkpoint >= options.checkpoint_interval;
+                if marker == b'I' && due && !self.recovered {
+                    self.write_checkpoint(frame_start, writer, stats)?;
+                }
+                self.frames_since_checkpoint += 1;
+            }
+            if marker == b'S' && values.is_some() {
+                self.slow.clone_from(&values);
+                self.slow_iteration = self.estimated_iteration;
+            }
+            if marker == b'G' && values.is_some() {
+                self.gps.clone_from(&values);
+            }
+            if marker == b'H' && values.is_some() {
+                self.home.clone_from(&values);
+            }
+
+            if let Some(dump) = outputs.frame_dump.as_mut() {
+                let frame = &data[frame_start..cursor];
+                dump.write_all(&(frame.len() as u32).to_le_bytes())?;
+                dump.write_all(frame)?;
+            }
+            if let (Some(values), b'G') = (&values, marker) {
+                if !self.too_few_satellites(values) {
+                    if let Some(gps) = outputs.gps.as_mut() {
+                        gps.write_frame(&log.layouts[&b'G'], values)?;
+                    }
+                    for track in &mut outputs.tracks {
+                        track.write_frame(values, self.first_time)?;
+                    }
+                }
+            }
+            if self.ended {
+                break;
+            }
+
+            // Every `I interval` worth of main frames must start with an I-frame
+            match (
+                marker,
+                self.frames_per_interval,
+                self.frames_since_keyframe.as_mut(),
+            ) {
+                (b'I', Some(expected), Some(since)) => {
+                    // A late keyframe was already counted by the P-frame that took its place
+                    if *since < expected {
+                        stats.i_interval_deviations += 1;
+                    }
+                    *since = 1;
+                }
+                (b'I', _, _) => self.frames_since_keyframe = Some(1),
+                (b'P', Some(expected), Some(since)) => {
+                    if *since % expected == 0 {
+                        stats.i_interval_deviations += 1;
+                    }
+                    *since += 1;
+                }
+                _ => {}
+            }
+
+            let wanted = (options.frame_types.is_empty()
+                || options.frame_types.contains(&(marker as char)))
+                && !self.window.as_ref().is_some_and(|window| window.disarmed);
+
+            if values.is_some() && marker == b'I' {
+                writer.start_segment();
+            }
+
+            let mut synthesized_time = None;
+            let mut repeated = false;
+            let mut out_of_range = false;
+            let mut slow_stale = false;
+            let mut thinned = false;
+            let mut row_time = None;
+            if let (Some(values), b'I' | b'P') = (&values, marker) {
+                let iteration = iteration_index.map(|index| values[index]);
+                repeated = options.per_iteration
+                    && iteration.is_some()
+                    && iteration == self.last_written_iteration;
+                let time = time_index.map(|index| values[index]);
+                if let (Some(window), true) = (self.window.as_mut(), wanted) {
+                    self.time_offset = *window.time_offset.get_or_insert(time.unwrap_or(0));
+                }
+                let time_offset = self.time_offset;
+                let in_range = |time: i64| {
+                    options.start_time.is_none_or(|start| time >= start)
+                        && options.end_time.is_none_or(|end| time <= end)
+                };
+                out_of_range = time.is_some_and(|time| !in_range(time - time_offset));
+
+                // Frames are missing if loopIteration skips past the next logged iteration
+                let missing = match (self.last_iteration, iteration) {
+                    (Some(last), Some(iteration))
+                        if iteration > next_iteration(intervals, last) =>
+                    {
+                        let skipped = (iteration - next_iteration(intervals, last)) as u64;
+                        let elapsed = self.last_time.zip(time).map(|(last, time)| time - last);
+                        let jitter = match options.gap_threshold {
+                            Some(GapThreshold::Iterations(threshold)) => skipped <= threshold,
+                            Some(GapThreshold::Micros(threshold)) => {
+                                elapsed.is_some_and(|elapsed| elapsed <= threshold)
+                            }
+                            None => false,
+                        };
+                        if jitter {
+                            0
+                        } else {
+                            missing_frames(intervals, last, iteration)
+                        }
+                    }
+                    _ => 0,
+                };
+                stats.missing_frames += missing;
+                let gap = missing > 0;
+                if gap {
+                    stats.gaps += 1;
+                }
+                let gap_time = self
+                    .last_time
+                    .zip(time)
+                    .map(|(last, time)| last + (time - last) / 2);
+                let gap_in_range = gap_time.is_none_or(|time| in_range(time - time_offset));
+                if gap && options.gap_markers && !options.per_iteration && wanted && gap_in_range {
+                    let time_column = time_index.map(ColumnSource::Field);
+                    let sample = stats.rows_written as i64;
+                    let record = build_record(columns, options.keep_raw, |source| match source {
+                        ColumnSource::SampleIndex => Some(sample),
+                        ColumnSource::Validity => Some(0),
+                        source if Some(source) == time_column => {
+                            gap_time.map(|time| time - time_offset)
+                        }
+                        _ => None,
+                    });
+                    write_row(writer, self.window.as_mut(), record)?;
+                    stats.rows_written += 1;
+                }
+
+                let logged_iteration = iteration.unwrap_or_else(|| {
+                    self.estimated_iteration
+                        .map_or(0, |last| next_iteration(intervals, last))
+                });
+                self.estimated_iteration = Some(logged_iteration);
+                if self.slow.is_some() {
+                    let slow_iteration = *self.slow_iteration.get_or_insert(logged_iteration);
+                    slow_stale = self
+                        .slow_max_age
+                        .is_some_and(|max_age| logged_iteration - slow_iteration > max_age);
+                }
+                let time_anchor = &mut self.time_anchor;
+                synthesized_time = self.loop_period.map(|period| {
+                    let (first_iteration, first_time) =
+                        *time_anchor.get_or_insert((logged_iteration, time.unwrap_or(0)));
+                    first_time
+                        + ((logged_iteration - first_iteration) as f64 * period).round() as i64
+                });
+
+                row_time = time.or(synthesized_time);
+                thinned = options.export_interval.is_some_and(|interval| {
+                    row_time
+                        .zip(self.last_written_time)
+                        .is_some_and(|(time, last)| time - last < interval)
+                }) || self
+                    .decimation
+                    .is_some_and(|n| !self.main_frames.is_multiple_of(n));
+                self.main_frames += 1;
+
+                if options.detect_loop_overrun {
+                    if let (
+                        Some(period),
+                        Some(last),
+                        Some(iteration),
+                        Some(last_time),
+                        Some(time),
+                    ) = (
+                        self.loop_period,
+                        self.last_iteration,
+                        iteration,
+                        self.last_time,
+                        time,
+                    ) {
+                        if iteration > last {
+                            let budget = (iteration - last) as f64 * period;
+                            stats.declared_loop_period.get_or_insert(period);
+                            let per_iteration =
+                                (time - last_time) as f64 / (iteration - last) as f64;
+                            *stats
+                                .iteration_times
+                                .entry(per_iteration.max(0.0).round() as u64)
+                                .or_default() += 1;
+                            stats.timed_intervals += 1;
+                            if (time - last_time) as f64 > budget * (1.0 + OVERRUN_TOLERANCE) {
+                                stats.overrun_intervals += 1;
+                            }
+                        }
+                    }
+                }
+
+                let vibration_window = options.vibration_window.filter(|&window| window > 0);
+                if let (Some(window), Some(indices)) = (vibration_window, self.acc_indices) {
+                    self.acc_window.push(indices.map(|index| values[index]));
+                    if self.acc_window.len() >= window {
+                        stats.vibration.push(vibration(&self.acc_window));
+                        self.acc_window.clear();
+                    }
+                }
+
+                let clip_threshold = self.clip_threshold.filter(|_| options.detect_clipping);
+                let throttle_range = self.throttle_range.filter(|_| options.report_anomalies);
+                let motors = self.motor_indices.len();
+                if motors > 0 && (clip_threshold.is_some() || throttle_range.is_some()) {
+                    stats.motor_samples += 1;
+                    if let Some(threshold) = clip_threshold {
+                        if stats.clipped_samples.len() < motors {
+                            stats.clipped_samples.resize(motors, 0);
+                        }
+                        for (motor, &index) in self.motor_indices.iter().enumerate() {
+                            if values[index] >= threshold {
+                                stats.clipped_samples[motor] += 1;
+                            }
+                        }
+                    }
+                    if let Some((min, max)) = throttle_range {
+                        if stats.anomalous_samples.len() < motors {
+                            stats.anomalous_samples.resize(motors, 0);
+                        }
+                        for (motor, &index) in self.motor_indices.iter().enumerate() {
+                            let stopped = self.digital_motors && values[index] == 0;
+                            if !(min..=max).contains(&values[index]) && !stopped {
+                                stats.anomalous_samples[motor] += 1;
+                            }
+                        }
+                    }
+                }
+
+                if let (Some(last), Some(time)) = (self.last_time, time) {
+                    stats.logged_time += (time - last).max(0) as u64;
+                }
+                self.last_iteration = iteration;
+                self.last_time = time;
+                self.first_time = self.first_time.or(time);
+            }
+
+            let skipped = repeated || out_of_range || thinned;
+            if let (Some(values), true, false, b'I' | b'P') = (values, wanted, skipped, marker) {
+                let sample = stats.rows_written as i64;
+                let time_offset = self.time_offset;
+                let slow = self.slow.as_ref().filter(|_| !slow_stale);
+                let gps = &self.gps;
+                let relative_time = row_time.map(|time| self.clock.advance(time));
+                let valid = i64::from(!self.recovered);
+                let value_of = |source| match source {
+                    ColumnSource::Field(index) if Some(index) == time_index => {
+                        Some(values[index] - time_offset)
+                    }
+                    ColumnSource::Field(index) => Some(values[index]),
+                    ColumnSource::SynthesizedTime => {
+                        synthesized_time.map(|time| time - time_offset)
+                    }
+                    ColumnSource::SampleIndex => Some(sample),
+                    ColumnSource::FieldAverage { first, count } => {
+                        Some(field_average(&values[first..first + count]))
+                    }
+                    ColumnSource::SlowField(index) => slow.map(|slow| slow[index]),
+                    ColumnSource::Altitude {
+                        baro,
+                        gps: gps_index,
+                    } => baro
+                        .map(|index| values[index])
+                        .or_else(|| Some(gps.as_ref()?[gps_index?] * 10)),
+                    ColumnSource::RelativeTime => relative_time,
+                    ColumnSource::MotorOutput {
+                        index,
+                        min,
+                        digital,
+                    } => Some(motor_offset(values[index], min, digital)),
+                    ColumnSource::Validity => Some(valid),
+                    ColumnSource::Absent => None,
+                };
+
+                // Every row has a time of its own, so only the other columns count
+                let unchanged = options.only_changed_rows && {
+                    let changing: Vec<Option<i64>> = columns
+                        .iter()
+                        .map(|column| column.source)
+                        .filter(|&source| !is_clock_column(source, time_index, iteration_index))
+                        .map(value_of)
+                        .collect();
+                    let unchanged = self.last_written_values.as_ref() == Some(&changing);
+                    self.last_written_values = Some(changing);
+                    unchanged
+                };
+                if !unchanged {
+                    let record = build_record(columns, options.keep_raw, value_of);
+                    write_row(writer, self.window.as_mut(), record)?;
+                    self.last_written_iteration = iteration_index.map(|index| values[index]);
+                    self.last_written_time = row_time;
+                    stats.rows_written += 1;
+                    if options.flush_every > 0
+                        && stats.rows_written.is_multiple_of(options.flush_every)
+                    {
+                        writer.flush()?;
+                    }
+                }
+            }
+
+            if options.progress_json {
+                let percent = cursor * 100 / data.len().max(1);
+                if percent > self.last_percent {
+                    self.last_percent = percent;
+                    emit_progress_json(cursor, stats.main_frames(), data.len());
+                }
+            }
+        }
+        Ok(cursor)
+    }
+
+    /// Gives up on the frame at `frame_start` and searches for the next keyframe from the
+    /// byte after its marker, returning that position. Nothing before the keyframe is a
+    /// baseline for the frames after it.
+    fn start_resync(&mut self, frame_start: usize, stats: &mut DecodeStats) -> usize {
+        stats.resyncs += 1;
+        stats.skipped_bytes += 1;
+        self.resync = true;
+        self.recovering = true;
+        self.previous = None;
+        self.previous2 = None;
+        self.frames_since_keyframe = None;
+        self.last_iteration = None;
+        frame_start + 1
+    }
+
+    /// Writes a checkpoint at the keyframe starting at `frame_start` of the data being
+    /// decoded to `checkpoint_out`, once the rows before it are flushed to `writer`.
+    fn write_checkpoint(
+        &mut self,
+        frame_start: usize,
+        writer: &mut dyn RecordSink,
+        stats: &DecodeStats,
+    ) -> io::Result<()> {
+        let (Some(path), Some(position)) = (&self.options.checkpoint_out, self.position) else {
+            return Ok(());
+        };
+        writer.flush()?;
+        self.frames_since_checkpoint = 0;
+        Checkpoint {
+            session: position.session,
+            session_offset: position.session_offset,
+            offset: position.data_offset + frame_start,
+            rows_written: stats.rows_written,
+        }
+        .write(path)
+    }
+
+    /// Whether a GPS frame's fix has fewer satellites than `min_satellites` allows.
+    fn too_few_satellites(&self, values: &[i64]) -> bool {
+        match (self.options.min_satellites, self.satellites_index) {
+            (Some(min), Some(index)) => values[index] < i64::from(min),
+            _ => false,
+        }
+    }
+
+    /// Writes the rows still held back at the end of the session.
+    fn finish(self, writer: &mut dyn RecordSink) -> io::Result<()> {
+        // Without an arming beep, the whole session counts as the flight
+        if let Some(window) = self.window {
+            for record in window.pending {
+                writer.write_record(&record)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tracks the armed part of a session for `flight_only`.
+#[derive(Default)]
+struct FlightWindow {
+    /// The arming beep was seen
+    armed: bool,
+    /// A disarm event was seen; no more rows are written
+    disarmed: bool,
+    /// Rows held back until it is known whether they precede an arming beep
+    pending: Vec<Vec<String>>,
+    /// `time` of the first row of the flight, which becomes 0
+    time_offset: Option<i64>,
+}
+
+impl FlightWindow {
+    /// Starts the flight, dropping the rows logged before it. Returns how many.
+    fn arm(&mut self) -> usize {
+        self.armed = true;
+        self.time_offset = None;
+        std::mem::take(&mut self.pending).len()
+    }
+}
+
+/// Returns whether a column holds a time or count that advances on every row: the
+/// `time` or `loopIteration` field, or a timestamp or row number derived from them.
+fn is_clock_column(
+    source: ColumnSource,
+    time_index: Option<usize>,
+    iteration_index: Option<usize>,
+) -> bool {
+    match source {
+        ColumnSource::SynthesizedTime | ColumnSource::SampleIndex | ColumnSource::RelativeTime => {
+            true
+        }
+        ColumnSource::Field(index) => Some(index) == time_index || Some(index) == iteration_index,
+        _ => false,
+    }
+}
+
+/// Writes a row, or holds it back while `window` waits for the craft to arm.
+fn write_row(
+    writer: &mut dyn RecordSink,
+    window: Option<&mut FlightWindow>,
+    record: Vec<String>,
+) -> io::Result<()> {
+    match window {
+        Some(window) if !window.armed => {
+            window.pending.push(record);
+            Ok(())
+        }
+        _ => writer.write_record(&record),
+    }
+}
+
+/// Returns the position of the first I-frame starting in `from..limit` that decodes and
+/// is followed by another frame marker or the end of the data.
+fn find_keyframe_before(data: &[u8], from: usize, limit: usize, log: &BblLog) -> Option<usize> {
+    let fields = log.layouts.get(&b'I')?;
+    (from..limit).find(|&start| {
+        if data[start] != b'I' {
+            return false;
+        }
+        let mut cursor = start + 1;
+        read_frame(data, &mut cursor, fields, None).is_some()
+            && data
+                .get(cursor)
+                .is_none_or(|next| *next == b'E' || log.layouts.contains_key(next))
+    })
+}
+
+/// Returns the loop iteration of the main frame logged after the one at `iteration`.
+pub(crate) fn next_iteration(intervals: Option<FrameIntervals>, iteration: i64) -> i64 {
+    match intervals {
+        Some(intervals) if iteration >= 0 => {
+            intervals.next_logged_iteration(iteration as u64) as i64
+        }
+        _ => iteration + 1,
+    }
+}
+
+/// Returns how many main frames should have been logged between the frames at loop
+/// iterations `last` and `iteration`.
+fn missing_frames(intervals: Option<FrameIntervals>, last: i64, iteration: i64) -> usize {
+    match intervals {
+        Some(intervals) if last >= 0 => {
+            intervals.logged_in(last as u64 + 1..iteration as u64) as usize
+        }
+        _ => (iteration - last - 1).max(0) as usize,
+    }
+}
+
+/// Formats an output row, taking each column's value from `value_of`. Columns without
+/// a value are left empty.
+fn build_record(
+    columns: &[Column],
+    keep_raw: bool,
+    value_of: impl Fn(ColumnSource) -> Option<i64>,
+) -> Vec<String> {
+    let mut record = Vec::new();
+    for column in columns {
+        // The relative time is only written in seconds, motor outputs only in percent
+        let keep_raw = keep_raw
+            && !matches!(
+                column.source,
+                ColumnSource::RelativeTime | ColumnSource::MotorOutput { .. }
+            );
+        match value_of(column.source) {
+            Some(value) => push_value(&mut record, value, &column.format, keep_raw),
+            None => push_empty(&mut record, &column.format, keep_raw),
+        }
+    }
+    record
+}
+
+/// Returns the magnitude of the AC component of accelerometer samples: the root of the
+/// summed variances of the three axes.
+fn vibration(samples: &[[i64; 3]]) -> f64 {
+    let count = samples.len() as f64;
+    (0..3)
+        .map(|axis| {
+            let mean = samples.iter().map(|s| s[axis] as f64).sum::<f64>() / count;
+            samples
+                .iter()
+                .map(|s| (s[axis] as f64 - mean).powi(2))
+                .sum::<f64>()
+                / count
+        })
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Returns a motor output's offset from `min`, the low end of the motor range. A
+/// digital motor's 0 stops it, the same as the low end.
+fn motor_offset(value: i64, min: i64, digital: bool) -> i64 {
+    match (digital, value) {
+        (true, 0) => 0,
+        _ => value - min,
+    }
+}
+
+/// Returns the mean of `values`, rounded towards zero.
+fn field_average(values: &[i64]) -> i64 {
+    values.iter().sum::<i64>() / values.len() as i64
+}
+
+/// Reads the values of one frame in layout order, or `None` if the frame is truncated
+/// or uses an unsupported encoding. The fields and bytes read with each encoding are
+/// added to `usage` if given.
+pub(crate) fn read_frame(
+    data: &[u8],
+    cursor: &mut usize,
+    fields: &[FieldDefinition],
+    mut usage: Option<&mut BTreeMap<u8, EncodingUsage>>,
+) -> Option<Vec<i64>> {
+    let mut values = Vec::with_capacity(fields.len());
+    while let Some(field) = fields.get(values.len()) {
+        // Tagged encodings store a group of fields together
+        let following = fields[values.len() + 1..].iter().map(|f| f.encoding);
+        let count = group_len(field.encoding, following);
+        if *cursor + bytes_needed(data, *cursor, field.encoding, count) > data.len() {
+            return None;
+        }
+        let (group_start, values_before) = (*cursor, values.len());
+        match field.encoding {
+            TAG8_8SVB => values.extend(
+                read_tag8_8svb(data, cursor, count)?
+                    .into_iter()
+                    .map(i64::from),
+            ),
+            TAG2_3S32 => values.extend(read_tag2_3s32(data, cursor)?.map(i64::from)),
+            TAG8_4S16 => values.extend(read_tag8_4s16(data, cursor)?.map(i64::from)),
+            encoding => {
+                // A predicted value is a delta, which is signed even when the field is
+                // not. Flag fields are bit patterns, which are never signed.
+                let signed =
+                    (field.signed && flag_names(&field.name).is_none()) || field.predictor != 0;
+                values.push(read_field_value(data, cursor, encoding, signed)?);
+            }
+        }
+        if let Some(usage) = usage.as_deref_mut() {
+            let entry = usage.entry(field.encoding).or_default();
+            entry.fields += values.len().min(fields.len()) - values_before;
+            entry.bytes += *cursor - group_start;
+        }
+    }
+    // A group may run past the last field
+    values.truncate(fields.len());
+    Some(values)
+}
+
+/// How far past its budget of loop periods an interval between main frames may run
+/// before it counts as an overrun, allowing for normal scheduler jitter. `--stats` also
+/// reports a logged loop period this far from the declared one as a mismatch.
+pub const OVERRUN_TOLERANCE: f64 = 0.1;
+
+/// Fraction of the `motorOutput` range below its top within which a motor counts as
+/// clipped.
+const CLIPPING_MARGIN: f64 = 0.01;
+
+/// Predictor of a field stored as its own value.
+const PREDICT_NONE: u8 = 0;
+/// Predictor of a field stored as its difference from the same field in the previous
+/// main frame.
+const PREDICT_PREVIOUS: u8 = 1;
+/// Predictor of a field stored as its difference from the straight line through the
+/// same field in the two previous main frames.
+const PREDICT_STRAIGHT_LINE: u8 = 2;
+/// Predictor of a field stored as its difference from the average of the same field in
+/// the two previous main frames.
+const PREDICT_AVERAGE_2: u8 = 3;
+/// Predictor of a motor output stored as its difference from the `minthrottle` header.
+const PREDICT_MINTHROTTLE: u8 = 4;
+/// Predictor of a motor output stored as its difference from `motor[0]` of the same
+/// frame.
+const PREDICT_MOTOR_0: u8 = 5;
+/// Predictor of a field that counts up by the loop iterations between main frames,
+/// stored as its difference from that count.
+const PREDICT_INCREMENT: u8 = 6;
+/// Predictor of a GPS coordinate stored as its difference from the same coordinate of
+/// the GPS home.
+const PREDICT_HOME_COORD: u8 = 7;
+/// Predictor of a field stored as its difference from the `vbatref` header.
+const PREDICT_VBATREF: u8 = 9;
+/// Predictor of a GPS frame's time stored as its difference from the latest main
+/// frame's time.
+const PREDICT_LAST_MAIN_FRAME_TIME: u8 = 10;
+/// Predictor of a motor output stored as its difference from the low end of the
+/// `motorOutput` header.
+const PREDICT_MINMOTOR: u8 = 11;
+
+/// Returns the firmware's name of a predictor, or `None` for an unknown one.
+pub(crate) fn predictor_name(predictor: u8) -> Option<&'static str> {
+    match predictor {
+        PREDICT_NONE => Some("ZERO"),
+        PREDICT_PREVIOUS => Some("PREVIOUS"),
+        PREDICT_STRAIGHT_LINE => Some("STRAIGHT_LINE"),
+        PREDICT_AVERAGE_2 => Some("AVERAGE_2"),
+        PREDICT_MINTHROTTLE => Some("MINTHROTTLE"),
+        PREDICT_MOTOR_0 => Some("MOTOR_0"),
+        PREDICT_INCREMENT => Some("INC"),
+        PREDICT_HOME_COORD => Some("HOME_COORD"),
+        8 => Some("1500"),
+        PREDICT_VBATREF => Some("VBATREF"),
+        PREDICT_LAST_MAIN_FRAME_TIME => Some("LAST_MAIN_FRAME_TIME"),
+        PREDICT_MINMOTOR => Some("MINMOTOR"),
+        _ => None,
+    }
+}
+
+/// What the predictors of one main or GPS frame refer to.
+pub(crate) struct PredictionContext<'a> {
+    /// Values of the previous main frame, `None` for a keyframe. For the first P-frame
+    /// after a keyframe, these are the I-frame's values.
+    pub(crate) previous: Option<&'a [i64]>,
+    /// Values of the main frame before the previous one. For the first P-frame after a
+    /// keyframe, these are the I-frame's values too.
+    pub(crate) previous2: Option<&'a [i64]>,
+    /// Loop iterations from the previous main frame to this one
+    pub(crate) iteration_step: i64,
+    pub(crate) vbatref: Option<i64>,
+    /// Low end of the motor output range from the `minthrottle` header
+    pub(crate) min_throttle: Option<i64>,
+    /// Low end of the motor output range from the `motorOutput` header
+    pub(crate) min_motor: Option<i64>,
+    /// Values of the latest GPS home frame: latitude, then longitude
+    pub(crate) home: Option<&'a [i64]>,
+    /// `time` of the latest main frame
+    pub(crate) last_main_time: Option<i64>,
+}
+
+/// Reconstructs the value of a field from the `raw` value stored for it, or returns
+/// `None` if its predictor is not supported. `previous` and `previous2` are the field's
+/// values in the two previous main frames, and `motor_0` is the value of `motor[0]` in
+/// the same frame. A missing baseline counts as 0.
+fn apply_predictor(
+    field: &FieldDefinition,
+    raw: i64,
+    previous: Option<i64>,
+    previous2: Option<i64>,
+    motor_0: Option<i64>,
+    context: &PredictionContext,
+) -> Option<i64> {
+    match field.predictor {
+        PREDICT_NONE => Some(raw),
+        PREDICT_PREVIOUS => Some(previous.unwrap_or(0) + raw),
+        PREDICT_STRAIGHT_LINE => Some(2 * previous.unwrap_or(0) - previous2.unwrap_or(0) + raw),
+        // Halved towards zero, as the firmware does
+        PREDICT_AVERAGE_2 => Some((previous.unwrap_or(0) + previous2.unwrap_or(0)) / 2 + raw),
+        PREDICT_INCREMENT => {
+            Some(previous.map_or(0, |previous| previous + context.iteration_step) + raw)
+        }
+        PREDICT_HOME_COORD => {
+            // `GPS_coord[1]`, the longitude, is predicted from the home longitude
+            let axis = usize::from(field.name.ends_with("[1]"));
+            let home = context.home.and_then(|home| home.get(axis).copied());
+            Some(home.unwrap_or(0) + raw)
+        }
+        PREDICT_MINTHROTTLE => Some(context.min_throttle.unwrap_or(0) + raw),
+        PREDICT_MOTOR_0 => Some(motor_0.unwrap_or(0) + raw),
+        PREDICT_VBATREF => Some(context.vbatref.unwrap_or(0) + raw),
+        PREDICT_LAST_MAIN_FRAME_TIME => Some(context.last_main_time.unwrap_or(0) + raw),
+        PREDICT_MINMOTOR => Some(context.min_motor.unwrap_or(0) + raw),
+        _ => None,
+    }
+}
+
+/// Applies the predictor of each field to the values of a main frame. Values with an
+/// unsupported predictor are kept as stored, with a warning the first time each
+/// predictor is seen.
+pub(crate) fn apply_predictors(
+    values: &mut [i64],
+    fields: &[FieldDefinition],
+    context: &PredictionContext,
+    unknown_predictors: &mut BTreeSet<u8>,
+) {
+    let motor_0_index = fields.iter().position(|field| field.name == "motor[0]");
+    for (index, field) in fields.iter().enumerate().take(values.len()) {
+        let previous = context
+            .previous
+            .and_then(|previous| previous.get(index).copied());
+        let previous2 = context
+            .previous2
+            .and_then(|previous2| previous2.get(index).copied());
+        // motor[0] is logged before the motors predicted from it
+        let motor_0 = motor_0_index
+            .filter(|&motor_0_index| motor_0_index < index)
+            .map(|motor_0_index| values[motor_0_index]);
+        match apply_predictor(field, values[index], previous, previous2, motor_0, context) {
+            Some(predicted) => values[index] = predicted,
+            None => {
+                if unknown_predictors.insert(field.predictor) {
+                    eprintln!(
+                        "Warning: predictor {} of {} is not supported, writing stored values",
+                        field.predictor, field.name
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Reads the payload of an event frame. Returns `None` when the event is truncated or
+/// its type is unknown, since decoding cannot continue past either.
+pub(crate) fn read_event(data: &[u8], cursor: &mut usize) -> Option<Event> {
+    let &event_type = data.get(*cursor)?;
+    *cursor += 1;
+
+    let event = match event_type {
+        // Sync beep: time
+        0 => {
+            let time = read_unsigned_vlq(data, cursor)?;
+            Event::SyncBeep { time }
+        }
+        // In-flight adjustment: function, then a float or an integer value, flagged by
+        // the function's top bit
+        13 => {
+            let &function = data.get(*cursor)?;
+            *cursor += 1;
+            let value = if function & 0x80 != 0 {
+                let bytes = data.get(*cursor..*cursor + 4)?;
+                *cursor += 4;
+                f32::from_le_bytes(bytes.try_into().unwrap()) as f64
+            } else {
+                read_signed_vlq(data, cursor)? as f64
+            };
+            Event::InflightAdjustment {
+                function: function & 0x7f,
+                value,
+            }
+        }
+        // Logging resume: iteration and time
+        14 => {
+            let iteration = read_unsigned_vlq(data, cursor)?;
+            let time = read_unsigned_vlq(data, cursor)?;
+            Event::LoggingResume { iteration, time }
+        }
+        // Disarm: reason
+        15 => {
+            let reason = read_unsigned_vlq(data, cursor)?;
+            Event::Disarm { reason }
+        }
+        // Flight mode change: new and previous flags
+        30 => {
+            let flags = read_unsigned_vlq(data, cursor)?;
+            let last_flags = read_unsigned_vlq(data, cursor)?;
+            Event::FlightMode { flags, last_flags }
+        }
+        // End of log, followed by a message
+        255 => {
+            const END_MESSAGE: &[u8] = b"End of log\0";
+            if data[*cursor..].starts_with(END_MESSAGE) {
+                *cursor += END_MESSAGE.len();
+            }
+            return Some(Event::LogEnd);
+        }
+        _ => {
+            eprintln!(
+                "Unknown event type {} at offset {}, stopping",
+                event_type,
+                *cursor - 1
+            );
+            return None;
+        }
+    };
+    (*cursor <= data.len()).then_some(event)
+}
+
+/// Finds the first I-frame in the binary data and writes its desired fields as a single
+/// CSV row. Nothing is written if the log has no complete I-frame.
+pub(crate) fn decode_first_i_frame(
+    data: &[u8],
+    log: &BblLog,
+    columns: &[Column],
+    writer: &mut dyn RecordSink,
+    options: &DecodeOptions,
+) -> io::Result<DecodeStats> {
+    let mut stats = DecodeStats::default();
+    let Some(mut values) = first_i_frame(data, log, &mut stats, options) else {
+        eprintln!("No I-frame found");
+        return Ok(stats);
+    };
+    let context = PredictionContext {
+        previous: None,
+        previous2: None,
+        iteration_step: 1,
+        vbatref: parse_vbatref(&log.headers),
+        min_throttle: parse_min_throttle(&log.headers),
+        min_motor: parse_motor_output(&log.headers).map(|(min, _)| min),
+        home: None,
+        last_main_time: None,
+    };
+    apply_predictors(
+        &mut values,
+        log.main_fields(),
+        &context,
+        &mut BTreeSet::new(),
+    );
+
+    // Synthesized time starts at the first frame's time
+    let time_index = log.main_fields().iter().position(|f| f.name == "time");
+    let synthesized_time =
+        parse_loop_period(&log.headers).map(|_| time_index.map_or(0, |index| values[index]));
+    let record = build_record(columns, options.keep_raw, |source| match source {
+        ColumnSource::Field(index) => Some(values[index]),
+        ColumnSource::SynthesizedTime => synthesized_time,
+        ColumnSource::SampleIndex => Some(0),
+        ColumnSource::FieldAverage { first, count } => {
+            Some(field_average(&values[first..first + count]))
+        }
+        ColumnSource::Altitude { baro, .. } => baro.map(|index| values[index]),
+        ColumnSource::RelativeTime => {
+            (time_index.is_some() || synthesized_time.is_some()).then_some(0)
+        }
+        ColumnSource::MotorOutput {
+            index,
+            min,
+            digital,
+        } => Some(motor_offset(values[index], min, digital)),
+        ColumnSource::Validity => Some(1),
+        ColumnSource::SlowField(_) | ColumnSource::Absent => None,
+    });
+    writer.write_record(&record)?;
+
+    stats.frame_counts.insert('I', 1);
+    stats.rows_written = 1;
+    Ok(stats)
+}
+
+/// Walks the frames from the start of `data` to the first I-frame and returns its
+/// values before prediction. Event, slow, GPS and home frames are stepped over whole, so
+/// a byte inside them that happens to be `I` is not taken for a marker, and a frame
+/// that does not decode is skipped to the next keyframe as in a full decode.
+fn first_i_frame(
+    data: &[u8],
+    log: &BblLog,
+    stats: &mut DecodeStats,
+    options: &DecodeOptions,
+) -> Option<Vec<i64>> {
+    let mut cursor = 0;
+    while cursor < data.len() {
+        let frame_start = cursor;
+        let marker = data[cursor];
+        cursor += 1;
+        let decoded = match (marker, log.layouts.get(&marker)) {
+            (b'E', _) => read_event(data, &mut cursor).map(|_| Vec::new()),
+            (_, Some(fields)) => {
+                let usage =
+                    (marker == b'I' && options.encoding_stats).then_some(&mut stats.encodings);
+                read_frame(data, &mut cursor, fields, usage)
+            }
+            // An unknown marker
+            (_, None) => None,
+        };
+        match decoded {
+            Some(values) if marker == b'I' => return Some(values),
+            Some(_) => {}
+            None => cursor = find_keyframe_before(data, frame_start + 1, data.len(), log)?,
+        }
+    }
+    None
+}
+
+/// Writes one `{"bytes":.."frames":..,"percent":..}` progress line to stderr, with the
+/// main frames decoded so far whether or not they were written.
+fn emit_progress_json(bytes: usize, frames: usize, total: usize) {
+    let percent = if total == 0 {
+        100.0
+    } else {
+        bytes as f64 * 100.0 / total as f64
+    };
+    eprintln!(
+        "{{\"bytes\":{},\"frames\":{},\"percent\":{:.1}}}",
+        bytes, frames, percent
+    );
+}
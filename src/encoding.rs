@@ -0,0 +1,297 @@
+/// Signed variable-byte encoding.
+pub(crate) const SIGNED_VB: u8 = 0;
+/// Unsigned variable-byte encoding.
+pub(crate) const UNSIGNED_VB: u8 = 1;
+/// Negated 14-bit two's complement number, stored as an unsigned variable-byte number.
+pub(crate) const NEG_14BIT: u8 = 3;
+/// Up to 8 consecutive fields behind a byte flagging which of them are non-zero.
+pub(crate) const TAG8_8SVB: u8 = 6;
+/// 3 fields packed into 1 to 13 bytes, sized by their largest value.
+pub(crate) const TAG2_3S32: u8 = 7;
+/// 4 fields behind a byte giving the size of each, from zero to 16 bits.
+pub(crate) const TAG8_4S16: u8 = 8;
+/// Nothing is stored, the predictor alone gives the value.
+pub(crate) const NULL: u8 = 9;
+
+/// Returns the firmware's name of an encoding, or `None` for an unknown one.
+pub fn encoding_name(encoding: u8) -> Option<&'static str> {
+    match encoding {
+        SIGNED_VB => Some("SIGNED_VB"),
+        UNSIGNED_VB => Some("UNSIGNED_VB"),
+        NEG_14BIT => Some("NEG_14BIT"),
+        TAG8_8SVB => Some("TAG8_8SVB"),
+        TAG2_3S32 => Some("TAG2_3S32"),
+        TAG8_4S16 => Some("TAG8_4S16"),
+        NULL => Some("NULL"),
+        10 => Some("TAG2_3SVARIABLE"),
+        _ => None,
+    }
+}
+
+/// Reads one field value with the given encoding, or `None` if the encoding is not
+/// supported, the value is malformed or the data ends first.
+pub(crate) fn read_field_value(
+    data: &[u8],
+    cursor: &mut usize,
+    encoding: u8,
+    signed: bool,
+) -> Option<i64> {
+    if encoding == NULL {
+        return Some(0);
+    }
+    if *cursor >= data.len() {
+        return None;
+    }
+
+    match encoding {
+        SIGNED_VB => {
+            let val = read_signed_vlq(data, cursor)?;
+            if signed {
+                Some(val as i64)
+            } else {
+                // The firmware casts unsigned values to i32 for this encoding, so values
+                // past i32::MAX, like a late `time`, come back negative
+                Some(val as u32 as i64)
+            }
+        }
+        UNSIGNED_VB => {
+            let val = read_unsigned_vlq(data, cursor)?;
+            if signed {
+                Some(val as i32 as i64)
+            } else {
+                // Keep the full u32 range instead of casting through i32
+                Some(val as i64)
+            }
+        }
+        NEG_14BIT => {
+            let val = read_unsigned_vlq(data, cursor)?;
+            Some(-i64::from(sign_extend(val & 0x3fff, 14)))
+        }
+        _ => None,
+    }
+}
+
+/// Returns how many consecutive fields starting with one of `encoding` are read as one
+/// group, or 1 for encodings that store each field on its own.
+pub(crate) fn group_len(encoding: u8, following: impl Iterator<Item = u8>) -> usize {
+    match encoding {
+        TAG8_8SVB => 1 + following.take(7).take_while(|&e| e == TAG8_8SVB).count(),
+        TAG2_3S32 => 3,
+        TAG8_4S16 => 4,
+        _ => 1,
+    }
+}
+
+/// Returns the most bytes a group of `count` fields with `encoding` can take, as read
+/// by [`group_len`]. Unsupported encodings take none, as they cannot be read at all.
+pub(crate) fn max_encoded_len(encoding: u8, count: usize) -> usize {
+    // A variable-byte `u32` takes at most 5 bytes
+    const LONGEST_VLQ: usize = 5;
+    match encoding {
+        SIGNED_VB | UNSIGNED_VB | NEG_14BIT => LONGEST_VLQ,
+        TAG8_8SVB if count == 1 => LONGEST_VLQ,
+        TAG8_8SVB => 1 + count * LONGEST_VLQ,
+        // The lead byte and three 32-bit fields
+        TAG2_3S32 => 1 + 3 * 4,
+        // The selector byte and four 16-bit fields
+        TAG8_4S16 => 1 + 4 * 2,
+        _ => 0,
+    }
+}
+
+/// Returns how many bytes a group of `count` fields with `encoding` starting at
+/// `cursor` needs, as far as the bytes there tell: exactly for the tagged encodings,
+/// whose first byte gives the size of the rest, and at least one byte per stored
+/// variable-byte number otherwise.
+pub(crate) fn bytes_needed(data: &[u8], cursor: usize, encoding: u8, count: usize) -> usize {
+    let Some(&lead) = data.get(cursor) else {
+        return usize::from(encoding != NULL);
+    };
+    match encoding {
+        NULL => 0,
+        TAG8_8SVB if count > 1 => 1 + (lead & (u8::MAX >> (8 - count))).count_ones() as usize,
+        TAG2_3S32 => match lead >> 6 {
+            0 => 1,
+            1 => 2,
+            2 => 3,
+            _ => {
+                1 + (0..3)
+                    .map(|i| 1 + ((lead >> (2 * i)) & 0x03) as usize)
+                    .sum::<usize>()
+            }
+        },
+        TAG8_4S16 => {
+            let nibbles: usize = (0..4)
+                .map(|i| match (lead >> (2 * i)) & 0x03 {
+                    0 => 0,
+                    1 => 1,
+                    2 => 2,
+                    _ => 4,
+                })
+                .sum();
+            1 + nibbles.div_ceil(2)
+        }
+        _ => 1,
+    }
+}
+
+/// Reads a signed variable-length quantity (VLQ) from the data buffer, stored zigzag
+/// encoded: 0, -1, 1, -2, ... as 0, 1, 2, 3, ...
+pub(crate) fn read_signed_vlq(data: &[u8], cursor: &mut usize) -> Option<i32> {
+    let value = read_unsigned_vlq(data, cursor)?;
+    Some((value >> 1) as i32 ^ -((value & 1) as i32))
+}
+
+/// Writes an unsigned variable-length quantity (VLQ) to `out`: 7 bits to a byte, least
+/// significant first, with the top bit set on every byte but the last.
+///
+/// ```
+/// let mut out = Vec::new();
+/// bbe_reader::write_unsigned_vlq(&mut out, 127);
+/// bbe_reader::write_unsigned_vlq(&mut out, 1000);
+/// bbe_reader::write_unsigned_vlq(&mut out, u32::MAX);
+/// assert_eq!(out, [0x7f, 0xe8, 0x07, 0xff, 0xff, 0xff, 0xff, 0x0f]);
+/// ```
+pub fn write_unsigned_vlq(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Writes a signed variable-length quantity (VLQ) to `out`, zigzag encoded the way
+/// `read_signed_vlq` reads it.
+///
+/// ```
+/// let mut out = Vec::new();
+/// bbe_reader::write_signed_vlq(&mut out, -1);
+/// bbe_reader::write_signed_vlq(&mut out, 64);
+/// bbe_reader::write_signed_vlq(&mut out, i32::MIN);
+/// assert_eq!(out, [0x01, 0x80, 0x01, 0xff, 0xff, 0xff, 0xff, 0x0f]);
+/// ```
+pub fn write_signed_vlq(out: &mut Vec<u8>, value: i32) {
+    write_unsigned_vlq(out, ((value << 1) ^ (value >> 31)) as u32);
+}
+
+/// Reads a group of `count` TAG8_8SVB fields, which is a single signed VLQ when the
+/// group has one field. Otherwise a header byte has bit `i` set if field `i` is
+/// non-zero, and a signed VLQ follows for each set bit. A field with a clear bit reads
+/// as 0, which its predictor still applies to.
+pub(crate) fn read_tag8_8svb(data: &[u8], cursor: &mut usize, count: usize) -> Option<Vec<i32>> {
+    if count == 1 {
+        return Some(vec![read_signed_vlq(data, cursor)?]);
+    }
+    let header = read_byte(data, cursor)?;
+    (0..count)
+        .map(|i| match header & (1 << i) {
+            0 => Some(0),
+            _ => read_signed_vlq(data, cursor),
+        })
+        .collect()
+}
+
+/// Reads a group of 3 TAG2_3S32 fields. The top 2 bits of the first byte select 2-bit,
+/// 4-bit or 6-bit fields packed into 1, 2 or 3 bytes, or fields of 8 to 32 bits, each
+/// sized by 2 bits of the first byte and stored little-endian after it.
+pub(crate) fn read_tag2_3s32(data: &[u8], cursor: &mut usize) -> Option<[i32; 3]> {
+    let lead = read_byte(data, cursor)?;
+    let values = match lead >> 6 {
+        0 => [lead >> 4, lead >> 2, lead].map(|bits| sign_extend((bits & 0x03) as u32, 2)),
+        1 => {
+            let next = read_byte(data, cursor)?;
+            [lead & 0x0f, next >> 4, next & 0x0f].map(|bits| sign_extend(bits as u32, 4))
+        }
+        2 => {
+            let second = read_byte(data, cursor)?;
+            let third = read_byte(data, cursor)?;
+            [lead, second, third].map(|bits| sign_extend((bits & 0x3f) as u32, 6))
+        }
+        _ => {
+            let mut values = [0; 3];
+            for (i, value) in values.iter_mut().enumerate() {
+                let bytes = 1 + ((lead >> (2 * i)) & 0x03) as usize;
+                let stored = data.get(*cursor..*cursor + bytes)?;
+                *cursor += bytes;
+                let bits = stored
+                    .iter()
+                    .rev()
+                    .fold(0, |bits, &byte| bits << 8 | byte as u32);
+                *value = sign_extend(bits, 8 * bytes as u32);
+            }
+            values
+        }
+    };
+    Some(values)
+}
+
+/// Reads a group of 4 TAG8_4S16 fields. Each 2 bits of the first byte, lowest first,
+/// make a field zero or give it 4, 8 or 16 bits, read big-endian from a stream of
+/// nibbles that follows.
+pub(crate) fn read_tag8_4s16(data: &[u8], cursor: &mut usize) -> Option<[i32; 4]> {
+    let selector = read_byte(data, cursor)?;
+    // Low nibble of the last byte read, when only its high nibble has been used
+    let mut pending: Option<u8> = None;
+    let mut read_nibbles = |count: u32| -> Option<u32> {
+        let mut bits = 0;
+        for _ in 0..count {
+            let nibble = match pending.take() {
+                Some(nibble) => nibble,
+                None => {
+                    let byte = read_byte(data, cursor)?;
+                    pending = Some(byte & 0x0f);
+                    byte >> 4
+                }
+            };
+            bits = bits << 4 | nibble as u32;
+        }
+        Some(bits)
+    };
+
+    let mut values = [0; 4];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = match (selector >> (2 * i)) & 0x03 {
+            0 => 0,
+            1 => sign_extend(read_nibbles(1)?, 4),
+            2 => sign_extend(read_nibbles(2)?, 8),
+            _ => sign_extend(read_nibbles(4)?, 16),
+        };
+    }
+    Some(values)
+}
+
+/// Interprets the low `bits` bits of `value` as a two's complement number.
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    ((value << shift) as i32) >> shift
+}
+
+fn read_byte(data: &[u8], cursor: &mut usize) -> Option<u8> {
+    let &byte = data.get(*cursor)?;
+    *cursor += 1;
+    Some(byte)
+}
+
+/// Reads an unsigned variable-length quantity (VLQ) from the data buffer. Returns `None`
+/// for a malformed VLQ that continues past the 5 bytes a `u32` needs, or one the data
+/// ends in the middle of.
+pub(crate) fn read_unsigned_vlq(data: &[u8], cursor: &mut usize) -> Option<u32> {
+    let mut value: u32 = 0;
+    let mut shift: u32 = 0;
+
+    loop {
+        if shift >= 32 {
+            return None;
+        }
+        let byte = read_byte(data, cursor)? as u32;
+
+        value |= (byte & 0x7F) << shift;
+        shift += 7;
+
+        if (byte & 0x80) == 0 {
+            break;
+        }
+    }
+
+    Some(value)
+}
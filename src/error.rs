@@ -0,0 +1,170 @@
+use std::fmt;
+use std::io;
+
+/// Errors returned while reading a blackbox log or writing its decoded output.
+#[derive(Debug)]
+pub enum BblError {
+    /// Reading the log or writing the output failed.
+    Io(io::Error),
+    /// The input has no blackbox headers but looks like CSV text, most likely the output
+    /// of an earlier decode.
+    ///
+    /// ```
+    /// use bbe_reader::{BblError, BblLog};
+    ///
+    /// let csv = b"loopIteration,time\n0,100\n1,200\n";
+    /// assert!(matches!(BblLog::from_bytes(csv), Err(BblError::DecodedCsv)));
+    /// ```
+    DecodedCsv,
+    /// The input has no `H Product:` header, the line every blackbox log starts with.
+    ///
+    /// ```
+    /// use bbe_reader::{BblError, BblLog};
+    ///
+    /// let mut log = Vec::new();
+    /// log.extend_from_slice(b"H Field I name:loopIteration,time\n");
+    /// log.extend_from_slice(b"H Field I encoding:1,1\n");
+    /// log.extend_from_slice(&[b'I', 0, 100]);
+    ///
+    /// assert!(matches!(BblLog::from_bytes(&log), Err(BblError::NotABlackboxLog)));
+    /// assert!(matches!(
+    ///     BblLog::from_bytes(b"\x7fELF\x02\x01\x01"),
+    ///     Err(BblError::NotABlackboxLog)
+    /// ));
+    /// ```
+    NotABlackboxLog,
+    /// A header the log cannot be decoded without is missing, named without its `H `
+    /// prefix, e.g. `Field I name`.
+    ///
+    /// ```
+    /// use bbe_reader::{BblError, BblLog};
+    ///
+    /// let log = b"H Product:Blackbox flight data recorder by Nicholas Sherlock\nH Data version:2\n";
+    /// assert!(matches!(
+    ///     BblLog::from_bytes(log),
+    ///     Err(BblError::MissingHeader("Field I name"))
+    /// ));
+    /// ```
+    MissingHeader(&'static str),
+    /// A header line is not a `H <key>:<value>` pair, a field list holds an entry that is
+    /// not a number, or, with [`HeaderOptions::strict`](crate::HeaderOptions::strict), a
+    /// field list is longer or shorter than the others of its frame type.
+    ///
+    /// ```
+    /// use bbe_reader::{BblError, BblLog};
+    ///
+    /// let mut log = Vec::new();
+    /// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+    /// log.extend_from_slice(b"H Field I name:loopIteration,time\n");
+    /// log.extend_from_slice(b"H Field I encoding:1,one\n");
+    ///
+    /// match BblLog::from_bytes(&log) {
+    ///     Err(BblError::MalformedHeader(line)) => assert_eq!(line, "H Field I encoding:1,one"),
+    ///     other => panic!("unexpected {:?}", other),
+    /// }
+    /// ```
+    MalformedHeader(String),
+    /// The input ends in the middle of its header block, at this byte offset.
+    ///
+    /// ```
+    /// use bbe_reader::{BblError, BblLog};
+    ///
+    /// let log = b"H Product:Blackbox flight data recorder by Nicholas Sherlock\nH Field I na";
+    /// assert!(matches!(
+    ///     BblLog::from_reader(&mut &log[..]),
+    ///     Err(BblError::UnexpectedEof(73))
+    /// ));
+    /// ```
+    UnexpectedEof(usize),
+    /// [`DecodeOptions::session`](crate::DecodeOptions::session) asks for a session the
+    /// log does not have.
+    NoSuchSession {
+        /// The session asked for, counting from 1
+        requested: usize,
+        /// Sessions in the log
+        sessions: usize,
+    },
+    /// A [`FrameReader`](crate::FrameReader) met a frame it cannot decode: a truncated
+    /// or corrupt frame, or an unknown frame type or encoding.
+    CorruptFrame {
+        /// Offset of the frame in the input
+        offset: usize,
+        /// The byte the frame starts with, which names its type
+        marker: u8,
+    },
+    /// The input has no keyframe at the offset
+    /// [`DecodeOptions::resume`](crate::DecodeOptions::resume) picks up from, so the
+    /// checkpoint was taken from another log.
+    CheckpointMismatch {
+        /// Offset of the keyframe in the input
+        offset: usize,
+    },
+}
+
+/// Another name for [`BblError`].
+pub type BbeError = BblError;
+
+impl fmt::Display for BblError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BblError::Io(e) => write!(f, "I/O error: {}", e),
+            BblError::DecodedCsv => {
+                write!(f, "this looks like an already-decoded CSV, not a BBL log")
+            }
+            BblError::NotABlackboxLog => {
+                write!(f, "not a blackbox log, there is no `H Product:` header")
+            }
+            BblError::MissingHeader(key) => write!(f, "the log has no `H {}:` header", key),
+            BblError::MalformedHeader(line) => write!(f, "malformed header line {:?}", line),
+            BblError::UnexpectedEof(offset) => {
+                write!(f, "the log ends inside its headers, at byte {}", offset)
+            }
+            BblError::NoSuchSession {
+                requested,
+                sessions,
+            } => write!(
+                f,
+                "there is no log {}, the file holds {} log(s)",
+                requested, sessions
+            ),
+            BblError::CorruptFrame { offset, marker } => write!(
+                f,
+                "cannot decode the frame at byte {} (marker 0x{:02x})",
+                offset, marker
+            ),
+            BblError::CheckpointMismatch { offset } => write!(
+                f,
+                "the checkpoint does not match the input, there is no keyframe at byte {}",
+                offset
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BblError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BblError::Io(e) => Some(e),
+            BblError::DecodedCsv
+            | BblError::NotABlackboxLog
+            | BblError::MissingHeader(_)
+            | BblError::MalformedHeader(_)
+            | BblError::UnexpectedEof(_)
+            | BblError::NoSuchSession { .. }
+            | BblError::CorruptFrame { .. }
+            | BblError::CheckpointMismatch { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for BblError {
+    fn from(e: io::Error) -> Self {
+        BblError::Io(e)
+    }
+}
+
+impl From<csv::Error> for BblError {
+    fn from(e: csv::Error) -> Self {
+        BblError::Io(e.into())
+    }
+}
@@ -0,0 +1,113 @@
+/// Names of the values of a flag field.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum FlagNames {
+    /// One name per bit, lowest bit first
+    Bits(&'static [&'static str]),
+    /// One name per value, starting at 0
+    Values(&'static [&'static str]),
+}
+
+/// Betaflight's flight mode flags, `flightModeFlags`.
+const FLIGHT_MODE_FLAGS: &[&str] = &[
+    "ANGLE_MODE",
+    "HORIZON_MODE",
+    "MAG_MODE",
+    "ALT_HOLD_MODE",
+    "GPS_HOME_MODE",
+    "POS_HOLD_MODE",
+    "HEADFREE_MODE",
+    "CHIRP_MODE",
+    "PASSTHRU_MODE",
+    "RANGEFINDER_MODE",
+    "FAILSAFE_MODE",
+    "GPS_RESCUE_MODE",
+];
+
+/// Betaflight's state flags, `stateFlags`.
+const STATE_FLAGS: &[&str] = &[
+    "GPS_FIX_HOME",
+    "GPS_FIX",
+    "CALIBRATE_MAG",
+    "SMALL_ANGLE",
+    "FIXED_WING",
+];
+
+/// Betaflight's failsafe phases, `failsafePhase`.
+const FAILSAFE_PHASES: &[&str] = &[
+    "IDLE",
+    "RX_LOSS_DETECTED",
+    "LANDING",
+    "LANDED",
+    "RX_LOSS_MONITORING",
+    "RX_LOSS_RECOVERED",
+    "GPS_RESCUE",
+];
+
+/// Betaflight's reasons arming is disabled, `armingDisableFlags`.
+const ARMING_DISABLE_FLAGS: &[&str] = &[
+    "NO_GYRO",
+    "FAILSAFE",
+    "RX_FAILSAFE",
+    "NOT_DISARMED",
+    "BOXFAILSAFE",
+    "RUNAWAY_TAKEOFF",
+    "CRASH_DETECTED",
+    "THROTTLE",
+    "ANGLE",
+    "BOOT_GRACE_TIME",
+    "NOPREARM",
+    "LOAD",
+    "CALIBRATING",
+    "CLI",
+    "CMS_MENU",
+    "BST",
+    "MSP",
+    "PARALYZE",
+    "GPS",
+    "RESC",
+    "RPMFILTER",
+    "REBOOT_REQUIRED",
+    "DSHOT_BITBANG",
+    "ACC_CALIBRATION",
+    "MOTOR_PROTOCOL",
+    "ARM_SWITCH",
+];
+
+/// Returns the names of the values of a known flag field, or `None` for numeric fields.
+///
+/// Flag fields are unsigned bit patterns whatever their header declares, so they are
+/// never read as signed numbers.
+pub(crate) fn flag_names(name: &str) -> Option<FlagNames> {
+    match name {
+        "flightModeFlags" => Some(FlagNames::Bits(FLIGHT_MODE_FLAGS)),
+        "stateFlags" => Some(FlagNames::Bits(STATE_FLAGS)),
+        "failsafePhase" => Some(FlagNames::Values(FAILSAFE_PHASES)),
+        "armingDisableFlags" => Some(FlagNames::Bits(ARMING_DISABLE_FLAGS)),
+        _ => None,
+    }
+}
+
+/// Formats a flag field value by name. Set bits are joined with `|`, `NONE` when no bit
+/// is set, and bits or values without a name are written as `BIT<n>` or the number.
+pub(crate) fn format_flags(value: i64, names: FlagNames) -> String {
+    match names {
+        FlagNames::Bits(names) => {
+            let bits = value as u32;
+            if bits == 0 {
+                return "NONE".to_string();
+            }
+            (0..32)
+                .filter(|bit| bits & (1 << bit) != 0)
+                .map(|bit| match names.get(bit) {
+                    Some(name) => name.to_string(),
+                    None => format!("BIT{}", bit),
+                })
+                .collect::<Vec<_>>()
+                .join("|")
+        }
+        FlagNames::Values(names) => match usize::try_from(value).ok().and_then(|i| names.get(i)) {
+            Some(name) => name.to_string(),
+            None => value.to_string(),
+        },
+    }
+}
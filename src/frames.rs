@@ -0,0 +1,280 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::Read;
+use std::sync::Arc;
+
+use crate::decode::{
+    apply_predictors, frame_lookahead, next_iteration, read_event, read_frame, Event,
+    PredictionContext, StreamBuffer, MAX_FRAME_SIZE, STREAM_BUFFER_LEN,
+};
+use crate::header::{
+    parse_frame_intervals, parse_min_throttle, parse_motor_output, parse_vbatref, BblLog,
+    FrameIntervals, HeaderOptions, PRODUCT_HEADER,
+};
+use crate::BblError;
+
+/// One frame decoded by a [`FrameReader`], with its predictors applied.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    frame_type: char,
+    names: Arc<[String]>,
+    values: Vec<i64>,
+    event: Option<Event>,
+}
+
+impl Frame {
+    /// The frame's type, the byte it starts with: `I` or `P` for main frames, `S` for
+    /// slow frames, `G` for GPS frames, `H` for GPS home frames and `E` for events.
+    pub fn frame_type(&self) -> char {
+        self.frame_type
+    }
+
+    /// The value of the field named `field_name`, or `None` if the frame has no such
+    /// field. A name listed twice in the headers finds the first.
+    pub fn value(&self, field_name: &str) -> Option<i64> {
+        let index = self.names.iter().position(|name| name == field_name)?;
+        self.values.get(index).copied()
+    }
+
+    /// The names of the frame's fields in header order. Event frames have none.
+    pub fn field_names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The values of the frame's fields in header order.
+    pub fn values(&self) -> &[i64] {
+        &self.values
+    }
+
+    /// The payload of an event frame, `None` for other frames.
+    pub fn event(&self) -> Option<Event> {
+        self.event
+    }
+}
+
+/// Decodes a log frame by frame as the caller asks for them, reading the input in
+/// chunks, so logs of any size can be processed without buffering their output. The
+/// predictor state is carried from one frame to the next, and from one session to the
+/// next the headers are read again.
+///
+/// P-frames before the first I-frame of a session have nothing to be predicted from
+/// and are skipped. A frame that cannot be decoded ends the iteration with
+/// [`BblError::CorruptFrame`]; [`crate::decode_file`] and the CSV writers resume at the
+/// next keyframe instead.
+///
+/// ```
+/// use bbe_reader::{Event, FrameReader};
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration,time\n");
+/// log.extend_from_slice(b"H Field I encoding:1,1\n");
+/// log.extend_from_slice(b"H Field P predictor:6,1\n");
+/// log.extend_from_slice(b"H Field P encoding:0,0\n");
+/// // loopIteration 0 at 100 µs, then the next iteration 100 µs later
+/// log.extend_from_slice(&[b'I', 0, 100, b'P', 0, 0xc8, 0x01]);
+/// log.extend_from_slice(b"E\xffEnd of log\0");
+///
+/// let mut frames = Vec::new();
+/// for frame in FrameReader::new(&log[..])? {
+///     let frame = frame?;
+///     frames.push((frame.frame_type(), frame.value("loopIteration"), frame.value("time")));
+///     if frame.frame_type() == 'E' {
+///         assert_eq!(frame.event(), Some(Event::LogEnd));
+///     }
+/// }
+///
+/// assert_eq!(
+///     frames,
+///     [('I', Some(0), Some(100)), ('P', Some(1), Some(200)), ('E', None, None)]
+/// );
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+pub struct FrameReader<R> {
+    buffer: StreamBuffer<R>,
+    log: BblLog,
+    /// Field names of each frame type, shared by the frames
+    names: HashMap<u8, Arc<[String]>>,
+    intervals: Option<FrameIntervals>,
+    iteration_index: Option<usize>,
+    time_index: Option<usize>,
+    vbatref: Option<i64>,
+    min_throttle: Option<i64>,
+    min_motor: Option<i64>,
+    lookahead: usize,
+
+    /// Values of the latest main frame, the baseline of the next P-frame
+    previous: Option<Vec<i64>>,
+    /// Values of the main frame before it, for the predictors of two previous frames
+    previous2: Option<Vec<i64>>,
+    /// Values of the latest GPS home frame, which GPS coordinates are predicted from
+    home: Option<Vec<i64>>,
+    /// `time` and `loopIteration` of the latest main frame
+    last_time: Option<i64>,
+    last_iteration: Option<i64>,
+    /// Predictors already warned about
+    unknown_predictors: BTreeSet<u8>,
+    /// The session's end-of-log event was read, anything up to the next header block
+    /// is padding
+    session_ended: bool,
+    ended: bool,
+}
+
+impl<R: Read> FrameReader<R> {
+    /// Reads the headers of the first session of `reader`, leaving the frames to be
+    /// decoded as the iterator is advanced.
+    pub fn new(reader: R) -> Result<Self, BblError> {
+        let mut buffer = StreamBuffer::new(reader);
+        let log = buffer.read_headers(&HeaderOptions::default())??;
+        let mut frames = FrameReader {
+            buffer,
+            log,
+            names: HashMap::new(),
+            intervals: None,
+            iteration_index: None,
+            time_index: None,
+            vbatref: None,
+            min_throttle: None,
+            min_motor: None,
+            lookahead: 0,
+            previous: None,
+            previous2: None,
+            home: None,
+            last_time: None,
+            last_iteration: None,
+            unknown_predictors: BTreeSet::new(),
+            session_ended: false,
+            ended: false,
+        };
+        frames.start_session();
+        Ok(frames)
+    }
+
+    /// The headers of the session the latest frame belongs to.
+    pub fn log(&self) -> &BblLog {
+        &self.log
+    }
+
+    /// Resets the predictor state for the frames following the headers in `self.log`.
+    fn start_session(&mut self) {
+        let log = &self.log;
+        let position = |name| log.main_fields().iter().position(|f| f.name == name);
+        self.iteration_index = position("loopIteration");
+        self.time_index = position("time");
+        self.names = log
+            .layouts
+            .iter()
+            .map(|(&marker, fields)| {
+                let names: Vec<String> = fields.iter().map(|field| field.name.clone()).collect();
+                (marker, names.into())
+            })
+            .collect();
+        self.intervals = parse_frame_intervals(&log.headers);
+        self.vbatref = parse_vbatref(&log.headers);
+        self.min_throttle = parse_min_throttle(&log.headers);
+        self.min_motor = parse_motor_output(&log.headers).map(|(min, _)| min);
+        self.lookahead = frame_lookahead(log, MAX_FRAME_SIZE);
+        self.previous = None;
+        self.previous2 = None;
+        self.home = None;
+        self.last_time = None;
+        self.last_iteration = None;
+        self.session_ended = false;
+    }
+
+    /// Decodes the frame at the start of the buffer. Returns `None` for a skipped frame
+    /// or the start of a new session, and at the end of the input.
+    fn next_frame(&mut self) -> Result<Option<Frame>, BblError> {
+        self.buffer
+            .fill(STREAM_BUFFER_LEN.max(2 * self.lookahead))?;
+        if self.session_ended {
+            self.buffer.skip_to_product_header()?;
+            self.session_ended = false;
+        }
+        let data = self.buffer.data();
+        if data.is_empty() {
+            self.ended = true;
+            return Ok(None);
+        }
+        if data.starts_with(PRODUCT_HEADER) {
+            self.log = self.buffer.read_headers(&HeaderOptions::default())??;
+            self.start_session();
+            return Ok(None);
+        }
+
+        let offset = self.buffer.offset();
+        let marker = data[0];
+        let corrupt = || BblError::CorruptFrame { offset, marker };
+        let mut cursor = 1;
+        if marker == b'E' {
+            let event = read_event(data, &mut cursor).ok_or_else(corrupt)?;
+            self.buffer.consume(cursor);
+            self.session_ended = event == Event::LogEnd;
+            return Ok(Some(Frame {
+                frame_type: 'E',
+                names: Arc::new([]),
+                values: Vec::new(),
+                event: Some(event),
+            }));
+        }
+        let fields = self.log.layouts.get(&marker).ok_or_else(corrupt)?;
+        let mut values = read_frame(data, &mut cursor, fields, None).ok_or_else(corrupt)?;
+        self.buffer.consume(cursor);
+        if marker == b'P' && self.previous.is_none() {
+            return Ok(None);
+        }
+
+        if let b'I' | b'P' | b'G' = marker {
+            let context = PredictionContext {
+                previous: self.previous.as_deref().filter(|_| marker == b'P'),
+                previous2: self.previous2.as_deref().filter(|_| marker == b'P'),
+                iteration_step: self
+                    .last_iteration
+                    .map_or(1, |last| next_iteration(self.intervals, last) - last),
+                vbatref: self.vbatref,
+                min_throttle: self.min_throttle,
+                min_motor: self.min_motor,
+                home: self.home.as_deref(),
+                last_main_time: self.last_time,
+            };
+            apply_predictors(&mut values, fields, &context, &mut self.unknown_predictors);
+        }
+        match marker {
+            b'I' | b'P' => {
+                self.last_time = self.time_index.map(|index| values[index]);
+                self.last_iteration = self.iteration_index.map(|index| values[index]);
+                // A keyframe is both baselines of the P-frame after it
+                self.previous2 = match marker {
+                    b'P' => self.previous.take(),
+                    _ => Some(values.clone()),
+                };
+                self.previous = Some(values.clone());
+            }
+            b'H' => self.home = Some(values.clone()),
+            _ => {}
+        }
+        Ok(Some(Frame {
+            frame_type: marker as char,
+            names: self.names[&marker].clone(),
+            values,
+            event: None,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for FrameReader<R> {
+    type Item = Result<Frame, BblError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.ended {
+            match self.next_frame() {
+                Ok(Some(frame)) => return Some(Ok(frame)),
+                Ok(None) => {}
+                Err(e) => {
+                    self.ended = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
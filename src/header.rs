@@ -0,0 +1,872 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+use crate::BblError;
+
+/// Represents a single field definition parsed from the header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDefinition {
+    pub name: String,
+    /// How the field is stored: signed (0) or unsigned (1) variable-byte, negated 14-bit
+    /// (3), not at all (9), or one of the tagged encodings that store a group of fields
+    /// together. Unsupported encodings stop decoding.
+    ///
+    /// TAG8_8SVB (6) groups up to 8 consecutive fields behind a byte flagging the
+    /// non-zero ones.
+    pub encoding: u8,
+    /// Whether the field holds signed values. Unsigned values stored with the signed
+    /// encoding are read back as `u32`, except predicted deltas, which stay signed.
+    pub signed: bool,
+    /// How the stored value is predicted. None (0), the previous value (1), the straight
+    /// line through the two previous values (2), their average (3), `minthrottle` (4),
+    /// `motor[0]` (5), the iteration increment (6), the GPS home coordinate (7), the
+    /// `vbatref` baseline (9), the last main frame's time (10) and the low end of
+    /// `motorOutput` (11) are applied; fields with other predictors are written as
+    /// stored, with a warning.
+    ///
+    /// The first P-frame after a keyframe is predicted from the I-frame's values.
+    pub predictor: u8,
+}
+
+/// Field definitions for each frame type, keyed by the frame's marker byte.
+pub type FrameLayouts = HashMap<u8, Vec<FieldDefinition>>;
+
+/// Settings for parsing the header block.
+///
+/// ```
+/// use bbe_reader::{BblLog, HeaderOptions};
+///
+/// let mut bytes = Vec::new();
+/// bytes.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// bytes.extend_from_slice(b"H Field I name:loopIteration,time,vbatLatest\n");
+/// // Too short: vbatLatest has no encoding
+/// bytes.extend_from_slice(b"H Field I encoding:1,1\n");
+///
+/// let log = BblLog::from_bytes(&bytes)?;
+/// assert_eq!(log.main_fields()[2].encoding, bbe_reader::DEFAULT_ENCODING);
+///
+/// let options = HeaderOptions {
+///     default_encoding: 1,
+///     ..HeaderOptions::default()
+/// };
+/// let log = BblLog::from_bytes_with(&bytes, &options)?;
+/// assert_eq!(log.main_fields()[2].encoding, 1);
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct HeaderOptions {
+    /// Encoding of fields past the end of a short `H Field X encoding:` list
+    pub default_encoding: u8,
+    /// Reject headers whose `H Field X` name, signed, predictor and encoding lists don't
+    /// all hold the same number of fields, as when the header block of a log was cut
+    /// short by a brownout, instead of warning and filling in defaults.
+    pub strict: bool,
+}
+
+impl Default for HeaderOptions {
+    fn default() -> Self {
+        HeaderOptions {
+            default_encoding: DEFAULT_ENCODING,
+            strict: false,
+        }
+    }
+}
+
+/// Encoding assumed for fields without one: signed variable-byte.
+pub const DEFAULT_ENCODING: u8 = 0;
+
+/// The plaintext header block of a log and the frame layouts it declares.
+#[derive(Debug, Clone)]
+pub struct BblLog {
+    /// Header lines in file order, without their trailing newline
+    pub headers: Vec<String>,
+    pub layouts: FrameLayouts,
+    /// Bytes skipped before the `H Product:` line
+    pub preamble_len: usize,
+    /// Offset of the first frame from the start of the input
+    pub data_offset: usize,
+}
+
+impl BblLog {
+    /// Reads the header block, leaving the reader positioned at the first frame.
+    pub fn from_reader<R: BufRead>(reader: &mut R) -> Result<BblLog, BblError> {
+        Self::from_reader_with(reader, &HeaderOptions::default())
+    }
+
+    /// Like [`BblLog::from_reader`], with non-default header parsing settings.
+    pub fn from_reader_with<R: BufRead>(
+        reader: &mut R,
+        options: &HeaderOptions,
+    ) -> Result<BblLog, BblError> {
+        // Skip anything a container format put in front of the headers
+        let preamble_len = skip_preamble(reader)?;
+        let (headers, headers_len) = read_headers(reader)?;
+        if headers.is_empty() && looks_like_csv(reader.fill_buf()?) {
+            return Err(BblError::DecodedCsv);
+        }
+        check_headers(&headers)?;
+        if options.strict {
+            check_field_counts(&headers)?;
+        }
+        let layouts = parse_frame_layouts(&headers, options);
+
+        Ok(BblLog {
+            headers,
+            layouts,
+            preamble_len,
+            data_offset: preamble_len + headers_len,
+        })
+    }
+
+    /// Parses the header block of a log that is already in memory. The frames start at
+    /// `data_offset` in `bytes`.
+    ///
+    /// ```
+    /// use bbe_reader::BblLog;
+    ///
+    /// let mut bytes = b"garbage".to_vec();
+    /// bytes.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+    /// bytes.extend_from_slice(b"H Field I name:loopIteration,time\n");
+    /// bytes.extend_from_slice(b"H Field I encoding:1,1\n");
+    /// bytes.extend_from_slice(&[b'I', 0, 100]);
+    ///
+    /// let log = BblLog::from_bytes(&bytes)?;
+    /// let from_reader = BblLog::from_reader(&mut &bytes[..])?;
+    ///
+    /// assert_eq!(log.headers, from_reader.headers);
+    /// assert_eq!(log.preamble_len, 7);
+    /// assert_eq!(log.data_offset, from_reader.data_offset);
+    /// assert_eq!(log.main_fields()[1].name, "time");
+    /// assert_eq!(&bytes[log.data_offset..], &[b'I', 0, 100]);
+    /// # Ok::<(), bbe_reader::BblError>(())
+    /// ```
+    ///
+    /// Header values may be UTF-8 text, and the headers after one are still read:
+    ///
+    /// ```
+    /// use bbe_reader::BblLog;
+    ///
+    /// let mut bytes = Vec::new();
+    /// bytes.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+    /// bytes.extend_from_slice("H Craft name:Río\n".as_bytes());
+    /// bytes.extend_from_slice(b"H Field I name:loopIteration,time\n");
+    /// bytes.extend_from_slice(b"H Field I encoding:1,1\n");
+    /// bytes.extend_from_slice(b"H Field P predictor:6,1\n");
+    /// bytes.extend_from_slice(b"H Field P encoding:0,0\n");
+    /// bytes.extend_from_slice(&[b'I', 0, 100]);
+    ///
+    /// let log = BblLog::from_bytes(&bytes)?;
+    /// let from_reader = BblLog::from_reader(&mut &bytes[..])?;
+    ///
+    /// assert_eq!(log.header_value("Craft name"), Some("Río"));
+    /// assert_eq!(log.headers, from_reader.headers);
+    /// assert_eq!(log.layouts[&b'P'][0].predictor, 6);
+    /// assert_eq!(log.layouts[&b'P'][1].predictor, 1);
+    /// assert_eq!(&bytes[log.data_offset..], &[b'I', 0, 100]);
+    /// assert_eq!(from_reader.data_offset, log.data_offset);
+    /// # Ok::<(), bbe_reader::BblError>(())
+    /// ```
+    pub fn from_bytes(bytes: &[u8]) -> Result<BblLog, BblError> {
+        Self::from_bytes_with(bytes, &HeaderOptions::default())
+    }
+
+    /// Like [`BblLog::from_bytes`], with non-default header parsing settings.
+    pub fn from_bytes_with(bytes: &[u8], options: &HeaderOptions) -> Result<BblLog, BblError> {
+        // Skip anything a container format put in front of the headers
+        let preamble_len = find_product_header(bytes).unwrap_or(0);
+
+        let mut headers = Vec::new();
+        let mut offset = preamble_len;
+        while bytes[offset..].starts_with(b"H ") {
+            let Some(newline) = bytes[offset..].iter().position(|&byte| byte == b'\n') else {
+                return Err(BblError::UnexpectedEof(bytes.len()));
+            };
+            let line_len = newline + 1;
+            let header_line = &bytes[offset..offset + line_len];
+            // A frame can start with the bytes `H `, so stop where the line is not text
+            if !is_header_text(header_line) {
+                break;
+            }
+            offset += line_len;
+            headers.push(String::from_utf8_lossy(header_line).trim().to_string());
+        }
+        if headers.is_empty() && looks_like_csv(bytes) {
+            return Err(BblError::DecodedCsv);
+        }
+        check_headers(&headers)?;
+        if options.strict {
+            check_field_counts(&headers)?;
+        }
+        let layouts = parse_frame_layouts(&headers, options);
+
+        Ok(BblLog {
+            headers,
+            layouts,
+            preamble_len,
+            data_offset: offset,
+        })
+    }
+
+    /// Returns the I-frame field definitions, which name the main frame columns.
+    pub fn main_fields(&self) -> &[FieldDefinition] {
+        self.layouts
+            .get(&b'I')
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Returns the names of the main frame fields in layout order. As
+    /// [`DecodeOptions::fields`](crate::DecodeOptions::fields) with empty columns kept,
+    /// they make another log's output follow this log's columns.
+    ///
+    /// ```
+    /// use bbe_reader::{BblLog, DecodeOptions};
+    ///
+    /// let mut reference = Vec::new();
+    /// reference.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+    /// reference.extend_from_slice(b"H Field I name:loopIteration,time,rssi\n");
+    /// reference.extend_from_slice(b"H Field I encoding:1,1,1\n");
+    ///
+    /// let mut log = Vec::new();
+    /// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+    /// log.extend_from_slice(b"H Field I name:time,axisP[0],loopIteration\n");
+    /// log.extend_from_slice(b"H Field I encoding:1,1,1\n");
+    /// log.extend_from_slice(&[b'I', 100, 5, 0]);
+    ///
+    /// let options = DecodeOptions {
+    ///     fields: BblLog::from_bytes(&reference)?.main_field_names(),
+    ///     include_empty_fields: true,
+    ///     ..DecodeOptions::default()
+    /// };
+    /// let mut csv = Vec::new();
+    /// bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+    ///
+    /// assert_eq!(String::from_utf8(csv).unwrap(), "loopIteration,time,rssi\n0,100,\n");
+    /// # Ok::<(), bbe_reader::BblError>(())
+    /// ```
+    pub fn main_field_names(&self) -> Vec<String> {
+        self.main_fields()
+            .iter()
+            .map(|field| field.name.clone())
+            .collect()
+    }
+
+    /// Returns the value of a `H <key>:<value>` header line, if present.
+    pub fn header_value(&self, key: &str) -> Option<&str> {
+        header_value(&self.headers, key)
+    }
+
+    /// Returns the system configuration recorded in the headers.
+    pub fn system_config(&self) -> crate::SystemConfig {
+        crate::parse_system_config(&self.headers)
+    }
+}
+
+/// Keyframe spacing declared by the `I interval` and `P interval` headers.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FrameIntervals {
+    i_interval: u32,
+    p_num: u32,
+    p_denom: u32,
+}
+
+impl FrameIntervals {
+    /// Number of main frames (the I-frame plus its P-frames) logged per `I interval`.
+    pub(crate) fn frames_per_interval(&self) -> usize {
+        (0..self.i_interval as u64)
+            .filter(|&iteration| self.is_logged(iteration))
+            .count()
+    }
+
+    /// Fraction of loop iterations that have a main frame.
+    pub(crate) fn logged_fraction(&self) -> f64 {
+        self.frames_per_interval() as f64 / self.i_interval as f64
+    }
+
+    /// Number of loop iterations in `iterations` that have a main frame.
+    pub(crate) fn logged_in(&self, iterations: std::ops::Range<u64>) -> u64 {
+        // Logging repeats every `I interval`, so only the last partial interval is scanned
+        let i_interval = self.i_interval as u64;
+        let intervals = iterations.end.saturating_sub(iterations.start) / i_interval;
+        let rest = iterations.start + intervals * i_interval..iterations.end;
+        intervals * self.frames_per_interval() as u64
+            + rest.filter(|&iteration| self.is_logged(iteration)).count() as u64
+    }
+
+    /// Returns the first loop iteration after `iteration` that has a main frame.
+    pub(crate) fn next_logged_iteration(&self, iteration: u64) -> u64 {
+        // Every I-frame iteration is logged, so this stops within one interval
+        (iteration + 1..)
+            .find(|&next| self.is_logged(next))
+            .unwrap_or(iteration + 1)
+    }
+
+    /// Mirrors the firmware's check for whether an iteration is logged.
+    fn is_logged(&self, iteration: u64) -> bool {
+        let (i_interval, p_num, p_denom) = (
+            self.i_interval as u64,
+            self.p_num as u64,
+            self.p_denom as u64,
+        );
+        (iteration % i_interval + p_num - 1) % p_denom < p_num
+    }
+}
+
+/// Consumes any bytes preceding the first `H Product:` line and returns how many were
+/// skipped. Only the initially buffered block is searched, so the preamble must be
+/// shorter than the reader's buffer; otherwise nothing is skipped.
+fn skip_preamble<R: BufRead>(reader: &mut R) -> io::Result<usize> {
+    let offset = find_product_header(reader.fill_buf()?).unwrap_or(0);
+    reader.consume(offset);
+    Ok(offset)
+}
+
+/// The line every header block starts with.
+pub(crate) const PRODUCT_HEADER: &[u8] = b"H Product:";
+
+/// Returns the offset of the first `H Product:` line in `bytes`.
+pub(crate) fn find_product_header(bytes: &[u8]) -> Option<usize> {
+    bytes
+        .windows(PRODUCT_HEADER.len())
+        .position(|window| window == PRODUCT_HEADER)
+}
+
+/// Returns the length of the header lines at the start of `bytes`, or `None` if
+/// `bytes` may end before the header block does.
+pub(crate) fn header_block_len(bytes: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    loop {
+        let rest = &bytes[offset..];
+        if rest.len() < 2 {
+            return None;
+        }
+        if !rest.starts_with(b"H ") {
+            return Some(offset);
+        }
+        let line_len = rest.iter().position(|&byte| byte == b'\n')? + 1;
+        if !is_header_text(&rest[..line_len]) {
+            return Some(offset);
+        }
+        offset += line_len;
+    }
+}
+
+/// Returns whether `line`, or the start of a line, that begins with `H ` reads as a
+/// header: UTF-8 text, so a craft name like `Río` is kept, without control characters
+/// other than tabs and the line ending. A frame can start with the same two bytes, as a
+/// GPS home frame can, but its values hardly ever read as such text.
+fn is_header_text(line: &[u8]) -> bool {
+    let text = match std::str::from_utf8(line) {
+        Ok(text) => text,
+        // A character cut off at the end of a partial line
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&line[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return false,
+    };
+    text.trim_end_matches(['\r', '\n'])
+        .chars()
+        .all(|c| c == '\t' || !c.is_control())
+}
+
+/// Returns the offset of every `H Product:` line in `data`, where the binary section of
+/// one session ends and the header block of the next begins.
+pub(crate) fn find_session_starts(data: &[u8]) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut from = 0;
+    while let Some(offset) = find_product_header(&data[from..]) {
+        starts.push(from + offset);
+        from += offset + 1;
+    }
+    starts
+}
+
+/// Returns whether `bytes` start with a line of printable text containing commas, like
+/// the header row of a CSV file.
+fn looks_like_csv(bytes: &[u8]) -> bool {
+    let line = bytes
+        .split(|&byte| byte == b'\n')
+        .next()
+        .unwrap_or_default();
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    line.contains(&b',')
+        && line
+            .iter()
+            .all(|&byte| byte.is_ascii_graphic() || byte == b' ' || byte == b'\t')
+}
+
+/// Checks that the headers hold what decoding relies on: the `H Product:` line that
+/// marks a blackbox log, an `H Field I name:` line, only `H <key>:<value>` lines, and
+/// numbers in the field encoding and predictor lists.
+fn check_headers(headers: &[String]) -> Result<(), BblError> {
+    if header_value(headers, "Product").is_none() {
+        return Err(BblError::NotABlackboxLog);
+    }
+    for header in headers {
+        let Some((key, value)) = parse_header_line(header) else {
+            return Err(BblError::MalformedHeader(header.clone()));
+        };
+        let numeric_list = key.starts_with("Field ")
+            && (key.ends_with(" encoding") || key.ends_with(" predictor"));
+        if numeric_list && value.split(',').any(|s| s.trim().parse::<u8>().is_err()) {
+            return Err(BblError::MalformedHeader(header.clone()));
+        }
+    }
+    if header_value(headers, "Field I name").is_none() {
+        return Err(BblError::MissingHeader("Field I name"));
+    }
+    Ok(())
+}
+
+/// Checks that the `H Field X` lists of every frame type hold the same number of
+/// fields, returning the first list that doesn't.
+fn check_field_counts(headers: &[String]) -> Result<(), BblError> {
+    for frame_type in ['I', 'P', 'S', 'G', 'H'] {
+        if let Some((line, _, _)) = mismatched_field_lists(headers, frame_type).first() {
+            return Err(BblError::MalformedHeader(line.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the `H Field X` name, signed, predictor and encoding lines of one frame type
+/// that list a different number of fields than the name line, or than the longest list
+/// when there is no name line, each with its count and the expected one.
+fn mismatched_field_lists(headers: &[String], frame_type: char) -> Vec<(&str, usize, usize)> {
+    let prefix = format!("H Field {} ", frame_type);
+    // The last line of each kind, as parse_field_definitions reads them
+    let mut lists: Vec<(&str, &str, usize)> = Vec::new();
+    for header in headers {
+        let Some((kind, value)) = header
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.split_once(':'))
+        else {
+            continue;
+        };
+        if !matches!(kind, "name" | "signed" | "predictor" | "encoding") {
+            continue;
+        }
+        lists.retain(|&(other, _, _)| other != kind);
+        lists.push((kind, header, value.split(',').count()));
+    }
+    let expected = match lists.iter().find(|&&(kind, _, _)| kind == "name") {
+        Some(&(_, _, count)) => count,
+        None => lists.iter().map(|&(_, _, count)| count).max().unwrap_or(0),
+    };
+    lists
+        .into_iter()
+        .filter(|&(_, _, count)| count != expected)
+        .map(|(_, line, count)| (line, count, expected))
+        .collect()
+}
+
+/// Reads all plaintext header lines, stopping before the first frame. Returns the lines
+/// and the number of bytes consumed.
+fn read_headers<R: BufRead>(reader: &mut R) -> Result<(Vec<String>, usize), BblError> {
+    let mut headers = Vec::new();
+    let mut consumed = 0;
+    loop {
+        // Frames start right after the last header line, so stop before consuming one
+        let buffered = reader.fill_buf()?;
+        let line_end = buffered
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(buffered.len(), |newline| newline + 1);
+        if !buffered.starts_with(b"H ") || !is_header_text(&buffered[..line_end]) {
+            break;
+        }
+
+        let mut header_line = Vec::new();
+        let bytes_read = reader.read_until(b'\n', &mut header_line)?;
+
+        if bytes_read == 0 {
+            // End of file
+            break;
+        }
+        consumed += bytes_read;
+        if !header_line.ends_with(b"\n") {
+            return Err(BblError::UnexpectedEof(consumed));
+        }
+        headers.push(String::from_utf8_lossy(&header_line).trim().to_string());
+    }
+    Ok((headers, consumed))
+}
+
+/// Parses one entry of a `H Field I signed:` list.
+///
+/// "1"/"true" mean signed and "0"/"false" unsigned. Anything else is reported, then
+/// treated as signed if it is a non-zero number and unsigned otherwise.
+fn parse_signed_flag(value: &str, index: usize) -> bool {
+    let value = value.trim();
+    if value == "1" || value.eq_ignore_ascii_case("true") {
+        true
+    } else if value == "0" || value.eq_ignore_ascii_case("false") {
+        false
+    } else {
+        let signed = value.parse::<i64>().is_ok_and(|n| n != 0);
+        eprintln!(
+            "Warning: unexpected signed flag {:?} for field index {}, treating it as {}",
+            value,
+            index,
+            if signed { "signed" } else { "unsigned" }
+        );
+        signed
+    }
+}
+
+/// Parses the field layouts of every frame type declared in the headers.
+///
+/// P-frames only declare predictors and encodings, so they take their field names and
+/// signedness from the I-frame layout. Each frame is decoded with the layout its marker
+/// byte names, and P-frame values are rebuilt from the frame before them, so both frame
+/// types fill the same columns. A P layout with a different number of fields than the I
+/// layout is warned about, since its columns cannot line up.
+///
+/// Decoding uses only these layouts, so the order of the header lines does not matter:
+///
+/// ```
+/// let lines = [
+///     "H Field I name:loopIteration,axisP[0]",
+///     "H Field I encoding:1,0",
+///     "H Field P predictor:6,1",
+///     "H Field P encoding:0,0",
+/// ];
+/// let frames = [b'I', 4, 0x14, b'P', 0, 0x03];
+///
+/// let mut outputs = Vec::new();
+/// for order in [[0, 1, 2, 3], [3, 1, 2, 0]] {
+///     let mut log = b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n".to_vec();
+///     for index in order {
+///         log.extend_from_slice(lines[index].as_bytes());
+///         log.push(b'\n');
+///     }
+///     log.extend_from_slice(&frames);
+///
+///     let options = bbe_reader::DecodeOptions {
+///         fields: vec!["loopIteration".to_string(), "axisP[0]".to_string()],
+///         ..Default::default()
+///     };
+///     let mut csv = Vec::new();
+///     bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+///     outputs.push(String::from_utf8(csv).unwrap());
+/// }
+///
+/// assert_eq!(outputs[0], "loopIteration,axisP[0]\n4,10\n5,8\n");
+/// assert_eq!(outputs[1], outputs[0]);
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+pub fn parse_frame_layouts(headers: &[String], options: &HeaderOptions) -> FrameLayouts {
+    let mut layouts: FrameLayouts = ['I', 'P', 'S', 'G', 'H']
+        .into_iter()
+        .map(|frame_type| {
+            (
+                frame_type as u8,
+                parse_field_definitions(headers, frame_type, options),
+            )
+        })
+        .filter(|(_, fields)| !fields.is_empty())
+        .collect();
+
+    if let (Some(i_fields), Some(p_fields)) = (layouts.get(&b'I').cloned(), layouts.get_mut(&b'P'))
+    {
+        if p_fields.len() != i_fields.len() {
+            eprintln!(
+                "Warning: H Field P declares {} fields but H Field I declares {}",
+                p_fields.len(),
+                i_fields.len()
+            );
+        }
+        for (p_field, i_field) in p_fields.iter_mut().zip(&i_fields) {
+            if p_field.name.is_empty() {
+                p_field.name = i_field.name.clone();
+                p_field.signed = i_field.signed;
+            }
+        }
+    }
+
+    layouts
+}
+
+/// Parses the field definitions of one frame type (`I`, `P`, `S`, `G` or `H`) from the
+/// plaintext headers. Fields without a declared name get an empty one, and fields
+/// without an encoding get `options.default_encoding` with a warning.
+///
+/// Name, signed, predictor and encoding lists of different lengths are warned about,
+/// or rejected by [`BblLog::from_bytes_with`] with [`HeaderOptions::strict`].
+///
+/// A name listed twice is warned about. Both fields are kept, so the frame still decodes
+/// in step, but output columns selected by name take the first one.
+///
+/// ```
+/// use bbe_reader::DecodeOptions;
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:time,axisP[0],axisP[0],axisP[1]\n");
+/// log.extend_from_slice(b"H Field I encoding:1,1,1,1\n");
+/// log.extend_from_slice(&[b'I', 100, 1, 2, 3]);
+///
+/// let fields = bbe_reader::parse_field_definitions(
+///     &bbe_reader::BblLog::from_bytes(&log)?.headers,
+///     'I',
+///     &Default::default(),
+/// );
+/// assert_eq!(fields.len(), 4);
+///
+/// let options = DecodeOptions {
+///     fields: vec!["axisP[0]".into(), "axisP[1]".into()],
+///     ..DecodeOptions::default()
+/// };
+/// let mut csv = Vec::new();
+/// bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+///
+/// assert_eq!(String::from_utf8(csv).unwrap(), "axisP[0],axisP[1]\n1,3\n");
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+///
+/// Only the lines of the requested frame type are read, so the slow-frame layout comes
+/// back on its own next to the main frame lines:
+///
+/// ```
+/// let headers: Vec<String> = [
+///     "H Field I name:loopIteration,time",
+///     "H Field I encoding:1,1",
+///     "H Field S name:flightModeFlags,stateFlags,failsafePhase",
+///     "H Field S signed:0,0,0",
+///     "H Field S predictor:0,0,0",
+///     "H Field S encoding:1,1,7",
+/// ]
+/// .map(String::from)
+/// .into();
+///
+/// let fields = bbe_reader::parse_field_definitions(&headers, 'S', &Default::default());
+///
+/// let names: Vec<&str> = fields.iter().map(|field| field.name.as_str()).collect();
+/// assert_eq!(names, ["flightModeFlags", "stateFlags", "failsafePhase"]);
+/// let encodings: Vec<u8> = fields.iter().map(|field| field.encoding).collect();
+/// assert_eq!(encodings, [1, 1, 7]);
+/// ```
+pub fn parse_field_definitions(
+    headers: &[String],
+    frame_type: char,
+    options: &HeaderOptions,
+) -> Vec<FieldDefinition> {
+    let mut field_names = Vec::new();
+    let mut encoding_types = Vec::new();
+    let mut signed_flags = Vec::new();
+    let mut predictor_types = Vec::new();
+
+    let prefix = format!("H Field {} ", frame_type);
+    for header in headers {
+        let Some(rest) = header.strip_prefix(&prefix) else {
+            continue;
+        };
+
+        if let Some(value) = rest.strip_prefix("name:") {
+            field_names = value.split(',').map(|s| s.trim().to_string()).collect();
+        } else if let Some(value) = rest.strip_prefix("encoding:") {
+            encoding_types = value
+                .split(',')
+                .map(|s| s.trim().parse::<u8>().unwrap_or(0))
+                .collect();
+        } else if let Some(value) = rest.strip_prefix("signed:") {
+            signed_flags = value
+                .split(',')
+                .enumerate()
+                .map(|(i, s)| parse_signed_flag(s, i))
+                .collect();
+        } else if let Some(value) = rest.strip_prefix("predictor:") {
+            predictor_types = value
+                .split(',')
+                .map(|s| s.trim().parse::<u8>().unwrap_or(0))
+                .collect();
+        }
+    }
+
+    let field_count = field_names
+        .len()
+        .max(encoding_types.len())
+        .max(predictor_types.len());
+
+    for (line, count, expected) in mismatched_field_lists(headers, frame_type) {
+        // A short encoding list gets the warning below, naming the encoding used instead
+        if line.starts_with(&format!("{}encoding:", prefix)) && count < field_count {
+            continue;
+        }
+        let key = line.split_once(':').map_or(line, |(key, _)| key);
+        eprintln!(
+            "Warning: {} lists {} fields but {} are declared, the header may be truncated",
+            key, count, expected
+        );
+    }
+
+    if encoding_types.len() < field_count {
+        eprintln!(
+            "Warning: H Field {} encoding lists {} of {} fields, using encoding {} for the rest",
+            frame_type,
+            encoding_types.len(),
+            field_count,
+            options.default_encoding
+        );
+    }
+
+    // Both copies stay in the layout to keep the frame aligned, but lookups by name
+    // find only the first
+    for (index, name) in field_names.iter().enumerate() {
+        if let Some(first) = field_names[..index].iter().position(|other| other == name) {
+            eprintln!(
+                "Warning: H Field {} name lists {} at index {} and {}, only index {} is written",
+                frame_type, name, first, index, first
+            );
+        }
+    }
+
+    // Combine parsed fields into a list of `FieldDefinition`
+    (0..field_count)
+        .map(|i| FieldDefinition {
+            name: field_names.get(i).cloned().unwrap_or_default(),
+            encoding: *encoding_types.get(i).unwrap_or(&options.default_encoding),
+            signed: *signed_flags.get(i).unwrap_or(&false),
+            predictor: *predictor_types.get(i).unwrap_or(&0),
+        })
+        .collect()
+}
+
+/// Parses the time in µs between loop iterations from the `looptime` (gyro period) and
+/// `pid_process_denom` headers.
+pub(crate) fn parse_loop_period(headers: &[String]) -> Option<f64> {
+    let looptime: f64 = header_value(headers, "looptime")?.parse().ok()?;
+    let pid_process_denom: f64 = match header_value(headers, "pid_process_denom") {
+        Some(value) => value.parse().ok()?,
+        None => 1.0,
+    };
+    (looptime > 0.0 && pid_process_denom > 0.0).then_some(looptime * pid_process_denom)
+}
+
+/// Parses the battery voltage reference from the `vbatref` header, the baseline of
+/// fields with the vbatref predictor.
+pub(crate) fn parse_vbatref(headers: &[String]) -> Option<i64> {
+    header_value(headers, "vbatref")?.trim().parse().ok()
+}
+
+/// Parses the range of motor output values from the `motorOutput` header, `min,max`.
+pub(crate) fn parse_motor_output(headers: &[String]) -> Option<(i64, i64)> {
+    let (min, max) = header_value(headers, "motorOutput")?.split_once(',')?;
+    let (min, max) = (min.trim().parse().ok()?, max.trim().parse().ok()?);
+    (max > min).then_some((min, max))
+}
+
+/// Motor values of the digital DShot and ProShot protocols: 0 stops the motor, 48 to
+/// 2047 are throttle and the values between are commands to the ESC.
+pub(crate) const DIGITAL_MOTOR_RANGE: (i64, i64) = (48, 2047);
+
+/// Whether the motors are driven by a digital protocol, whose motor values are
+/// [`DIGITAL_MOTOR_RANGE`] instead of a pulse width in µs. Told from
+/// `motor_pwm_protocol`, a protocol name or Betaflight's index of one (5 to 8 are
+/// DSHOT150, DSHOT300, DSHOT600 and DSHOT1200 or PROSHOT1000), or else from a
+/// `motorOutput` range ending at 2047.
+pub(crate) fn is_digital_motor_protocol(headers: &[String]) -> bool {
+    match header_value(headers, "motor_pwm_protocol") {
+        Some(protocol) => match protocol.parse::<u8>() {
+            Ok(index) => (5..=8).contains(&index),
+            Err(_) => {
+                let protocol = protocol.to_ascii_uppercase();
+                protocol.contains("DSHOT") || protocol.contains("PROSHOT")
+            }
+        },
+        None => parse_motor_output(headers).is_some_and(|(_, max)| max == DIGITAL_MOTOR_RANGE.1),
+    }
+}
+
+/// Returns the range of motor values from the `motorOutput` header or, for digital
+/// motors logged without one, [`DIGITAL_MOTOR_RANGE`].
+pub(crate) fn motor_range(headers: &[String]) -> Option<(i64, i64)> {
+    parse_motor_output(headers)
+        .or_else(|| is_digital_motor_protocol(headers).then_some(DIGITAL_MOTOR_RANGE))
+}
+
+/// Parses the `minthrottle` header, the baseline of motor outputs with the minthrottle
+/// predictor.
+pub(crate) fn parse_min_throttle(headers: &[String]) -> Option<i64> {
+    header_value(headers, "minthrottle")?.trim().parse().ok()
+}
+
+/// Parses the range motor outputs are kept in from the `minthrottle` and `maxthrottle`
+/// headers.
+pub(crate) fn parse_throttle_range(headers: &[String]) -> Option<(i64, i64)> {
+    let bound = |key| header_value(headers, key)?.trim().parse::<i64>().ok();
+    let (min, max) = (bound("minthrottle")?, bound("maxthrottle")?);
+    (max > min).then_some((min, max))
+}
+
+/// Loop iterations between slow (S) frames per iteration between keyframes, the
+/// firmware's fixed ratio when the headers do not declare an `S interval`.
+const SLOW_FRAMES_PER_I_INTERVAL: u64 = 256;
+
+/// Parses the loop iterations between slow frames from the `S interval` header, or
+/// derives them from `I interval` as the firmware does. Slow frames are also logged
+/// whenever their flags change, so this is the longest they go unrefreshed.
+pub(crate) fn parse_slow_interval(headers: &[String]) -> Option<u64> {
+    if let Some(value) = header_value(headers, "S interval") {
+        return value.parse().ok().filter(|&interval| interval > 0);
+    }
+    let i_interval: u64 = header_value(headers, "I interval")?.parse().ok()?;
+    (i_interval > 0).then_some(i_interval * SLOW_FRAMES_PER_I_INTERVAL)
+}
+
+/// Parses the keyframe spacing from the `I interval` and `P interval` headers.
+///
+/// `P interval` is either a `num/denom` fraction of iterations (older firmware) or a
+/// plain number of iterations between logged frames.
+pub(crate) fn parse_frame_intervals(headers: &[String]) -> Option<FrameIntervals> {
+    let i_interval: u32 = header_value(headers, "I interval")?.parse().ok()?;
+    let (p_num, p_denom) = match header_value(headers, "P interval") {
+        Some(value) => match value.split_once('/') {
+            Some((num, denom)) => (num.trim().parse().ok()?, denom.trim().parse().ok()?),
+            None => (1, value.parse().ok()?),
+        },
+        None => (1, 1),
+    };
+
+    if i_interval == 0 || p_num == 0 || p_denom == 0 {
+        return None;
+    }
+    Some(FrameIntervals {
+        i_interval,
+        p_num,
+        p_denom,
+    })
+}
+
+/// Returns the value of a `H <key>:<value>` header line, if present.
+pub fn header_value<'a>(headers: &'a [String], key: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .filter_map(|header| parse_header_line(header))
+        .find_map(|(name, value)| (name == key).then_some(value))
+}
+
+/// Splits a `H <key>:<value>` header line into its key and value, or returns `None`
+/// for a line of another form.
+///
+/// Only the first colon separates the two: the value is kept as written, colons and
+/// quotes included, with just the whitespace around it trimmed.
+///
+/// ```
+/// use bbe_reader::parse_header_line;
+///
+/// assert_eq!(
+///     parse_header_line("H Log start datetime:2024-05-12T10:20:30.000+02:00"),
+///     Some(("Log start datetime", "2024-05-12T10:20:30.000+02:00"))
+/// );
+/// assert_eq!(
+///     parse_header_line("H Craft name:\"Quad: 5\" \r"),
+///     Some(("Craft name", "\"Quad: 5\""))
+/// );
+/// assert_eq!(parse_header_line("H no separator"), None);
+/// assert_eq!(parse_header_line("Product:Blackbox"), None);
+/// ```
+pub fn parse_header_line(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.strip_prefix("H ")?.split_once(':')?;
+    Some((key, value.trim()))
+}
@@ -0,0 +1,91 @@
+use std::io::{self, Write};
+
+use crate::header::BblLog;
+use crate::metadata::json_string;
+use crate::output::RecordSink;
+
+/// Writes the output as JSON Lines: a leading `"type": "header"` object with the log's
+/// identifying headers, then one object per row, keyed by the header row. Cells are
+/// written as numbers where they parse as one, empty cells as `null` and anything else as
+/// strings. Pretty-printed objects span several lines, one member to a line.
+pub(crate) struct JsonLinesSink<W: Write> {
+    writer: W,
+    pretty: bool,
+    /// The header object, written before the first row
+    log_header: Option<String>,
+    header: Option<Vec<String>>,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub(crate) fn new(writer: W, log: &BblLog, pretty: bool) -> Self {
+        JsonLinesSink {
+            writer,
+            pretty,
+            log_header: Some(header_object(log, pretty)),
+            header: None,
+        }
+    }
+}
+
+impl<W: Write> RecordSink for JsonLinesSink<W> {
+    fn write_record(&mut self, record: &[String]) -> io::Result<()> {
+        if let Some(log_header) = self.log_header.take() {
+            writeln!(self.writer, "{}", log_header)?;
+        }
+        let Some(header) = &self.header else {
+            self.header = Some(record.to_vec());
+            return Ok(());
+        };
+        let cells: Vec<String> = header
+            .iter()
+            .zip(record)
+            .map(|(name, cell)| format!("{}: {}", json_string(name), json_cell(cell)))
+            .collect();
+        writeln!(self.writer, "{}", json_object(&cells, self.pretty))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Returns the header object of a log: its firmware, craft name and data version, with
+/// headers it lacks as `null`.
+fn header_object(log: &BblLog, pretty: bool) -> String {
+    let header = |key| {
+        log.header_value(key)
+            .map_or("null".to_string(), json_string)
+    };
+    let data_version = log
+        .header_value("Data version")
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map_or("null".to_string(), |value| value.to_string());
+    let members = [
+        "\"type\": \"header\"".to_string(),
+        format!("\"firmware\": {}", header("Firmware revision")),
+        format!("\"craft_name\": {}", header("Craft name")),
+        format!("\"data_version\": {}", data_version),
+    ];
+    json_object(&members, pretty)
+}
+
+/// Joins `"name": value` members into an object, on one line or, pretty-printed, with
+/// each member on its own line indented by two spaces.
+fn json_object(members: &[String], pretty: bool) -> String {
+    match pretty {
+        true if !members.is_empty() => format!("{{\n  {}\n}}", members.join(",\n  ")),
+        _ => format!("{{{}}}", members.join(", ")),
+    }
+}
+
+fn json_cell(cell: &str) -> String {
+    if cell.is_empty() {
+        "null".to_string()
+    } else if let Ok(value) = cell.parse::<i64>() {
+        value.to_string()
+    } else if let Some(value) = cell.parse::<f64>().ok().filter(|value| value.is_finite()) {
+        value.to_string()
+    } else {
+        json_string(cell)
+    }
+}
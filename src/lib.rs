@@ -0,0 +1,1638 @@
+//! Decoding of Betaflight blackbox (`.BBL`) logs into CSV, or into memory with [`decode_file`].
+//!
+//! The `bbe_reader_again` binary is a thin command-line wrapper around this crate.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use csv::Writer;
+
+mod checkpoint;
+mod compare;
+mod config;
+mod decode;
+mod encoding;
+mod error;
+mod flags;
+mod frames;
+mod header;
+mod json_lines;
+mod metadata;
+#[cfg(feature = "msgpack")]
+mod msgpack;
+mod output;
+mod self_test;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod track;
+mod units;
+
+pub use checkpoint::Checkpoint;
+pub use compare::{first_mismatch, Mismatch};
+pub use config::{parse_system_config, BlackboxDevice, SystemConfig};
+pub use decode::{
+    ColumnSummary, DecodeStats, EncodingUsage, Event, LoggedEvent, PausedSpan, EVENT_NAMES,
+    FRAME_TYPES, MAX_FRAME_SIZE, OVERRUN_TOLERANCE,
+};
+pub use encoding::{encoding_name, write_signed_vlq, write_unsigned_vlq};
+pub use error::{BbeError, BblError};
+pub use frames::{Frame, FrameReader};
+pub use header::{
+    header_value, parse_field_definitions, parse_frame_layouts, parse_header_line, BblLog,
+    FieldDefinition, FrameLayouts, HeaderOptions, DEFAULT_ENCODING,
+};
+pub use metadata::{write_field_defs_csv, write_metadata_json};
+pub use output::{
+    input_stem, sanitize_file_stem, AltitudeSource, AtomicFile, ColumnType, GapThreshold,
+    OutputFormat, SlowMaxAge,
+};
+pub use self_test::{fuzz_vlq, self_test, VlqMismatch};
+pub use units::parse_gyro_scale;
+
+use decode::{
+    decode_binary_data, decode_first_i_frame, decode_stream, Column, ColumnSource, Session,
+};
+use flags::flag_names;
+use header::{find_session_starts, is_digital_motor_protocol, motor_range, parse_loop_period};
+use json_lines::JsonLinesSink;
+use metadata::json_string;
+#[cfg(feature = "msgpack")]
+use msgpack::MessagePackSink;
+use output::{
+    push_dtypes, ChannelSink, ColumnFormat, LongFormat, RecordSink, ReversedSegments, SinkMessage,
+    Summarizing, WithPreview, WRITER_QUEUE_LEN,
+};
+#[cfg(feature = "sqlite")]
+use sqlite::SqliteSink;
+use units::{field_unit, physical_scale, rc_pulse_scale, scale_header, Scale};
+
+/// Main frame fields written with [`DecodeOptions::noise_prep`], followed by a throttle column.
+pub const NOISE_PREP_FIELDS: &[&str] = &["time", "gyroADC[0]", "gyroADC[1]", "gyroADC[2]"];
+
+/// Main frame fields selected by `--feedforward` for feedforward tuning: the stick
+/// setpoints and the feedforward term of each axis.
+///
+/// ```
+/// use bbe_reader::{DecodeOptions, FEEDFORWARD_FIELDS};
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration,time,gyroADC[0],setpoint[0],setpoint[1],");
+/// log.extend_from_slice(b"setpoint[2],setpoint[3],axisF[0],axisF[1],axisF[2]\n");
+/// log.extend_from_slice(b"H Field I signed:0,0,1,1,1,1,0,1,1,1\n");
+/// log.extend_from_slice(b"H Field I encoding:1,1,0,0,0,0,1,0,0,0\n");
+/// log.extend_from_slice(b"H Field P predictor:6,1,1,1,1,1,1,1,1,1\n");
+/// log.extend_from_slice(b"H Field P encoding:0,0,0,0,0,0,0,0,0,0\n");
+/// // Setpoints 30, -20, 0 and 1000 with feedforward 12, -6 and 0
+/// log.extend_from_slice(&[b'I', 0, 100, 0x0a, 0x3c, 0x27, 0, 0xe8, 0x07, 0x18, 0x0b, 0]);
+/// // Changed by +10, -5, 0, +50 and +3, +2, 0
+/// log.extend_from_slice(&[b'P', 0, 0xc8, 0x01, 0, 0x14, 0x09, 0, 0x64, 0x06, 0x04, 0]);
+///
+/// let options = DecodeOptions {
+///     fields: FEEDFORWARD_FIELDS.iter().map(|name| name.to_string()).collect(),
+///     ..DecodeOptions::default()
+/// };
+/// let mut csv = Vec::new();
+/// bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+///
+/// assert_eq!(
+///     String::from_utf8(csv).unwrap(),
+///     "time,setpoint[0],setpoint[1],setpoint[2],setpoint[3],axisF[0],axisF[1],axisF[2]\n\
+///      100,30,-20,0,1000,12,-6,0\n\
+///      200,40,-25,0,1050,15,-4,0\n"
+/// );
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+pub const FEEDFORWARD_FIELDS: &[&str] = &[
+    "time",
+    "setpoint[0]",
+    "setpoint[1]",
+    "setpoint[2]",
+    "setpoint[3]",
+    "axisF[0]",
+    "axisF[1]",
+    "axisF[2]",
+];
+
+/// Main frame fields selected by `--heading` for yaw and heading analysis: the attitude
+/// INAV logs in decidegrees, whose yaw `attitude[2]` is the heading, and the raw
+/// magnetometer readings. A log without a magnetometer lacks some or all of them, and
+/// the missing ones are left out.
+///
+/// ```
+/// use bbe_reader::{DecodeOptions, HEADING_FIELDS};
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:time,attitude[0],attitude[1],attitude[2],");
+/// log.extend_from_slice(b"magADC[0],magADC[1],magADC[2]\n");
+/// log.extend_from_slice(b"H Field I signed:0,1,1,1,1,1,1\n");
+/// log.extend_from_slice(b"H Field I encoding:1,0,0,0,0,0,0\n");
+/// // Rolled -2.5 degrees, pitched 4 degrees and heading south, magADC 120, -340, 512
+/// log.extend_from_slice(&[b'I', 0xe8, 0x07, 0x31, 0x50, 0x90, 0x1c, 0xf0, 0x01, 0xa7, 0x05]);
+/// log.extend_from_slice(&[0x80, 0x08]);
+///
+/// let decode = |log: &[u8]| {
+///     let options = DecodeOptions {
+///         fields: HEADING_FIELDS.iter().map(|name| name.to_string()).collect(),
+///         physical_units: true,
+///         units_header: true,
+///         precision: Some(1),
+///         ..DecodeOptions::default()
+///     };
+///     let mut csv = Vec::new();
+///     bbe_reader::decode_to_writer(log, &mut csv, options).map(|_| csv)
+/// };
+///
+/// assert_eq!(
+///     String::from_utf8(decode(&log)?).unwrap(),
+///     "time,attitude[0],attitude[1],attitude[2],magADC[0],magADC[1],magADC[2]\n\
+///      µs,deg,deg,deg,raw,raw,raw\n\
+///      1000,-2.5,4.0,180.0,120,-340,512\n"
+/// );
+///
+/// // Without a magnetometer, only the time is written
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:time,gyroADC[0]\n");
+/// log.extend_from_slice(b"H Field I encoding:1,0\n");
+/// log.extend_from_slice(&[b'I', 0xe8, 0x07, 0x02]);
+/// assert_eq!(String::from_utf8(decode(&log)?).unwrap(), "time\nµs\n1000\n");
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+pub const HEADING_FIELDS: &[&str] = &[
+    "time",
+    "attitude[0]",
+    "attitude[1]",
+    "attitude[2]",
+    "magADC[0]",
+    "magADC[1]",
+    "magADC[2]",
+];
+
+/// Main frame fields written when no others are requested.
+pub const DEFAULT_FIELDS: &[&str] = &[
+    "loopIteration",
+    "time",
+    "axisP[0]",
+    "axisP[1]",
+    "axisP[2]",
+    "axisI[0]",
+    "axisI[1]",
+    "axisI[2]",
+    "axisD[0]",
+    "axisD[1]",
+    "axisF[0]",
+    "axisF[1]",
+    "axisF[2]",
+];
+
+/// Settings that control which columns are written and how.
+///
+/// ```
+/// use bbe_reader::DecodeOptions;
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:vbatLatest,accSmooth[0]\n");
+/// log.extend_from_slice(b"H Field I encoding:1,1\n");
+/// log.extend_from_slice(b"H acc_1G:2048\n");
+/// // vbatLatest of 1650 (16.5 V) and accSmooth[0] of 1024 (0.5 g)
+/// log.extend_from_slice(&[b'I', 0xf2, 0x0c, 0x80, 0x08]);
+///
+/// let options = DecodeOptions {
+///     fields: vec!["vbatLatest".to_string(), "accSmooth[0]".to_string()],
+///     physical_units: true,
+///     precision: Some(1),
+///     field_precision: [("vbatLatest".to_string(), 3)].into(),
+///     ..DecodeOptions::default()
+/// };
+/// let mut csv = Vec::new();
+/// bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+///
+/// assert_eq!(
+///     String::from_utf8(csv).unwrap(),
+///     "vbatLatest,accSmooth[0]\n16.500,0.5\n"
+/// );
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    /// Main frame fields to write, in column order; fields missing from the log are skipped
+    /// unless `include_empty_fields` is set
+    pub fields: Vec<String>,
+    /// Write exactly the main frame fields each session's headers declare, in header
+    /// order, instead of `fields`, for logs whose fields are not known in advance
+    pub only_fields_present: bool,
+    /// Write a second header row listing each column's unit
+    pub units_header: bool,
+    /// Convert supported fields to physical units: `gyroADC[*]` to deg/s with the
+    /// `gyro_scale` header, `accSmooth[*]` to g with `acc_1G`, `vbatLatest` from 0.01 V
+    /// steps to volts, `rssi` to percent and `attitude[*]` from decidegrees to degrees.
+    /// Other fields stay raw, and so does a gyro or
+    /// accelerometer field whose header is missing, with a warning for that field.
+    pub physical_units: bool,
+    /// With `physical_units` or `rc_as_us`, keep each raw column next to its scaled
+    /// column
+    pub keep_raw: bool,
+    /// Convert `rcCommand[*]` to the receiver pulse width in µs. Roll, pitch and yaw are
+    /// logged as the deflection from the 1500 µs stick center and are offset by it, while
+    /// throttle, `rcCommand[3]`, is logged on its own 1000 to 2000 µs range and is kept
+    /// as it is.
+    pub rc_as_us: bool,
+    /// Print progress as JSON lines on stderr
+    pub progress_json: bool,
+    /// Flush the output after every `flush_every` data rows, so a reader at the other end
+    /// of a pipe sees rows as they are decoded. Header rows do not count, and 0 flushes
+    /// only once the output is finished.
+    pub flush_every: usize,
+    /// Decode only the first I-frame and write it as a single row
+    pub first_i_frame: bool,
+    /// Write the CSV on a separate thread
+    pub threaded: bool,
+    /// Write the keyframe segments, each an I-frame and the P-frames that follow it, last
+    /// segment first. The rows of a segment stay in log order, and `sample` numbers rows
+    /// in log order too.
+    pub reverse: bool,
+    /// Write only the armed part of each session, from the arming beep to the disarm
+    /// event, with `time` (and `time_synth`) counted from the first row written. A
+    /// session without an arming beep is written from its start.
+    pub flight_only: bool,
+    /// Frame types to decode into the output; empty means all. Frames of the other types
+    /// are still decoded, so P-frames are predicted from keyframes left out of the
+    /// output as usual.
+    ///
+    /// ```
+    /// // Only the keyframes, one row per I-frame
+    /// let options = bbe_reader::DecodeOptions {
+    ///     frame_types: vec!['I'],
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub frame_types: Vec<char>,
+    /// Append a `time_synth` column computed from `loopIteration` and the loop period
+    pub synthesize_time: bool,
+    /// Prepend a `sample` column numbering the written rows 0, 1, 2, ...
+    pub sample_index: bool,
+    /// Append a `time_s` column with the seconds since the first written row, from
+    /// `time` or, without it, the synthesized time. Where the time jumps back, as when
+    /// the clock resets after a logging pause or in a later session, it continues from
+    /// the row before instead of going negative.
+    pub relative_time: bool,
+    /// Append a `valid` column, 1 for rows decoded from clean frames and 0 for rows that
+    /// are not: the frames from the keyframe decoding resumed at after a corrupt frame
+    /// up to the next keyframe, which a false match in the search could have come from,
+    /// and the rows `gap_markers` inserts.
+    pub validity_column: bool,
+    /// Insert a row that is empty apart from `time` wherever frames are missing, so plots
+    /// show a break instead of a line across the gap
+    pub gap_markers: bool,
+    /// How far `loopIteration` has to skip ahead to count as a gap. Smaller skips are
+    /// taken as jitter: they get no gap marker and are not counted in
+    /// [`DecodeStats::gaps`] or [`DecodeStats::missing_frames`]. `None` counts every skip.
+    pub gap_threshold: Option<GapThreshold>,
+    /// Write exactly one row per `loopIteration`: a main frame repeating the iteration of
+    /// the row before it is dropped, gap markers are not written, and the fields of the
+    /// latest slow frame are added as columns, carried forward until the next one and
+    /// empty before the first.
+    pub per_iteration: bool,
+    /// Leave out a main frame row whose columns all hold the same values as the row
+    /// written before it, apart from `time`, `loopIteration` and the timestamps and row
+    /// numbers derived from them, which change on every row. This shrinks the output of
+    /// slowly changing or idle logs, but the rows are no longer evenly spaced in time.
+    pub only_changed_rows: bool,
+    /// Add the fields of the latest slow frame, such as `flightModeFlags` and
+    /// `failsafePhase`, as columns of every row, carried forward until the next slow frame
+    /// and empty before the first. `per_iteration` adds them too.
+    pub include_slow: bool,
+    /// With `per_iteration` or `include_slow`, leave the slow frame columns empty once no slow frame has
+    /// been logged for longer than this many loop iterations, instead of carrying very
+    /// old values forward. `None` carries them forward indefinitely.
+    pub slow_max_age: Option<SlowMaxAge>,
+    /// Write flag fields (`flightModeFlags`, `stateFlags`, `failsafePhase`,
+    /// `armingDisableFlags`) as the names of their set bits or their value instead of a
+    /// number. Flag fields are always read as unsigned bits, even if the header declares
+    /// them signed.
+    pub flag_names: bool,
+    /// Write only main frames whose `time`, as written, is at or after this many µs.
+    /// Frames before it are still decoded, so the first row written is predicted from
+    /// the frames before it like any other.
+    pub start_time: Option<i64>,
+    /// Write only main frames whose `time`, as written, is at or before this many µs
+    pub end_time: Option<i64>,
+    /// Write a main frame only if its time is at least this many µs after the last
+    /// written row, thinning the output to at most one row per interval. The time is
+    /// taken from the `time` field, or the synthesized time without one.
+    pub export_interval: Option<i64>,
+    /// Write only every Nth main frame of each session, starting with its first. All
+    /// frames are still decoded, as each is the baseline of the next, and the rows
+    /// written keep their logged `time` and `loopIteration`.
+    pub decimate: Option<usize>,
+    /// Like `decimate`, with N chosen per session to bring the main frames logged per
+    /// second, from the loop period and the P ratio, down to about this rate in Hz.
+    /// Sessions without a `looptime` header are written in full.
+    pub decimate_to: Option<f64>,
+    /// Stop decoding at the first event with this name, one of [`EVENT_NAMES`]. Rows
+    /// after the event, including those of later sessions, are not written.
+    pub stop_at_event: Option<String>,
+    /// Longest frame, in bytes including its marker, that is decoded. A longer frame is
+    /// taken as corrupt and skipped, and decoding resumes at the next keyframe.
+    pub max_frame_size: usize,
+    /// Add an `altitude` column in centimetres from the barometer, the GPS or both. GPS
+    /// altitude is carried forward from the latest GPS frame and empty before the first.
+    /// Under [`AltitudeSource::Merge`], a session logging `baroAlt` takes its altitude
+    /// from the barometer and any other session from the GPS.
+    pub altitude_source: Option<AltitudeSource>,
+    /// Measure vibration over windows of this many main frames, the magnitude of the AC
+    /// component of `accSmooth[0..3]`, into [`DecodeStats::vibration`]. A partial window
+    /// at the end of a session is not measured.
+    pub vibration_window: Option<usize>,
+    /// Decode only this session, counting from 1, under a header row of its own fields.
+    /// The firmware starts a session, with a fresh header block, every time it arms, so
+    /// one file can hold several logs. A session without frames is written as just the
+    /// header row.
+    pub session: Option<usize>,
+    /// Also write the GPS (G) frames to this CSV file, one column per field of the first
+    /// session's `H Field G` layout. Coordinates are predicted from the latest GPS home
+    /// (H) frame, and times from the latest main frame.
+    pub gps_out: Option<PathBuf>,
+    /// Also write the GPS frames to this GPX file as a track, with a segment per session.
+    /// Points are placed in time from the `Log start datetime` header, have no time if
+    /// the log lacks one, and are left out while the GPS has no fix.
+    pub gpx_out: Option<PathBuf>,
+    /// Also write the GPS frames to this KML file, as a `<LineString>` placemark per
+    /// session with `longitude,latitude,altitude` coordinates. Points are left out while
+    /// the GPS has no fix.
+    pub kml_out: Option<PathBuf>,
+    /// Leave GPS frames with fewer than this many satellites, from their `GPS_numSat`
+    /// field, out of the `gps_out` CSV and the GPX and KML tracks. Such fixes are too
+    /// imprecise to plot. The frames are still decoded, and carried into main frame rows.
+    pub min_satellites: Option<u32>,
+    /// Count the intervals between main frames that took longer than their loop time
+    /// budget, the `loopIteration` step times the loop period, by more than 10%. Needs
+    /// the `time` and `loopIteration` fields and a `looptime` header.
+    pub detect_loop_overrun: bool,
+    /// Count the main frames each motor spends clipped, at or within 1% of the top of
+    /// the `motorOutput` range, into [`DecodeStats::clipped_samples`]. A motor pinned
+    /// there is out of authority.
+    pub detect_clipping: bool,
+    /// Count the main frames each motor spends below `minthrottle` or above
+    /// `maxthrottle` into [`DecodeStats::anomalous_samples`]. Logged motor outputs stay
+    /// within that range, so a value outside it points to a decode error. Digital motors
+    /// are held to the `motorOutput` range instead, and their 0, a stopped motor, is
+    /// never anomalous.
+    pub report_anomalies: bool,
+    /// Write the raw bytes of every decoded frame, marker included, to this file. Each
+    /// frame is stored as a little-endian `u32` length followed by that many bytes.
+    pub dump_frame_bytes: Option<PathBuf>,
+    /// Count the field values read with each encoding and the bytes they took into
+    /// [`DecodeStats::encodings`], to show which encodings dominate a log
+    pub encoding_stats: bool,
+    /// Collect the count, minimum, maximum, mean and standard deviation of the numeric
+    /// cells of every output column into [`DecodeStats::columns`], in one pass as the
+    /// rows are written.
+    pub column_stats: bool,
+    /// Also write a preview to this file, in the same format as the output: the header
+    /// rows and every [`preview_decimate`](Self::preview_decimate)th row, taken from the
+    /// same decode as the full output.
+    pub preview: Option<PathBuf>,
+    /// Rows of the full output per row of the preview
+    pub preview_decimate: usize,
+    /// Name array elements like `axisP[0]` as `axisP_0` in the header row
+    pub flatten_arrays: bool,
+    /// Header names to write in place of field names, overriding `flatten_arrays`
+    pub renames: HashMap<String, String>,
+    /// Keep requested fields the log does not have as empty columns, so every log gives
+    /// the same columns
+    pub include_empty_fields: bool,
+    /// Write the selected fields in the order of the log's `H Field I name` header
+    /// instead of the order they are listed in `fields`, as blackbox_decode does
+    pub preserve_order: bool,
+    /// Decode only this span of the binary section, starting at the first keyframe in it
+    pub byte_range: Option<Range<usize>>,
+    /// Decode from this offset of the input on, as when picking up a log that is still
+    /// being written where an earlier run stopped. The headers are still read from the
+    /// start of the input, and decoding resumes at the first keyframe at or after the
+    /// offset. Takes the place of `byte_range`.
+    pub resume_offset: Option<usize>,
+    /// Write a [`Checkpoint`] to this file at the first clean keyframe after every
+    /// `checkpoint_interval` main frames, once the rows before it are flushed, so a
+    /// decode stopped by a crash can pick up there with `resume`. Only a streamed decode
+    /// to CSV, [`stream_csv`] or [`decode_to_writer`], writes checkpoints.
+    pub checkpoint_out: Option<PathBuf>,
+    /// Main frames between checkpoints
+    pub checkpoint_interval: usize,
+    /// Pick up a streamed decode at a checkpoint an earlier run wrote, leaving out the
+    /// header rows and counting `rows_written` and the `sample` column on from the rows
+    /// written before it. The output the earlier run wrote is to be cut back with
+    /// [`Checkpoint::truncate_output`] and the rows appended to it.
+    ///
+    /// Decoding starts afresh at the checkpoint's keyframe, so the rows match those of
+    /// an uninterrupted decode as long as no option carries state from one keyframe to
+    /// the next: not slow or GPS fields, gap markers, thinning, relative or synthesized
+    /// time, `only_changed_rows`, `per_iteration`, `flight_only`, `reverse` or
+    /// `long_format`. The statistics only cover the frames after the checkpoint.
+    pub resume: Option<Checkpoint>,
+    /// Write [`NOISE_PREP_FIELDS`] instead of `fields`, followed by a `throttle` column
+    /// taken from `rcCommand[3]`, or from the mean motor output when the log has no
+    /// RC commands, so every gyro sample is paired with the throttle it was taken at
+    pub noise_prep: bool,
+    /// Write every `motor[N]` field the log has after `fields`, however many motors
+    /// it declares, followed by a `motor[N]_pct` column per motor with its output as a
+    /// percentage of the range in the `motorOutput` header. Motors driven by DShot or
+    /// ProShot, told from `motor_pwm_protocol`, are taken over the digital range of 48
+    /// to 2047 when the log has no `motorOutput`, with 0, a stopped motor, at 0%.
+    pub motors: bool,
+    /// Write the output in long format: one row per roll, pitch and yaw axis with an
+    /// `axis` column of 0, 1 or 2, a column per per-axis field such as `axisP` or
+    /// `gyroADC` holding that axis' value, and the other columns repeated on each row
+    pub long_format: bool,
+    /// File format of the output
+    pub format: OutputFormat,
+    /// Pretty-print each [`OutputFormat::JsonLines`] object over several lines, one
+    /// member to a line, for reading a few frames by eye. The output is then a stream of
+    /// JSON objects rather than JSON Lines, holding the same data. Other formats ignore it.
+    pub pretty_json: bool,
+    /// How the header block is parsed
+    pub header: HeaderOptions,
+    /// Numeric type forced on every column, `None` to keep each value's natural type
+    pub force_type: Option<ColumnType>,
+    /// Numeric type forced on individual fields, overriding `force_type`
+    pub force_types: HashMap<String, ColumnType>,
+    /// Digits after the decimal point for float values, `None` for the shortest exact form
+    pub precision: Option<usize>,
+    /// Digits after the decimal point for individual fields, overriding `precision`
+    pub field_precision: HashMap<String, usize>,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            fields: DEFAULT_FIELDS.iter().map(|name| name.to_string()).collect(),
+            only_fields_present: false,
+            units_header: false,
+            physical_units: false,
+            keep_raw: false,
+            rc_as_us: false,
+            progress_json: false,
+            flush_every: 1000,
+            first_i_frame: false,
+            threaded: false,
+            reverse: false,
+            flight_only: false,
+            frame_types: Vec::new(),
+            synthesize_time: false,
+            relative_time: false,
+            validity_column: false,
+            sample_index: false,
+            gap_markers: false,
+            gap_threshold: None,
+            per_iteration: false,
+            only_changed_rows: false,
+            include_slow: false,
+            slow_max_age: None,
+            flag_names: false,
+            start_time: None,
+            end_time: None,
+            export_interval: None,
+            decimate: None,
+            decimate_to: None,
+            stop_at_event: None,
+            max_frame_size: MAX_FRAME_SIZE,
+            altitude_source: None,
+            vibration_window: None,
+            session: None,
+            gps_out: None,
+            gpx_out: None,
+            kml_out: None,
+            min_satellites: None,
+            detect_loop_overrun: false,
+            detect_clipping: false,
+            report_anomalies: false,
+            dump_frame_bytes: None,
+            encoding_stats: false,
+            column_stats: false,
+            preview: None,
+            preview_decimate: 10,
+            flatten_arrays: false,
+            renames: HashMap::new(),
+            include_empty_fields: false,
+            preserve_order: false,
+            byte_range: None,
+            resume_offset: None,
+            checkpoint_out: None,
+            checkpoint_interval: 100_000,
+            resume: None,
+            noise_prep: false,
+            motors: false,
+            long_format: false,
+            format: OutputFormat::Csv,
+            pretty_json: false,
+            header: HeaderOptions::default(),
+            force_type: None,
+            force_types: HashMap::new(),
+            precision: None,
+            field_precision: HashMap::new(),
+        }
+    }
+}
+
+/// Decodes a complete log from `reader` and writes it to `writer` as CSV, the same way
+/// the command-line tool does.
+///
+/// ```
+/// use bbe_reader::DecodeOptions;
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration,time\n");
+/// log.extend_from_slice(b"H Field I signed:0,0\n");
+/// log.extend_from_slice(b"H Field I predictor:0,0\n");
+/// log.extend_from_slice(b"H Field I encoding:1,1\n");
+/// // Two I-frames: iteration 0 at 100 µs and iteration 1 at 200 µs
+/// log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01]);
+///
+/// let mut csv = Vec::new();
+/// let stats = bbe_reader::decode_to_writer(&log[..], &mut csv, DecodeOptions::default())?;
+///
+/// assert_eq!(stats.rows_written, 2);
+/// assert_eq!(stats.frame_counts[&'I'], 2);
+/// assert_eq!(String::from_utf8(csv).unwrap(), "loopIteration,time\n0,100\n1,200\n");
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+///
+/// Decoding stops cleanly at a malformed value, such as a variable-byte number that runs
+/// past 32 bits:
+///
+/// ```
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration\n");
+/// log.extend_from_slice(b"H Field I encoding:1\n");
+/// log.extend_from_slice(&[b'I', 7, b'I', 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+///
+/// let options = bbe_reader::DecodeOptions {
+///     fields: vec!["loopIteration".to_string()],
+///     ..Default::default()
+/// };
+/// let mut csv = Vec::new();
+/// let stats = bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+///
+/// assert_eq!(stats.rows_written, 1);
+/// assert_eq!(String::from_utf8(csv).unwrap(), "loopIteration\n7\n");
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+///
+/// A last frame that the input ends in the middle of is dropped, here one cut off after
+/// the first byte of its `time`:
+///
+/// ```
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration,time\n");
+/// log.extend_from_slice(b"H Field I encoding:1,1\n");
+/// log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8]);
+///
+/// let mut csv = Vec::new();
+/// let stats = bbe_reader::decode_to_writer(&log[..], &mut csv, Default::default())?;
+///
+/// assert_eq!(stats.rows_written, 1);
+/// assert_eq!(String::from_utf8(csv).unwrap(), "loopIteration,time\n0,100\n");
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+pub fn decode_to_writer<R: Read, W: Write + Send>(
+    reader: R,
+    writer: W,
+    opts: DecodeOptions,
+) -> Result<DecodeStats, BblError> {
+    let mut reader = BufReader::new(reader);
+    let log = BblLog::from_reader_with(&mut reader, &opts.header)?;
+
+    stream_csv(&log, reader, writer, &opts)
+}
+
+/// Renames an array element like `axisP[0]` to `axisP_0`.
+fn flatten_array_name(name: &str) -> String {
+    name.replace('[', "_").replace(']', "")
+}
+
+/// Returns where the throttle of a frame is read from: `rcCommand[3]` if logged, otherwise
+/// the mean of the motor outputs, which Betaflight logs as consecutive fields.
+fn throttle_source(field_map: &HashMap<&str, usize>) -> Option<ColumnSource> {
+    if let Some(&index) = field_map.get("rcCommand[3]") {
+        return Some(ColumnSource::Field(index));
+    }
+    let first = *field_map.get("motor[0]")?;
+    let count = (1..)
+        .take_while(|motor| {
+            field_map.get(format!("motor[{}]", motor).as_str()) == Some(&(first + motor))
+        })
+        .count()
+        + 1;
+    Some(ColumnSource::FieldAverage { first, count })
+}
+
+/// Returns the names of the motor output fields, `motor[0]`, `motor[1]`, ... up to the
+/// first the log lacks, so quads, hexes and octos all get every motor.
+fn motor_fields(field_map: &HashMap<&str, usize>) -> Vec<String> {
+    (0..)
+        .map(|motor| format!("motor[{}]", motor))
+        .take_while(|name| field_map.contains_key(name.as_str()))
+        .collect()
+}
+
+/// Returns how the values of a column are formatted.
+fn column_format(options: &DecodeOptions, name: &str, scale: Option<Scale>) -> ColumnFormat {
+    ColumnFormat {
+        scale,
+        column_type: options
+            .force_types
+            .get(name)
+            .copied()
+            .or(options.force_type),
+        precision: options
+            .field_precision
+            .get(name)
+            .copied()
+            .or(options.precision),
+        flags: flag_names(name).filter(|_| options.flag_names),
+    }
+}
+
+/// Output columns with the key they are matched by across sessions, which is the name of
+/// the field or derived value they hold.
+type KeyedColumns = Vec<(String, Column)>;
+
+/// Resolves the requested output columns against the field layout of `log`. Returns the
+/// header row, the unit of each header cell and the columns.
+fn output_columns(
+    log: &BblLog,
+    options: &DecodeOptions,
+) -> (Vec<String>, Vec<&'static str>, KeyedColumns) {
+    // Create a map of field names to their position in the main frame layout, inserting
+    // in reverse so that a duplicated name maps to its first position
+    let field_map: HashMap<&str, usize> = log
+        .main_fields()
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(index, f)| (f.name.as_str(), index))
+        .collect();
+
+    // Look up each desired field with its physical-unit conversion
+    let mut csv_header: Vec<String> = Vec::new();
+    let mut units: Vec<&'static str> = Vec::new();
+    let mut columns: Vec<(String, Column)> = Vec::new();
+
+    if options.sample_index {
+        csv_header.push("sample".to_string());
+        units.push("count");
+        columns.push((
+            "sample".to_string(),
+            Column {
+                source: ColumnSource::SampleIndex,
+                format: column_format(options, "sample", None),
+            },
+        ));
+    }
+
+    let mut fields: Vec<String> = if options.noise_prep {
+        NOISE_PREP_FIELDS
+            .iter()
+            .map(|name| name.to_string())
+            .collect()
+    } else if options.only_fields_present {
+        log.main_field_names()
+    } else {
+        options.fields.clone()
+    };
+    let motors = if options.motors {
+        motor_fields(&field_map)
+    } else {
+        Vec::new()
+    };
+    for motor in &motors {
+        if !fields.contains(motor) {
+            fields.push(motor.clone());
+        }
+    }
+    if options.preserve_order {
+        // Fields the log lacks keep their requested order after the others
+        fields.sort_by_key(|name| field_map.get(name.as_str()).copied().unwrap_or(usize::MAX));
+    }
+    for field_name in &fields {
+        let index = field_map.get(field_name.as_str()).copied();
+        if index.is_none() && !options.include_empty_fields {
+            continue;
+        }
+        let rc_scale = rc_pulse_scale(field_name).filter(|_| options.rc_as_us);
+        let scale: Option<Scale> = if rc_scale.is_some() {
+            rc_scale
+        } else if options.physical_units {
+            let scale = physical_scale(field_name, &log.headers);
+            if let (None, Some(_), Some(header)) = (scale, index, scale_header(field_name)) {
+                eprintln!(
+                    "Warning: no usable `H {}:` header to convert {}, writing raw values",
+                    header, field_name
+                );
+            }
+            scale
+        } else {
+            None
+        };
+
+        let column_name = if let Some(renamed) = options.renames.get(field_name) {
+            renamed.clone()
+        } else if options.flatten_arrays {
+            flatten_array_name(field_name)
+        } else {
+            field_name.to_string()
+        };
+        match scale {
+            Some(scale) if options.keep_raw => {
+                let scaled_name = format!("{}_{}", column_name, scale.suffix);
+                csv_header.push(column_name);
+                units.push(field_unit(field_name));
+                csv_header.push(scaled_name);
+                units.push(scale.unit);
+            }
+            Some(scale) => {
+                csv_header.push(column_name);
+                units.push(scale.unit);
+            }
+            None => {
+                csv_header.push(column_name);
+                units.push(field_unit(field_name));
+            }
+        }
+        columns.push((
+            field_name.to_string(),
+            Column {
+                source: index.map_or(ColumnSource::Absent, ColumnSource::Field),
+                format: column_format(options, field_name, scale),
+            },
+        ));
+    }
+
+    if options.noise_prep {
+        match throttle_source(&field_map) {
+            Some(source) => {
+                csv_header.push("throttle".to_string());
+                units.push("raw");
+                columns.push((
+                    "throttle".to_string(),
+                    Column {
+                        source,
+                        format: column_format(options, "throttle", None),
+                    },
+                ));
+            }
+            None => eprintln!("Warning: no rcCommand[3] or motor fields to take throttle from"),
+        }
+    }
+
+    if options.motors {
+        let digital = is_digital_motor_protocol(&log.headers);
+        match motor_range(&log.headers) {
+            _ if motors.is_empty() => eprintln!("Warning: the log has no motor fields"),
+            Some((min, max)) => {
+                let percent = Scale {
+                    factor: 100.0 / (max - min) as f64,
+                    offset: 0.0,
+                    unit: "%",
+                    suffix: "pct",
+                };
+                for motor in &motors {
+                    let column_name = if options.flatten_arrays {
+                        flatten_array_name(motor)
+                    } else {
+                        motor.clone()
+                    };
+                    let column_name = format!("{}_{}", column_name, percent.suffix);
+                    let mut format = column_format(options, &column_name, Some(percent));
+                    format.precision = format.precision.or(Some(1));
+                    csv_header.push(column_name);
+                    units.push(percent.unit);
+                    columns.push((
+                        format!("{}_{}", motor, percent.suffix),
+                        Column {
+                            source: ColumnSource::MotorOutput {
+                                index: field_map[motor.as_str()],
+                                min,
+                                digital,
+                            },
+                            format,
+                        },
+                    ));
+                }
+            }
+            None => eprintln!(
+                "Warning: no usable `H motorOutput:` header, writing motors without percentages"
+            ),
+        }
+    }
+
+    if options.per_iteration || options.include_slow {
+        let slow_fields = log
+            .layouts
+            .get(&b'S')
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        for (index, field) in slow_fields.iter().enumerate() {
+            let column_name = if options.flatten_arrays {
+                flatten_array_name(&field.name)
+            } else {
+                field.name.clone()
+            };
+            csv_header.push(column_name);
+            units.push(field_unit(&field.name));
+            columns.push((
+                format!("S {}", field.name),
+                Column {
+                    source: ColumnSource::SlowField(index),
+                    format: column_format(options, &field.name, None),
+                },
+            ));
+        }
+    }
+
+    if let Some(altitude_source) = options.altitude_source {
+        let baro = field_map.get("baroAlt").copied();
+        let gps = log
+            .layouts
+            .get(&b'G')
+            .and_then(|fields| fields.iter().position(|f| f.name == "GPS_altitude"));
+        let (baro, gps) = match altitude_source {
+            AltitudeSource::Baro => (baro, None),
+            AltitudeSource::Gps => (None, gps),
+            AltitudeSource::Merge => (baro, gps),
+        };
+        if baro.is_none() && gps.is_none() {
+            eprintln!("Warning: no baroAlt or GPS_altitude field to take altitude from");
+        }
+        csv_header.push("altitude".to_string());
+        units.push("cm");
+        columns.push((
+            "altitude".to_string(),
+            Column {
+                source: ColumnSource::Altitude { baro, gps },
+                format: column_format(options, "altitude", None),
+            },
+        ));
+    }
+
+    if options.synthesize_time {
+        if parse_loop_period(&log.headers).is_some() {
+            csv_header.push("time_synth".to_string());
+            units.push("µs");
+            columns.push((
+                "time_synth".to_string(),
+                Column {
+                    source: ColumnSource::SynthesizedTime,
+                    format: column_format(options, "time_synth", None),
+                },
+            ));
+        } else {
+            eprintln!("Warning: cannot synthesize time without a looptime header");
+        }
+    }
+
+    if options.relative_time {
+        if !field_map.contains_key("time") && parse_loop_period(&log.headers).is_none() {
+            eprintln!("Warning: the log has no time field or looptime, leaving time_s empty");
+        }
+        csv_header.push("time_s".to_string());
+        units.push("s");
+        let seconds = Scale {
+            factor: 1e-6,
+            offset: 0.0,
+            unit: "s",
+            suffix: "s",
+        };
+        let mut format = column_format(options, "time_s", Some(seconds));
+        // Down to the µs the time is logged in
+        format.precision = format.precision.or(Some(6));
+        columns.push((
+            "time_s".to_string(),
+            Column {
+                source: ColumnSource::RelativeTime,
+                format,
+            },
+        ));
+    }
+
+    if options.validity_column {
+        csv_header.push("valid".to_string());
+        units.push("flag");
+        columns.push((
+            "valid".to_string(),
+            Column {
+                source: ColumnSource::Validity,
+                format: column_format(options, "valid", None),
+            },
+        ));
+    }
+    (csv_header, units, columns)
+}
+
+/// Lines the columns of a later session up with those of the first, so its rows fit
+/// under the header row that was already written. Columns the session cannot fill in
+/// the same number of cells are left empty.
+fn align_columns(
+    first: &KeyedColumns,
+    session: &KeyedColumns,
+    options: &DecodeOptions,
+) -> Vec<Column> {
+    first
+        .iter()
+        .map(|(key, column)| {
+            let cells = |column: &Column| column.format.scale.is_some() && options.keep_raw;
+            match session.iter().find(|(session_key, _)| session_key == key) {
+                Some((_, session_column)) if cells(session_column) == cells(column) => {
+                    *session_column
+                }
+                _ => Column {
+                    source: ColumnSource::Absent,
+                    format: column.format,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Writes the header row(s) and the decoded frames of `data`, the binary section that
+/// follows the headers of `log`, in the format chosen by `options`.
+///
+/// Later sessions in `data` are decoded with their own header block. Their columns are
+/// matched to the first session's header row by field name, and fields a session does
+/// not log are left empty. With a byte range, only the first session is decoded.
+///
+/// ```
+/// use bbe_reader::DecodeOptions;
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration,time,axisP[0]\n");
+/// log.extend_from_slice(b"H Field I encoding:1,1,0\n");
+/// log.extend_from_slice(&[b'I', 0, 100, 4, b'E', 255]);
+/// log.extend_from_slice(b"End of log\0");
+/// // The second session logs axisP[0] before time and drops loopIteration
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:axisP[0],time\n");
+/// log.extend_from_slice(b"H Field I encoding:0,1\n");
+/// log.extend_from_slice(&[b'I', 6, 0xc8, 0x01]);
+///
+/// let options = DecodeOptions {
+///     fields: vec!["loopIteration".into(), "time".into(), "axisP[0]".into()],
+///     ..DecodeOptions::default()
+/// };
+/// let mut csv = Vec::new();
+/// bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+///
+/// assert_eq!(
+///     String::from_utf8(csv).unwrap(),
+///     "loopIteration,time,axisP[0]\n0,100,2\n,200,3\n"
+/// );
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+pub fn write_csv<W: Write + Send>(
+    log: &BblLog,
+    data: &[u8],
+    writer: W,
+    options: &DecodeOptions,
+) -> Result<DecodeStats, BblError> {
+    let options = &resolve_resume_offset(log, options);
+    with_sessions(log, data, options, |csv_header, units, sessions| {
+        write_output(log, writer, csv_header, units, options, |sink| {
+            decode_sessions(sessions, sink, options)
+        })
+    })
+}
+
+/// Like [`write_csv`], reading the binary section from `reader`, which is positioned
+/// after the headers of `log`. Only a window of the input is held in memory, so long
+/// logs start writing at once and need no more memory than short ones.
+///
+/// A byte range, a chosen session, [`DecodeOptions::first_i_frame`] and
+/// [`DecodeOptions::progress_json`] need the whole binary section, which is then read
+/// into memory first.
+///
+/// ```
+/// use bbe_reader::{BblLog, DecodeOptions};
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H I interval:32\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration,time,axisP[0]\n");
+/// log.extend_from_slice(b"H Field I encoding:1,1,0\n");
+/// log.extend_from_slice(b"H Field P predictor:6,1,1\n");
+/// log.extend_from_slice(b"H Field P encoding:0,0,0\n");
+/// // Far more than the decoder reads at a time, so frames straddle its refills
+/// for iteration in 0..100_000 {
+///     if iteration % 32 == 0 {
+///         log.extend_from_slice(&[b'I', 0xc0, 0x84, 0x3d, 0xe8, 0x07, 0x14]);
+///     } else {
+///         log.extend_from_slice(&[b'P', 0, 0xfa, 0x01, (iteration % 4) as u8]);
+///     }
+/// }
+///
+/// let header = BblLog::from_bytes(&log)?;
+/// let data = &log[header.data_offset..];
+/// let options = DecodeOptions {
+///     fields: vec!["loopIteration".into(), "time".into(), "axisP[0]".into()],
+///     ..DecodeOptions::default()
+/// };
+/// let mut streamed = Vec::new();
+/// let stats = bbe_reader::stream_csv(&header, data, &mut streamed, &options)?;
+/// let mut in_memory = Vec::new();
+/// bbe_reader::write_csv(&header, data, &mut in_memory, &options)?;
+///
+/// assert_eq!(stats.rows_written, 100_000);
+/// assert!(streamed == in_memory);
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+pub fn stream_csv<R: Read, W: Write + Send>(
+    log: &BblLog,
+    mut reader: R,
+    writer: W,
+    options: &DecodeOptions,
+) -> Result<DecodeStats, BblError> {
+    if options.byte_range.is_some()
+        || options.resume_offset.is_some()
+        || options.session.is_some()
+        || options.first_i_frame
+        || options.progress_json
+    {
+        let mut buffer = Vec::new();
+        reader.read_to_end(&mut buffer)?;
+        return write_csv(log, &buffer, writer, options);
+    }
+
+    warn_missing_renames(log, options);
+    let (csv_header, units, first_columns) = output_columns(log, options);
+    let units: Vec<String> = units.into_iter().map(String::from).collect();
+    // The first session is written under its own header row, and the columns of later
+    // ones are lined up with it
+    let mut first_session = options
+        .resume
+        .is_none_or(|checkpoint| checkpoint.session == 0);
+    let session_columns = |session_log: &BblLog| {
+        if std::mem::take(&mut first_session) {
+            first_columns.iter().map(|(_, column)| *column).collect()
+        } else {
+            let (_, _, columns) = output_columns(session_log, options);
+            align_columns(&first_columns, &columns, options)
+        }
+    };
+    write_output(log, writer, &csv_header, &units, options, |sink| {
+        in_output_order(sink, options, |sink| {
+            decode_stream(log, reader, session_columns, sink, options)
+        })
+    })
+}
+
+/// Writes the header row, the units row if asked for, and the rows `decode` produces in
+/// the output format and to the preview chosen by `options`.
+fn write_output<W: Write + Send>(
+    log: &BblLog,
+    writer: W,
+    csv_header: &[String],
+    units: &[String],
+    options: &DecodeOptions,
+    decode: impl FnOnce(&mut dyn RecordSink) -> Result<DecodeStats, BblError>,
+) -> Result<DecodeStats, BblError> {
+    let units = options.units_header.then_some(units);
+    let preview = match &options.preview {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+    match options.format {
+        OutputFormat::Csv => write_rows(
+            WithPreview::new(
+                Writer::from_writer(writer),
+                preview.map(Writer::from_writer),
+                options.preview_decimate,
+                1 + units.is_some() as usize,
+            ),
+            csv_header,
+            units,
+            options,
+            decode,
+        ),
+        OutputFormat::JsonLines => write_rows(
+            WithPreview::new(
+                JsonLinesSink::new(writer, log, options.pretty_json),
+                preview.map(|preview| JsonLinesSink::new(preview, log, options.pretty_json)),
+                options.preview_decimate,
+                1,
+            ),
+            csv_header,
+            None,
+            options,
+            decode,
+        ),
+        #[cfg(feature = "msgpack")]
+        OutputFormat::MessagePack => write_rows(
+            WithPreview::new(
+                MessagePackSink::new(writer),
+                preview.map(MessagePackSink::new),
+                options.preview_decimate,
+                1,
+            ),
+            csv_header,
+            None,
+            options,
+            decode,
+        ),
+        #[cfg(feature = "sqlite")]
+        OutputFormat::Sqlite => write_rows(
+            WithPreview::new(
+                SqliteSink::new(writer, log),
+                preview.map(|preview| SqliteSink::new(preview, log)),
+                options.preview_decimate,
+                1,
+            ),
+            csv_header,
+            None,
+            options,
+            decode,
+        ),
+    }
+}
+
+/// A log decoded into memory.
+#[derive(Debug, Clone)]
+pub struct DecodedLog {
+    /// The header block of the first session
+    pub header: BlackboxHeader,
+    /// Name of each column, as in the CSV header row
+    pub columns: Vec<String>,
+    /// The decoded rows, each with one cell per column as it would be written to CSV
+    pub records: Vec<Vec<String>>,
+    /// Frame counts, missing frames and the other counters collected while decoding
+    pub stats: DecodeStats,
+}
+
+/// The plaintext header block of a log, parsed into its header lines and frame layouts.
+pub type BlackboxHeader = BblLog;
+
+/// Decodes the binary data following the headers of `log` into memory, with the same
+/// columns and cells [`write_csv`] would write.
+///
+/// ```
+/// use bbe_reader::{BblLog, DecodeOptions};
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration,time\n");
+/// log.extend_from_slice(b"H Field I encoding:1,1\n");
+/// log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01]);
+///
+/// let header = BblLog::from_bytes(&log)?;
+/// let data = &log[header.data_offset..];
+/// let decoded = bbe_reader::decode_log(&header, data, &DecodeOptions::default())?;
+///
+/// assert_eq!(decoded.columns, ["loopIteration", "time"]);
+/// assert_eq!(decoded.records, [["0", "100"], ["1", "200"]]);
+/// assert_eq!(decoded.stats.rows_written, 2);
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+pub fn decode_log(
+    log: &BblLog,
+    data: &[u8],
+    options: &DecodeOptions,
+) -> Result<DecodedLog, BblError> {
+    let options = &resolve_resume_offset(log, options);
+    let mut records = Vec::new();
+    let stats = with_sessions(log, data, options, |columns, _, sessions| {
+        write_rows(&mut records, columns, None, options, |sink| {
+            decode_sessions(sessions, sink, options)
+        })
+    })?;
+    // The header row is written first, like any other record
+    let columns = if records.is_empty() {
+        Vec::new()
+    } else {
+        records.remove(0)
+    };
+    Ok(DecodedLog {
+        header: log.clone(),
+        columns,
+        records,
+        stats,
+    })
+}
+
+/// Reads and decodes a whole `.BBL` file into memory with the default options.
+pub fn decode_file(path: &Path) -> Result<DecodedLog, BblError> {
+    decode_file_with(path, &DecodeOptions::default())
+}
+
+/// Reads and decodes a whole `.BBL` file into memory.
+pub fn decode_file_with(path: &Path, options: &DecodeOptions) -> Result<DecodedLog, BblError> {
+    let bytes = std::fs::read(path)?;
+    let log = BblLog::from_bytes_with(&bytes, &options.header)?;
+    decode_log(&log, &bytes[log.data_offset..], options)
+}
+
+/// Splits a whole `.BBL` file into its sessions, each from its `H Product:` header line
+/// to the start of the next. A session holds its own headers and predictor state, so
+/// each can be decoded on its own, for example on a thread of its own.
+///
+/// ```
+/// use bbe_reader::DecodeOptions;
+///
+/// let mut session = Vec::new();
+/// session.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// session.extend_from_slice(b"H Field I name:loopIteration,time\n");
+/// session.extend_from_slice(b"H Field I encoding:1,1\n");
+/// session.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01]);
+/// let log = [&session[..], &session[..]].concat();
+///
+/// let sessions = bbe_reader::split_sessions(&log);
+///
+/// assert_eq!(sessions, [&session[..], &session[..]]);
+/// let stats = bbe_reader::decode_to_writer(sessions[1], std::io::sink(), DecodeOptions::default())?;
+/// assert_eq!(stats.rows_written, 2);
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+pub fn split_sessions(data: &[u8]) -> Vec<&[u8]> {
+    let starts = find_session_starts(data);
+    let ends = starts.iter().skip(1).copied().chain([data.len()]);
+    starts
+        .iter()
+        .zip(ends)
+        .map(|(&start, end)| &data[start..end])
+        .collect()
+}
+
+/// Splits a whole `.BBL` file where its field layout changes: each part runs from a
+/// session to the next one whose headers declare different fields, so the sessions of a
+/// part decode under one header row. A session whose headers cannot be read stays in
+/// the part before it.
+///
+/// ```
+/// use bbe_reader::DecodeOptions;
+///
+/// let header = b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n";
+/// let mut first = header.to_vec();
+/// first.extend_from_slice(b"H Field I name:loopIteration,time\n");
+/// first.extend_from_slice(b"H Field I encoding:1,1\n");
+/// first.extend_from_slice(&[b'I', 0, 100]);
+/// let mut second = header.to_vec();
+/// second.extend_from_slice(b"H Field I name:loopIteration,time,vbatLatest\n");
+/// second.extend_from_slice(b"H Field I encoding:1,1,1\n");
+/// second.extend_from_slice(&[b'I', 0, 100, 42]);
+/// let log = [&first[..], &first[..], &second[..]].concat();
+///
+/// let parts = bbe_reader::split_on_layout_change(&log, &Default::default());
+///
+/// assert_eq!(parts, [&[&first[..], &first[..]].concat()[..], &second[..]]);
+/// let mut outputs = Vec::new();
+/// for part in parts {
+///     let options = DecodeOptions {
+///         fields: bbe_reader::BblLog::from_bytes(part)?.main_field_names(),
+///         ..DecodeOptions::default()
+///     };
+///     let mut csv = Vec::new();
+///     bbe_reader::decode_to_writer(part, &mut csv, options)?;
+///     outputs.push(String::from_utf8(csv).unwrap());
+/// }
+/// assert_eq!(outputs[0], "loopIteration,time\n0,100\n0,100\n");
+/// assert_eq!(outputs[1], "loopIteration,time,vbatLatest\n0,100,42\n");
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+pub fn split_on_layout_change<'a>(data: &'a [u8], options: &HeaderOptions) -> Vec<&'a [u8]> {
+    let starts = find_session_starts(data);
+    let ends = starts.iter().skip(1).copied().chain([data.len()]);
+    let mut part_starts = Vec::new();
+    let mut part_layouts = None;
+    for (&start, end) in starts.iter().zip(ends) {
+        let layouts = BblLog::from_bytes_with(&data[start..end], options)
+            .ok()
+            .map(|log| log.layouts);
+        if part_starts.is_empty() || layouts.is_some() && layouts != part_layouts {
+            part_starts.push(start);
+            part_layouts = layouts;
+        }
+    }
+    let part_ends = part_starts.iter().skip(1).copied().chain([data.len()]);
+    part_starts
+        .iter()
+        .zip(part_ends)
+        .map(|(&start, end)| &data[start..end])
+        .collect()
+}
+
+/// Turns [`DecodeOptions::resume_offset`], an offset of the input, into the byte range of
+/// the binary section of `log` from there on.
+fn resolve_resume_offset<'a>(log: &BblLog, options: &'a DecodeOptions) -> Cow<'a, DecodeOptions> {
+    match options.resume_offset {
+        Some(offset) => Cow::Owned(DecodeOptions {
+            byte_range: Some(offset.saturating_sub(log.data_offset)..usize::MAX),
+            resume_offset: None,
+            ..options.clone()
+        }),
+        None => Cow::Borrowed(options),
+    }
+}
+
+/// Resolves the output columns of `log` and of each later session in `data`, then hands
+/// the header row, the units row and the sessions to `write`.
+fn with_sessions<T>(
+    log: &BblLog,
+    data: &[u8],
+    options: &DecodeOptions,
+    write: impl FnOnce(&[String], &[String], &[Session]) -> Result<T, BblError>,
+) -> Result<T, BblError> {
+    warn_missing_renames(log, options);
+
+    // The firmware starts a new header block on every arm, and each may declare
+    // different fields. A byte range is decoded as part of the first session.
+    let starts = if options.byte_range.is_some() {
+        Vec::new()
+    } else {
+        find_session_starts(data)
+    };
+    // A session whose headers cannot be read ends the log, as when the recording was cut
+    // off while writing them
+    let mut logs = Vec::new();
+    for (index, &start) in starts.iter().enumerate() {
+        match BblLog::from_bytes_with(&data[start..], &options.header) {
+            Ok(session_log) => logs.push(session_log),
+            Err(e) => {
+                eprintln!(
+                    "Warning: cannot read the headers of session {}: {}, stopping",
+                    index + 2,
+                    e
+                );
+                break;
+            }
+        }
+    }
+    let ends = starts.iter().skip(1).copied().chain([data.len()]);
+    let mut session_logs = vec![(log, &data[..starts.first().copied().unwrap_or(data.len())])];
+    for ((session_log, &start), end) in logs.iter().zip(&starts).zip(ends) {
+        session_logs.push((
+            session_log,
+            &data[(start + session_log.data_offset).min(end)..end],
+        ));
+    }
+
+    // A chosen session is written on its own, under its own header row
+    if let Some(number) = options.session {
+        let Some(&(session_log, session_data)) = number
+            .checked_sub(1)
+            .and_then(|index| session_logs.get(index))
+        else {
+            return Err(BblError::NoSuchSession {
+                requested: number,
+                sessions: session_logs.len(),
+            });
+        };
+        let (csv_header, units, columns) = output_columns(session_log, options);
+        let session = Session {
+            log: session_log,
+            data: session_data,
+            columns: columns.into_iter().map(|(_, column)| column).collect(),
+        };
+        let units: Vec<String> = units.into_iter().map(String::from).collect();
+        return write(&csv_header, &units, &[session]);
+    }
+
+    let (csv_header, units, first_columns) = output_columns(log, options);
+    let sessions: Vec<Session> = session_logs
+        .into_iter()
+        .enumerate()
+        .map(|(index, (session_log, session_data))| {
+            let columns = if index == 0 {
+                first_columns.iter().map(|(_, column)| *column).collect()
+            } else {
+                let (_, _, columns) = output_columns(session_log, options);
+                align_columns(&first_columns, &columns, options)
+            };
+            Session {
+                log: session_log,
+                data: session_data,
+                columns,
+            }
+        })
+        .collect();
+
+    let units: Vec<String> = units.into_iter().map(String::from).collect();
+    write(&csv_header, &units, &sessions)
+}
+
+/// Warns about renamed fields `log` does not have.
+fn warn_missing_renames(log: &BblLog, options: &DecodeOptions) {
+    for source in options.renames.keys() {
+        if !log.main_fields().iter().any(|field| &field.name == source) {
+            eprintln!(
+                "Warning: cannot rename {}, the log has no such field",
+                source
+            );
+        }
+    }
+}
+
+/// Writes only the CSV header row that [`write_csv`] would write for `log`, so files can
+/// be created with the right columns before rows from many logs are appended.
+///
+/// ```
+/// use bbe_reader::{BblLog, DecodeOptions};
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration,time,axisP[0]\n");
+/// log.extend_from_slice(b"H Field I encoding:1,1,0\n");
+/// log.extend_from_slice(&[b'I', 0, 100, 2]);
+///
+/// let options = DecodeOptions {
+///     fields: vec!["time".to_string(), "axisP[0]".to_string()],
+///     units_header: true,
+///     ..DecodeOptions::default()
+/// };
+/// let mut csv = Vec::new();
+/// bbe_reader::write_schema_csv(&BblLog::from_bytes(&log)?, &mut csv, &options)?;
+///
+/// assert_eq!(String::from_utf8(csv).unwrap(), "time,axisP[0]\n");
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+pub fn write_schema_csv<W: Write>(
+    log: &BblLog,
+    writer: W,
+    options: &DecodeOptions,
+) -> Result<(), BblError> {
+    let (csv_header, _, _) = output_columns(log, options);
+    let mut writer = Writer::from_writer(writer);
+    writer.write_record(&csv_header)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes a Polars schema for the CSV [`write_csv`] would write for `log`: a JSON object
+/// from each column name to its dtype, `Int64`, `Float64` or `String` for flag names. The
+/// dtypes follow from how each column is formatted rather than from its values, so a
+/// scaled column that happens to start with whole numbers is still `Float64`. It
+/// describes the wide layout, not [`DecodeOptions::long_format`], and with
+/// [`DecodeOptions::units_header`] the units row has to be skipped when reading.
+///
+/// ```
+/// use bbe_reader::{BblLog, DecodeOptions};
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H gyro_scale:0x3f800000\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration,time,gyroADC[0],flightModeFlags\n");
+/// log.extend_from_slice(b"H Field I encoding:1,1,0,1\n");
+/// log.extend_from_slice(&[b'I', 0, 100, 2, 0]);
+///
+/// let options = DecodeOptions {
+///     fields: vec![
+///         "loopIteration".to_string(),
+///         "gyroADC[0]".to_string(),
+///         "flightModeFlags".to_string(),
+///     ],
+///     physical_units: true,
+///     keep_raw: true,
+///     flag_names: true,
+///     ..DecodeOptions::default()
+/// };
+/// let mut json = Vec::new();
+/// bbe_reader::write_polars_schema(&BblLog::from_bytes(&log)?, &mut json, &options)?;
+///
+/// assert_eq!(
+///     String::from_utf8(json).unwrap(),
+///     concat!(
+///         "{\n",
+///         "  \"loopIteration\": \"Int64\",\n",
+///         "  \"gyroADC[0]\": \"Int64\",\n",
+///         "  \"gyroADC[0]_degps\": \"Float64\",\n",
+///         "  \"flightModeFlags\": \"String\"\n",
+///         "}\n",
+///     )
+/// );
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn write_polars_schema<W: Write>(
+    log: &BblLog,
+    mut writer: W,
+    options: &DecodeOptions,
+) -> Result<(), BblError> {
+    let (csv_header, _, columns) = output_columns(log, options);
+    let mut dtypes = Vec::with_capacity(csv_header.len());
+    for (_, column) in &columns {
+        push_dtypes(&mut dtypes, &column.format, options.keep_raw);
+    }
+    let members: Vec<String> = csv_header
+        .iter()
+        .zip(dtypes)
+        .map(|(name, dtype)| format!("  {}: {}", json_string(name), json_string(dtype)))
+        .collect();
+    writeln!(writer, "{{\n{}\n}}", members.join(",\n"))?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Decodes the sessions of a log in memory into `sink`.
+fn decode_sessions(
+    sessions: &[Session],
+    sink: &mut dyn RecordSink,
+    options: &DecodeOptions,
+) -> Result<DecodeStats, BblError> {
+    if options.first_i_frame {
+        // Write only the keyframe's values
+        let first = &sessions[0];
+        Ok(decode_first_i_frame(
+            first.data,
+            first.log,
+            &first.columns,
+            sink,
+            options,
+        )?)
+    } else {
+        in_output_order(sink, options, |sink| {
+            Ok(decode_binary_data(sessions, sink, options)?)
+        })
+    }
+}
+
+/// Runs `decode` into `sink`, reversing the order of the rows if asked.
+fn in_output_order(
+    sink: &mut dyn RecordSink,
+    options: &DecodeOptions,
+    decode: impl FnOnce(&mut dyn RecordSink) -> Result<DecodeStats, BblError>,
+) -> Result<DecodeStats, BblError> {
+    if options.reverse {
+        // P-frames are deltas from the frames before them, so every segment is
+        // decoded forward and only the order of the segments is reversed
+        let mut segments = ReversedSegments::default();
+        let stats = decode(&mut segments)?;
+        segments.write_to(sink)?;
+        Ok(stats)
+    } else {
+        decode(sink)
+    }
+}
+
+/// Writes the header row, the optional units row and the rows `decode` produces to
+/// `sink`.
+fn write_rows<S: RecordSink + Send>(
+    sink: S,
+    header: &[String],
+    units: Option<&[String]>,
+    options: &DecodeOptions,
+    decode: impl FnOnce(&mut dyn RecordSink) -> Result<DecodeStats, BblError>,
+) -> Result<DecodeStats, BblError> {
+    let header_rows = 1 + units.is_some() as usize;
+    let mut sink = LongFormat::new(sink, options.long_format, header_rows);
+    // A resumed decode appends to the rows written before the checkpoint
+    if options.resume.is_none() {
+        sink.write_record(header)?;
+        if let Some(units) = units {
+            sink.write_record(units)?;
+        }
+    }
+
+    let mut summaries = options
+        .column_stats
+        .then(|| header.iter().map(|name| ColumnSummary::new(name)).collect());
+    let mut stats = if options.threaded {
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::sync_channel(WRITER_QUEUE_LEN);
+            let writer_thread = scope.spawn(move || -> io::Result<_> {
+                for message in receiver {
+                    match message {
+                        SinkMessage::Record(record) => sink.write_record(&record)?,
+                        SinkMessage::Flush => sink.flush()?,
+                    }
+                }
+                Ok(sink)
+            });
+
+            let decoded = decode(&mut Summarizing::new(
+                ChannelSink(sender),
+                summaries.as_mut(),
+            ));
+            // The sender is dropped by now, so the writer thread drains the queue and exits
+            let mut sink = writer_thread.join().expect("writer thread panicked")?;
+            sink.finish()?;
+            decoded
+        })?
+    } else {
+        let stats = decode(&mut Summarizing::new(&mut sink, summaries.as_mut()))?;
+        sink.finish()?; // Ensure all data is written to the output
+        stats
+    };
+
+    if let Some(summaries) = summaries {
+        stats.columns = summaries;
+    }
+    Ok(stats)
+}
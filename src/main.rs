@@ -1,315 +1,1661 @@
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+use clap::error::ErrorKind;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, BufReader, BufRead, Read};
-use csv::Writer;
+use std::io::{self, BufReader, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use bbe_reader::{
+    AltitudeSource, AtomicFile, BblError, BblLog, Checkpoint, ColumnType, DecodeOptions,
+    DecodeStats, GapThreshold, HeaderOptions, OutputFormat, SlowMaxAge, EVENT_NAMES,
+    FEEDFORWARD_FIELDS, FRAME_TYPES, HEADING_FIELDS,
+};
+
+/// Set while the output is written to stdout, so status messages go to stderr instead.
+static OUTPUT_ON_STDOUT: AtomicBool = AtomicBool::new(false);
+
+/// Set by --quiet to leave out the progress notes.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Prints a status message to stdout, or to stderr while stdout carries the output.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if OUTPUT_ON_STDOUT.load(Ordering::Relaxed) {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+/// Prints a progress note like [`status!`], unless --quiet is given.
+macro_rules! note {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            status!($($arg)*)
+        }
+    };
+}
 
 /// Command-line arguments
 #[derive(Parser, Debug)]
 #[clap(author = "Your Name", version = "0.1.0", about = "BBL File Reader with CSV Output")]
 struct Args {
-    /// Input .BBL file
-    #[clap(short, long)]
-    input: String,
+    /// Input .BBL file, or - to read the log from stdin and write the output to stdout
+    #[clap(short, long, required_unless_present_any = ["input_list", "self_test", "fuzz_vlq"])]
+    input: Option<String>,
+
+    /// Print the headers of the log to stderr, and with -vv also the field definitions
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Leave out progress notes such as the decoded frame count; reports asked for, like
+    /// --stats, are still printed
+    #[clap(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Write the output to this path instead of <input stem>.csv in the working
+    /// directory, or to stdout for -. An existing directory, or a path ending in /, gets
+    /// the stem-named file, and {stem} in the path is replaced by the input's stem, so
+    /// each input of --input-list gets its own file
+    #[clap(short, long, value_name = "PATH")]
+    output: Option<String>,
+
+    /// Text file listing one input per line, each decoded in turn; blank lines and
+    /// lines starting with # are ignored
+    #[clap(long, conflicts_with_all = ["input", "reference"])]
+    input_list: Option<std::path::PathBuf>,
+
+    /// Decode a small log built into the program, check the output against its known
+    /// values and print whether the decoder passed
+    #[clap(long, exclusive = true)]
+    self_test: bool,
+
+    /// Round-trip N random u32 and i32 values and the VLQ boundary values through the
+    /// VLQ writers and readers, reporting any that come back different
+    #[clap(long, exclusive = true, hide = true, value_name = "N")]
+    fuzz_vlq: Option<usize>,
+
+    /// Emit a second header row listing each column's unit
+    #[clap(long)]
+    units_header: bool,
+
+    /// Convert supported fields to physical units (gyro in deg/s, accelerometer in g, vbat in volts,
+    /// rssi in percent)
+    #[clap(long, conflicts_with = "units")]
+    physical_units: bool,
+
+    /// Units of the written values: `raw` as logged, or `physical`, the same as
+    /// --physical-units
+    #[clap(long, value_name = "UNITS", default_value = "raw", value_parser = parse_units)]
+    units: Units,
+
+    /// With physical units or --rc-as-us, keep each raw column next to its scaled column
+    #[clap(long)]
+    keep_raw: bool,
+
+    /// Write rcCommand as receiver pulse widths in µs: roll, pitch and yaw offset from
+    /// the 1500 µs stick center, throttle (rcCommand[3]) on its own 1000-2000 µs range
+    #[clap(long)]
+    rc_as_us: bool,
+
+    /// Print machine-readable progress as JSON lines on stderr
+    #[clap(long)]
+    progress_json: bool,
+
+    /// Flush the output every N records so a reader on the other end of a pipe sees
+    /// data as it is decoded (0 flushes only at the end)
+    #[clap(long, default_value_t = 1000)]
+    flush_every: usize,
+
+    /// Decode only the first I-frame (keyframe) and write it as a single row
+    #[clap(long)]
+    first_i_frame: bool,
+
+    /// Write the CSV on a separate thread so slow storage does not stall decoding
+    #[clap(long)]
+    threaded: bool,
+
+    /// Write the log last keyframe segment first; each segment (an I-frame and its
+    /// P-frames) is still decoded and written forward
+    #[clap(long, conflicts_with = "first_i_frame")]
+    reverse: bool,
+
+    /// Export only the armed flight: drop the rows before the arming beep and after the
+    /// disarm, and start time at 0
+    #[clap(long)]
+    flight_only: bool,
+
+    /// Only decode these frame types into the output, e.g. `I,G` (others are still
+    /// parsed to stay aligned with the stream)
+    #[clap(long, value_delimiter = ',', value_parser = parse_frame_type)]
+    only_frame_types: Vec<char>,
+
+    /// Append a `time_synth` column computed from loopIteration, the loop period
+    /// (looptime × pid_process_denom) and the P ratio
+    #[clap(long)]
+    synthesize_time: bool,
+
+    /// Append a `time_s` column with the seconds since the first written row, continuing
+    /// across clock resets
+    #[clap(long)]
+    relative_time: bool,
+
+    /// Append a `valid` column, 0 for rows recovered after a corrupt frame, up to the
+    /// next keyframe, and for gap markers, 1 for rows decoded from clean frames
+    #[clap(long)]
+    validity_column: bool,
+
+    /// Prepend a `sample` column numbering the written rows 0, 1, 2, ...
+    #[clap(long)]
+    add_sample_index: bool,
+
+    /// Write time, the gyro axes and the throttle (rcCommand[3], or the mean motor
+    /// output) of every sample, ready for throttle-vs-noise FFT analysis
+    #[clap(long)]
+    noise_prep: bool,
+
+    /// Also write every motor output, motor[0] up to however many motors the log has,
+    /// and each as a percentage of the motorOutput range in a motor[N]_pct column
+    #[clap(long)]
+    motors: bool,
+
+    /// Write time, the setpoints and the feedforward term of each axis, for feedforward
+    /// tuning
+    #[clap(
+        long,
+        conflicts_with_all = ["fields", "all_fields", "field_indices", "columns_from", "noise_prep"]
+    )]
+    feedforward: bool,
+
+    /// Write time, the attitude angles and the magnetometer readings, for yaw and heading
+    /// analysis. With --physical-units the attitude is converted to degrees
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "fields",
+            "all_fields",
+            "field_indices",
+            "columns_from",
+            "noise_prep",
+            "feedforward",
+        ]
+    )]
+    heading: bool,
+
+    /// Write one row per roll, pitch and yaw axis, with an axis column and a column per
+    /// per-axis field (axisP, gyroADC, setpoint, ...) instead of one per field and axis
+    #[clap(long, alias = "group-by-axis", conflicts_with = "flatten_arrays")]
+    long_format: bool,
+
+    /// Where loopIteration skips frames, insert a row that is empty apart from a time
+    /// inside the gap, so plots show a break instead of a connecting line
+    #[clap(long, alias = "mark-gaps")]
+    gap_markers: bool,
+
+    /// Count a loopIteration skip as a gap only when it passes this threshold, either a
+    /// number of loop iterations past the next logged one, or the time between the
+    /// frames with a `us` suffix, e.g. `2000us`. Smaller skips are taken as jitter
+    #[clap(long, value_name = "THRESHOLD", value_parser = parse_gap_threshold)]
+    time_gap_threshold: Option<GapThreshold>,
+
+    /// Write exactly one row per loopIteration, dropping repeated iterations and adding
+    /// the latest slow-frame fields (flight mode, state and failsafe flags) as columns
+    #[clap(long, conflicts_with = "gap_markers")]
+    per_iteration: bool,
+
+    /// Leave out rows whose values, apart from time and loopIteration, repeat the row
+    /// before. The rows written are no longer evenly spaced in time
+    #[clap(long)]
+    only_changed_rows: bool,
+
+    /// Add the latest slow-frame fields (flight mode, state and failsafe flags) as
+    /// columns of every row, carried forward until the next slow frame
+    #[clap(long)]
+    include_slow: bool,
+
+    /// With --per-iteration or --include-slow, leave the slow-frame columns empty once no
+    /// slow frame has been logged for this many loop iterations, or for twice the S-frame
+    /// interval of the headers if no number is given
+    #[clap(
+        long,
+        value_name = "ITERATIONS",
+        num_args = 0..=1,
+        default_missing_value = "auto",
+        value_parser = parse_slow_max_age
+    )]
+    slow_max_age: Option<SlowMaxAge>,
+
+    /// Write flightModeFlags, stateFlags, failsafePhase and armingDisableFlags as flag
+    /// names, such as ANGLE_MODE|HORIZON_MODE or NO_GYRO|THROTTLE, instead of numbers
+    #[clap(long, visible_alias = "decode-flags")]
+    flag_names: bool,
+
+    /// Write only the rows whose time is at or after this many seconds
+    #[clap(long, value_name = "SECONDS")]
+    start: Option<f64>,
+
+    /// Write only the rows whose time is at or before this many seconds
+    #[clap(long, value_name = "SECONDS")]
+    end: Option<f64>,
+
+    /// Write at most one row per this many milliseconds: a frame only if its time is at
+    /// least this far past the last written row
+    #[clap(long, value_name = "MS")]
+    export_interval: Option<f64>,
+
+    /// Write only every Nth main frame, keeping its logged time and loopIteration. All
+    /// frames are still decoded
+    #[clap(long, value_name = "N", conflicts_with = "decimate_to")]
+    decimate: Option<usize>,
+
+    /// Like --decimate, with N chosen from the looptime and P interval headers to write
+    /// about this many rows per second
+    #[clap(long, value_name = "HZ")]
+    decimate_to: Option<f64>,
+
+    /// Stop decoding at the first event of this type: arm, inflight-adjustment,
+    /// logging-resume, disarm, flight-mode, failsafe or log-end
+    #[clap(long, value_name = "TYPE", value_parser = parse_event_name)]
+    stop_at_event: Option<String>,
+
+    /// Longest frame in bytes; longer frames are skipped as corrupt and decoding resumes
+    /// at the next keyframe
+    #[clap(long, value_name = "BYTES", default_value_t = bbe_reader::MAX_FRAME_SIZE)]
+    max_frame_size: usize,
+
+    /// Add an altitude column in cm: baro (baroAlt), gps (GPS_altitude of the latest GPS
+    /// frame) or merge (baroAlt where the session logs it, GPS_altitude otherwise)
+    #[clap(long, value_name = "SOURCE", value_parser = parse_altitude_source)]
+    altitude_source: Option<AltitudeSource>,
+
+    /// Print the vibration of each window of FRAMES main frames (default 256), the
+    /// magnitude of the AC component of accSmooth in raw accelerometer units
+    #[clap(
+        long,
+        value_name = "FRAMES",
+        num_args = 0..=1,
+        default_missing_value = "256"
+    )]
+    vibration: Option<usize>,
+
+    /// Decode only the Nth log of the file, counting from 1, under its own header row.
+    /// The firmware starts a new log every time it arms
+    #[clap(long, value_name = "N", conflicts_with = "byte_range")]
+    log: Option<usize>,
+
+    /// Write each log of the file to <input>.NN.csv, counting from 01, decoding the logs
+    /// in parallel. With --output, the files are written to that directory
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "log",
+            "byte_range",
+            "resume_from_offset",
+            "reference",
+            "schema_csv",
+            "polars_schema",
+            "gps_out",
+            "gpx",
+            "kml",
+            "with_preview",
+            "dump_frame_bytes",
+            "meta",
+        ]
+    )]
+    split_logs: bool,
+
+    /// Like --split-logs, but start a new file only where a log declares different
+    /// fields than the one before it, keeping logs with the same layout in one file
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "split_logs",
+            "log",
+            "byte_range",
+            "resume_from_offset",
+            "reference",
+            "schema_csv",
+            "polars_schema",
+            "gps_out",
+            "gpx",
+            "kml",
+            "with_preview",
+            "dump_frame_bytes",
+            "meta",
+        ]
+    )]
+    detect_and_split_on_format_change: bool,
+
+    /// Also write the GPS frames to <output>.gps.csv, next to the output and named after
+    /// it without its extension
+    #[clap(long)]
+    gps_out: bool,
+
+    /// Also write the GPS frames as a GPX track to FILE, one track segment per session
+    #[clap(long, value_name = "FILE")]
+    gpx: Option<std::path::PathBuf>,
+
+    /// Also write the GPS frames as a KML track to FILE, one placemark per session
+    #[clap(long, value_name = "FILE")]
+    kml: Option<std::path::PathBuf>,
+
+    /// Leave GPS fixes with fewer than N satellites out of --gps-out, --gpx and --kml
+    #[clap(long, value_name = "N")]
+    min_satellites: Option<u32>,
+
+    /// Report in --stats the fraction of intervals between frames that overran their
+    /// loop time budget, from the time and loopIteration fields and the looptime header
+    #[clap(long)]
+    detect_loop_overrun: bool,
+
+    /// Report in --stats the fraction of main frames each motor spent at or near the top
+    /// of the motorOutput range
+    #[clap(long)]
+    detect_clipping: bool,
+
+    /// Warn about motor values below the minthrottle or above the maxthrottle header,
+    /// or outside the motorOutput range for DShot and ProShot motors, which real motor
+    /// outputs never reach and so point to a decode error
+    #[clap(long)]
+    report_anomalies: bool,
+
+    /// Write the raw bytes of every decoded frame to this file, each as a little-endian
+    /// u32 length followed by the frame bytes, for analyzing the binary format
+    #[clap(long, value_name = "FILE")]
+    dump_frame_bytes: Option<std::path::PathBuf>,
+
+    /// Print how many field values each encoding stored and the bytes they took, to see
+    /// which encodings dominate a log
+    #[clap(long)]
+    encoding_stats: bool,
+
+    /// Write the name, encoding, signedness and predictor of every field of every frame
+    /// type to this CSV file, for spreadsheets and other tools that need the schema
+    #[clap(long, value_name = "FILE")]
+    dump_field_defs: Option<std::path::PathBuf>,
+
+    /// Also write a decimated preview to <output>.preview.csv (or .jsonl, .msgpack, .db)
+    /// next to the output in the same pass, for showing a quick overview before loading the full output
+    #[clap(long)]
+    with_preview: bool,
+
+    /// Rows of the full output per row of the preview
+    #[clap(
+        long,
+        value_name = "N",
+        default_value_t = 10,
+        requires = "with_preview"
+    )]
+    preview_decimate: usize,
+
+    /// Fields to write, comma-separated or repeated, instead of the default fields, e.g.
+    /// `--fields time,gyroADC[0],motor[0]`
+    #[clap(
+        long,
+        value_delimiter = ',',
+        value_name = "FIELDS",
+        conflicts_with_all = ["field_indices", "columns_from", "noise_prep"]
+    )]
+    fields: Vec<String>,
+
+    /// Write exactly the fields listed in `H Field I name`, in header order, whatever the
+    /// log holds
+    #[clap(
+        long,
+        alias = "decode-only-fields-present",
+        conflicts_with_all = ["fields", "field_indices", "columns_from", "noise_prep"]
+    )]
+    all_fields: bool,
+
+    /// Select fields by their 0-based position in `H Field I name` instead of the default
+    /// fields, e.g. `0,1,5,6`
+    #[clap(long, value_delimiter = ',', value_name = "INDICES")]
+    field_indices: Vec<usize>,
+
+    /// Write the columns of this reference log, in its order, leaving those the input
+    /// lacks empty, so logs from different firmwares share one schema
+    #[clap(long, value_name = "REFERENCE", conflicts_with_all = ["field_indices", "noise_prep"])]
+    columns_from: Option<std::path::PathBuf>,
+
+    /// Encoding assumed for fields past the end of a short `H Field X encoding` list
+    #[clap(long, default_value_t = bbe_reader::DEFAULT_ENCODING)]
+    default_encoding: u8,
+
+    /// Fail on `H Field X` name, signed, predictor and encoding lists of different
+    /// lengths, a sign of a header cut short, instead of warning and decoding with
+    /// defaults for the missing entries
+    #[clap(long)]
+    strict: bool,
+
+    /// Name array elements like `axisP[0]` as `axisP_0` in the CSV header
+    #[clap(long)]
+    flatten_arrays: bool,
+
+    /// CSV file of `source,target` rows renaming output columns from the field name in
+    /// the first column to the name in the second
+    #[clap(long)]
+    rename_map: Option<std::path::PathBuf>,
+
+    /// Keep every requested column even if the log lacks the field, leaving it empty,
+    /// so all logs give the same CSV schema
+    #[clap(long)]
+    include_empty_fields: bool,
+
+    /// Write the selected columns in the order the log's `H Field I name` header lists
+    /// them, as blackbox_decode does, instead of the order they were requested in
+    #[clap(long)]
+    preserve_order: bool,
+
+    /// Decode only this byte span of the binary section after the headers, e.g.
+    /// `1000:5000` or `1000:`. Decoding starts at the first keyframe in the span
+    #[clap(long, value_name = "START:END", value_parser = parse_byte_range)]
+    byte_range: Option<std::ops::Range<usize>>,
+
+    /// Decode from byte N of the input on, e.g. where an earlier run over a log that is
+    /// still being written stopped. Decoding resumes at the first keyframe from there
+    #[clap(long, value_name = "N", conflicts_with_all = ["byte_range", "log"])]
+    resume_from_offset: Option<usize>,
+
+    /// Write a checkpoint to <output>.checkpoint every N main frames, so a decode stopped
+    /// by a crash can pick up there with --resume. The output is then written in place
+    /// rather than appearing only once complete. CSV only, and not with the options
+    /// that carry state from one keyframe to the next
+    #[clap(
+        long,
+        value_name = "N",
+        conflicts_with_all = [
+            "input_list",
+            "threaded",
+            "reverse",
+            "flight_only",
+            "first_i_frame",
+            "progress_json",
+            "synthesize_time",
+            "relative_time",
+            "long_format",
+            "gap_markers",
+            "per_iteration",
+            "only_changed_rows",
+            "include_slow",
+            "export_interval",
+            "decimate",
+            "decimate_to",
+            "altitude_source",
+            "vibration",
+            "log",
+            "split_logs",
+            "detect_and_split_on_format_change",
+            "gps_out",
+            "gpx",
+            "kml",
+            "dump_frame_bytes",
+            "with_preview",
+            "byte_range",
+            "resume_from_offset",
+        ]
+    )]
+    checkpoint_interval: Option<usize>,
+
+    /// Pick up a decode stopped by a crash at the last checkpoint it wrote, cutting the
+    /// output back to the rows written before it and appending the rest. Pass the same
+    /// options as the stopped decode
+    #[clap(long, requires = "checkpoint_interval")]
+    resume: bool,
+
+    /// Print decode statistics and the min, max, mean and standard deviation of each
+    /// numeric output column after writing the CSV
+    #[clap(long)]
+    stats: bool,
+
+    /// Write only the CSV header row for the selected fields to <input>.csv, without
+    /// decoding any frames, to create files that rows from other logs are appended to
+    #[clap(long, conflicts_with_all = ["format", "reference", "with_preview"])]
+    schema_csv: bool,
+
+    /// Also write a Polars schema of the CSV to this JSON file, declaring each column's
+    /// dtype (Int64, Float64 or String) for `read_csv` instead of leaving it to inference
+    #[clap(long, value_name = "FILE", conflicts_with = "long_format")]
+    polars_schema: Option<std::path::PathBuf>,
+
+    /// Also write <output>.meta.json next to the output with the log's identifying
+    /// headers and blackbox device, its gyro, accelerometer and battery scales and the
+    /// decode statistics, including frame counts per type, missing frames and the logged
+    /// time in seconds
+    #[clap(long)]
+    meta: bool,
+
+    /// Print every event (arm, disarm, flight mode changes, log end, ...) with its time to
+    /// stderr after writing the CSV
+    #[clap(long, alias = "events")]
+    summarize_events: bool,
+
+    /// After writing the CSV, compare it against this known-good CSV and report the
+    /// first mismatching cell, exiting with status 1 if there is one
+    #[clap(long)]
+    reference: Option<std::path::PathBuf>,
+
+    /// Write these fields as integers, or every column if no fields are given. Scaled
+    /// values are rounded to the nearest integer, with halves rounded away from zero
+    #[clap(long, num_args = 0.., value_delimiter = ',', value_name = "FIELDS")]
+    force_int: Option<Vec<String>>,
+
+    /// Write these fields as floats with a decimal point, or every column if no fields
+    /// are given. A field listed here overrides a global --force-int and vice versa
+    #[clap(long, num_args = 0.., value_delimiter = ',', value_name = "FIELDS")]
+    force_float: Option<Vec<String>>,
+
+    /// Digits after the decimal point for float values: a number for every column and/or
+    /// NAME=DIGITS per field, e.g. `3,time=9,gyroADC[0]=2`
+    #[clap(long, value_delimiter = ',', value_parser = parse_precision)]
+    precision: Vec<Precision>,
+
+    /// Output format: `csv`, `json` for JSON Lines written to <input>.jsonl, one object
+    /// per row after a header object, `msgpack` (built with the `msgpack` feature) for a
+    /// MessagePack array of row maps written to <input>.msgpack, or `sqlite` (built with
+    /// the `sqlite` feature) for an SQLite database with `frames` and `headers` tables
+    /// written to <input>.db
+    #[clap(long, default_value = "csv", value_parser = parse_output_format)]
+    format: OutputFormat,
+
+    /// Pretty-print each JSON object over several lines, for reading a few frames by eye
+    #[clap(long, alias = "pretty-json")]
+    pretty: bool,
 }
 
-/// Represents a single field definition parsed from the header.
+/// Units the values are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Units {
+    Raw,
+    Physical,
+}
+
+/// Parses a --units value.
+fn parse_units(value: &str) -> Result<Units, String> {
+    match value {
+        "raw" => Ok(Units::Raw),
+        "physical" => Ok(Units::Physical),
+        _ => Err(format!("unknown units `{}`, expected raw or physical", value)),
+    }
+}
+
+/// Parses a --format value.
+fn parse_output_format(value: &str) -> Result<OutputFormat, String> {
+    match value {
+        "csv" => Ok(OutputFormat::Csv),
+        "json" => Ok(OutputFormat::JsonLines),
+        #[cfg(feature = "msgpack")]
+        "msgpack" => Ok(OutputFormat::MessagePack),
+        #[cfg(not(feature = "msgpack"))]
+        "msgpack" => Err("this build has no MessagePack support (feature `msgpack`)".to_string()),
+        #[cfg(feature = "sqlite")]
+        "sqlite" => Ok(OutputFormat::Sqlite),
+        #[cfg(not(feature = "sqlite"))]
+        "sqlite" => Err("this build has no SQLite support (feature `sqlite`)".to_string()),
+        _ => Err(format!(
+            "unknown format `{}`, expected csv, json, msgpack or sqlite",
+            value
+        )),
+    }
+}
+
+/// Parses an --altitude-source value.
+fn parse_altitude_source(value: &str) -> Result<AltitudeSource, String> {
+    match value {
+        "baro" => Ok(AltitudeSource::Baro),
+        "gps" => Ok(AltitudeSource::Gps),
+        "merge" => Ok(AltitudeSource::Merge),
+        _ => Err(format!(
+            "unknown altitude source `{}`, expected baro, gps or merge",
+            value
+        )),
+    }
+}
+
+/// Parses a --slow-max-age value, a number of loop iterations or `auto`.
+fn parse_slow_max_age(value: &str) -> Result<SlowMaxAge, String> {
+    match value {
+        "auto" => Ok(SlowMaxAge::Auto),
+        _ => value.parse().map(SlowMaxAge::Iterations).map_err(|_| {
+            format!(
+                "invalid slow frame age {:?}, expected a number of loop iterations",
+                value
+            )
+        }),
+    }
+}
+
+/// Parses a --time-gap-threshold value, a number of loop iterations or µs with a `us`
+/// suffix.
+fn parse_gap_threshold(value: &str) -> Result<GapThreshold, String> {
+    let threshold = match value.strip_suffix("us") {
+        Some(micros) => micros.trim().parse().map(GapThreshold::Micros),
+        None => value.parse().map(GapThreshold::Iterations),
+    };
+    threshold.map_err(|_| {
+        format!(
+            "invalid gap threshold {:?}, expected loop iterations or µs like 2000us",
+            value
+        )
+    })
+}
+
+/// Reads a --rename-map file of `source,target` rows, skipping a `source,target` header.
+fn read_rename_map(path: &std::path::Path) -> Result<HashMap<String, String>, BblError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_path(path)?;
+    let mut renames = HashMap::new();
+    for (index, record) in reader.records().enumerate() {
+        let record = record?;
+        let [source, target] = [0, 1].map(|column| record.get(column).unwrap_or_default());
+        if index == 0 && (source, target) == ("source", "target") {
+            continue;
+        }
+        if record.len() != 2 || source.is_empty() || target.is_empty() {
+            let message = format!(
+                "{}: row {} is not `source,target`",
+                path.display(),
+                index + 1
+            );
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+        }
+        renames.insert(source.to_string(), target.to_string());
+    }
+    Ok(renames)
+}
+
+/// Reads the inputs of an --input-list file, skipping blank lines and `#` comments.
+fn read_input_list(path: &std::path::Path) -> io::Result<Vec<String>> {
+    let list = std::fs::read_to_string(path)?;
+    Ok(list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Parses a --byte-range value, `START:END` with either end optional.
+fn parse_byte_range(value: &str) -> Result<std::ops::Range<usize>, String> {
+    let invalid = || format!("invalid byte range {:?}, expected START:END", value);
+    let (start, end) = value.split_once(':').ok_or_else(invalid)?;
+    let start = match start.trim() {
+        "" => 0,
+        start => start.parse().map_err(|_| invalid())?,
+    };
+    let end = match end.trim() {
+        "" => usize::MAX,
+        end => end.parse().map_err(|_| invalid())?,
+    };
+    if start > end {
+        return Err(invalid());
+    }
+    Ok(start..end)
+}
+
+/// One entry of the --precision list.
 #[derive(Debug, Clone)]
-struct FieldDefinition {
-    name: String,
-    encoding: u8,
-    signed: bool,
-    predictor: u8,
+enum Precision {
+    All(usize),
+    Field(String, usize),
 }
 
-fn main() -> io::Result<()> {
+/// Parses a --precision entry, either `DIGITS` or `NAME=DIGITS`.
+fn parse_precision(value: &str) -> Result<Precision, String> {
+    let digits = |digits: &str| {
+        digits
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("invalid precision {:?}, expected a number of digits", value))
+    };
+    match value.rsplit_once('=') {
+        Some((name, precision)) => Ok(Precision::Field(
+            name.trim().to_string(),
+            digits(precision)?,
+        )),
+        None => Ok(Precision::All(digits(value)?)),
+    }
+}
+
+/// Converts a time in seconds given on the command line to the log's µs.
+fn seconds_to_micros(seconds: f64) -> i64 {
+    (seconds * 1_000_000.0).round() as i64
+}
+
+/// Parses a frame type letter given on the command line.
+fn parse_frame_type(value: &str) -> Result<char, String> {
+    let mut chars = value.trim().chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if FRAME_TYPES.contains(&c.to_ascii_uppercase()) => {
+            Ok(c.to_ascii_uppercase())
+        }
+        _ => Err(format!(
+            "unknown frame type {:?}, expected one of I, P, S, G, H, E",
+            value
+        )),
+    }
+}
+
+/// Parses an event type name given on the command line.
+fn parse_event_name(value: &str) -> Result<String, String> {
+    let name = value.trim().to_ascii_lowercase();
+    if EVENT_NAMES.contains(&name.as_str()) {
+        Ok(name)
+    } else {
+        Err(format!(
+            "unknown event type {:?}, expected one of {}",
+            value,
+            EVENT_NAMES.join(", ")
+        ))
+    }
+}
+
+fn main() {
     let args = Args::parse();
+    if args.self_test {
+        match bbe_reader::self_test() {
+            Ok(None) => println!("Self-test passed"),
+            Ok(Some(mismatch)) => {
+                eprintln!("Self-test failed: {}", mismatch);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Self-test failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(count) = args.fuzz_vlq {
+        // A new seed every run, printed so a failure can be reproduced
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        match bbe_reader::fuzz_vlq(count, seed) {
+            None => println!("VLQ round trip passed for {} values (seed {})", count, seed),
+            Some(mismatch) => {
+                eprintln!("VLQ round trip failed (seed {}): {}", seed, mismatch);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    QUIET.store(args.quiet, Ordering::Relaxed);
+    if let Err(e) = run(&args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Decodes the input, or each input of the list, given on the command line.
+fn run(args: &Args) -> Result<(), BblError> {
+    let (force_type, force_types) = parse_column_types(args);
+    let mut precision = None;
+    let mut field_precision = HashMap::new();
+    for entry in &args.precision {
+        match entry {
+            Precision::All(digits) => precision = Some(*digits),
+            Precision::Field(name, digits) => {
+                field_precision.insert(name.clone(), *digits);
+            }
+        }
+    }
+    if let (Some(start), Some(end)) = (args.start, args.end) {
+        if end < start {
+            Args::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("--end {} is before --start {}", end, start),
+                )
+                .exit();
+        }
+    }
+    if args.decimate == Some(0) {
+        Args::command()
+            .error(ErrorKind::ValueValidation, "--decimate needs a stride of at least 1")
+            .exit();
+    }
+    if args.decimate_to.is_some_and(|rate| rate.is_nan() || rate <= 0.0) {
+        Args::command()
+            .error(ErrorKind::ValueValidation, "--decimate-to needs a positive rate")
+            .exit();
+    }
+    if args.slow_max_age.is_some() && !args.per_iteration && !args.include_slow {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--slow-max-age needs --per-iteration or --include-slow",
+            )
+            .exit();
+    }
+    let physical_units = args.physical_units || args.units == Units::Physical;
+    if args.keep_raw && !physical_units && !args.rc_as_us {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--keep-raw needs --physical-units, --units physical or --rc-as-us",
+            )
+            .exit();
+    }
+    let mut options = DecodeOptions {
+        units_header: args.units_header,
+        physical_units,
+        keep_raw: args.keep_raw,
+        rc_as_us: args.rc_as_us,
+        progress_json: args.progress_json,
+        flush_every: args.flush_every,
+        first_i_frame: args.first_i_frame,
+        threaded: args.threaded,
+        reverse: args.reverse,
+        flight_only: args.flight_only,
+        frame_types: args.only_frame_types.clone(),
+        synthesize_time: args.synthesize_time,
+        relative_time: args.relative_time,
+        validity_column: args.validity_column,
+        sample_index: args.add_sample_index,
+        gap_markers: args.gap_markers,
+        per_iteration: args.per_iteration,
+        only_changed_rows: args.only_changed_rows,
+        include_slow: args.include_slow,
+        slow_max_age: args.slow_max_age,
+        gap_threshold: args.time_gap_threshold,
+        flag_names: args.flag_names,
+        start_time: args.start.map(seconds_to_micros),
+        end_time: args.end.map(seconds_to_micros),
+        export_interval: args
+            .export_interval
+            .map(|millis| seconds_to_micros(millis / 1000.0)),
+        decimate: args.decimate,
+        decimate_to: args.decimate_to,
+        stop_at_event: args.stop_at_event.clone(),
+        max_frame_size: args.max_frame_size,
+        altitude_source: args.altitude_source,
+        vibration_window: args.vibration,
+        session: args.log,
+        // --stats compares the logged loop period with the declared one
+        detect_loop_overrun: args.detect_loop_overrun || args.stats,
+        detect_clipping: args.detect_clipping,
+        report_anomalies: args.report_anomalies,
+        dump_frame_bytes: args.dump_frame_bytes.clone(),
+        encoding_stats: args.encoding_stats,
+        column_stats: args.stats,
+        preview_decimate: args.preview_decimate,
+        flatten_arrays: args.flatten_arrays,
+        renames: match &args.rename_map {
+            Some(path) => read_rename_map(path)?,
+            None => HashMap::new(),
+        },
+        include_empty_fields: args.include_empty_fields,
+        preserve_order: args.preserve_order,
+        byte_range: args.byte_range.clone(),
+        resume_offset: args.resume_from_offset,
+        only_fields_present: args.all_fields,
+        noise_prep: args.noise_prep,
+        motors: args.motors,
+        long_format: args.long_format,
+        header: HeaderOptions {
+            default_encoding: args.default_encoding,
+            strict: args.strict,
+        },
+        force_type,
+        force_types,
+        precision,
+        field_precision,
+        format: args.format,
+        pretty_json: args.pretty,
+        ..DecodeOptions::default()
+    };
+    if !args.fields.is_empty() {
+        options.fields = args.fields.clone();
+    }
+    if args.feedforward {
+        options.fields = FEEDFORWARD_FIELDS.iter().map(|name| name.to_string()).collect();
+    }
+    if args.heading {
+        options.fields = HEADING_FIELDS.iter().map(|name| name.to_string()).collect();
+    }
+    if let Some(path) = &args.columns_from {
+        let reference =
+            BblLog::from_reader_with(&mut BufReader::new(File::open(path)?), &options.header)?;
+        options.fields = reference.main_field_names();
+        options.include_empty_fields = true;
+    }
+    if args.reference.is_some() && args.format != OutputFormat::Csv {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--reference compares CSV output only",
+            )
+            .exit();
+    }
+    if args.pretty && args.format != OutputFormat::JsonLines {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--pretty applies to --format json only",
+            )
+            .exit();
+    }
+    if args.polars_schema.is_some() && args.format != OutputFormat::Csv {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--polars-schema describes CSV output only",
+            )
+            .exit();
+    }
+    if args.checkpoint_interval.is_some() && args.format != OutputFormat::Csv {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--checkpoint-interval resumes CSV output only",
+            )
+            .exit();
+    }
+
+    let single_output = args
+        .output
+        .as_deref()
+        .is_some_and(|output| !output.contains("{stem}") && !is_directory(output));
+    if args.input_list.is_some() && single_output {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--output with --input-list needs a directory or a {stem} template",
+            )
+            .exit();
+    }
 
-    // Define the desired Field I names
-    let desired_fields = [
-        "loopIteration",
-        "time",
-        "axisP[0]",
-        "axisP[1]",
-        "axisP[2]",
-        "axisI[0]",
-        "axisI[1]",
-        "axisI[2]",
-        "axisD[0]",
-        "axisD[1]",
-        "axisF[0]",
-        "axisF[1]",
-        "axisF[2]",
-    ];
-
-    // Determine output CSV file name
-    let input_path = std::path::Path::new(&args.input);
-    let file_stem = input_path.file_stem().unwrap().to_str().unwrap();
-    let csv_file_name = format!("{}.csv", file_stem);
-
-    // Create CSV writer
-    let mut writer = Writer::from_path(csv_file_name)?;
-
-    // Open the BBL file
-    let file = File::open(&args.input)?;
-    let mut reader = BufReader::new(file);
-
-    // Read all plaintext headers dynamically
-    let mut headers = Vec::new();
-    loop {
-        let mut header_line = Vec::new();
-        let bytes_read = reader.read_until(b'\n', &mut header_line)?;
-
-        if bytes_read == 0 {
-            // End of file
-            break;
-        }
-
-        // Check if the line is plaintext (ASCII)
-        if header_line.iter().all(|&byte| byte.is_ascii()) {
-            headers.push(String::from_utf8_lossy(&header_line).trim().to_string());
+    let Some(list) = &args.input_list else {
+        let input = args.input.as_deref().unwrap_or_default();
+        return decode_input(args, input, options);
+    };
+    let mut failed = 0;
+    for input in read_input_list(list)? {
+        note!("Decoding {}", input);
+        if let Err(e) = decode_input(args, &input, options.clone()) {
+            eprintln!("Error: {}: {}", input, e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        eprintln!("{} of the listed inputs failed to decode", failed);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Decodes one input, writing the output to the working directory.
+fn decode_input(args: &Args, input: &str, mut options: DecodeOptions) -> Result<(), BblError> {
+    // The log is read from stdin and, without --output, the output written to stdout,
+    // with side files named after `stdin`
+    let from_stdin = input == "-";
+    let to_stdout = match args.output.as_deref() {
+        Some(output) => output == "-",
+        None => from_stdin,
+    };
+    OUTPUT_ON_STDOUT.store(to_stdout, Ordering::Relaxed);
+    if to_stdout && args.reference.is_some() {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--reference cannot reread output written to stdout",
+            )
+            .exit();
+    }
+    if to_stdout && args.checkpoint_interval.is_some() {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--checkpoint-interval cannot resume output written to stdout",
+            )
+            .exit();
+    }
+
+    // Determine output file name (ignoring any URL query string). Side files are named
+    // after an --output file when the input has no usable name.
+    let stem = match from_stdin {
+        true => Some("stdin".to_string()),
+        false => bbe_reader::input_stem(input),
+    };
+    let output_file = args
+        .output
+        .as_deref()
+        .filter(|output| *output != "-" && !is_directory(output) && !output.contains("{stem}"));
+    let Some(file_stem) = stem.or_else(|| output_file.and_then(bbe_reader::input_stem)) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{:?} has no file name to name the output after, pass --output FILE",
+                input
+            ),
+        )
+        .into());
+    };
+    let extension = match args.format {
+        OutputFormat::Csv => "csv",
+        OutputFormat::JsonLines => "jsonl",
+        #[cfg(feature = "msgpack")]
+        OutputFormat::MessagePack => "msgpack",
+        #[cfg(feature = "sqlite")]
+        OutputFormat::Sqlite => "db",
+    };
+    let stem_file_name = format!("{}.{}", file_stem, extension);
+    let output_file_name = match args.output.as_deref() {
+        Some(output) if is_directory(output) => std::path::Path::new(output)
+            .join(&stem_file_name)
+            .to_string_lossy()
+            .into_owned(),
+        Some(output) => output.replace("{stem}", &file_stem),
+        None => stem_file_name.clone(),
+    };
+    if args.split_logs || args.detect_and_split_on_format_change {
+        let output = args.output.as_deref();
+        if output.is_some_and(|output| !is_directory(output)) {
+            let flag = match args.split_logs {
+                true => "--split-logs",
+                false => "--detect-and-split-on-format-change",
+            };
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!("--output with {} needs a directory", flag),
+                )
+                .exit();
+        }
+        return decode_split(args, input, &file_stem, extension, &options);
+    }
+    // Side files go next to the output and share its stem, or are named after the
+    // input in the working directory when the output goes to stdout
+    let side_file_base = match to_stdout {
+        true => stem_file_name.clone(),
+        false => output_file_name.clone(),
+    };
+    if args.with_preview {
+        let suffix = format!("preview.{}", extension);
+        options.preview = Some(side_file_name(&side_file_base, &suffix).into());
+    }
+
+    // Open the BBL file or URL
+    let source: Box<dyn Read> = match from_stdin {
+        true => Box::new(io::stdin()),
+        false => open_input(input)?,
+    };
+    let mut reader = BufReader::new(source);
+
+    // Read all plaintext headers
+    let log = BblLog::from_reader_with(&mut reader, &options.header)?;
+    if log.preamble_len > 0 {
+        note!("Skipped {} byte preamble before headers", log.preamble_len);
+    }
+
+    // Print the headers and field definitions to stderr, keeping stdout for the output
+    if args.verbose >= 1 {
+        eprintln!("Headers:");
+        for (index, header) in log.headers.iter().enumerate() {
+            eprintln!("Header {}: {}", index + 1, header);
+        }
+        if let Some(scale) = log
+            .header_value("gyro_scale")
+            .and_then(bbe_reader::parse_gyro_scale)
+        {
+            eprintln!("Gyro scale: {}", scale);
+        }
+    }
+    if args.verbose >= 2 {
+        eprintln!("Internal Column Definitions:");
+        for (i, field) in log.main_fields().iter().enumerate() {
+            eprintln!(
+                "Column {}: Name=\"{}\", Signed={}, Predictor={}, Encoding={}",
+                i + 1,
+                field.name,
+                field.signed,
+                field.predictor,
+                field.encoding
+            );
+        }
+    }
+
+    if !args.field_indices.is_empty() {
+        options.fields = fields_by_index(&log, &args.field_indices);
+    }
+    for field in &args.fields {
+        if !log.main_fields().iter().any(|f| &f.name == field) {
+            eprintln!("Warning: the log has no field {}", field);
+        }
+    }
+    if args.heading {
+        let has_heading = log
+            .main_fields()
+            .iter()
+            .any(|f| HEADING_FIELDS[1..].contains(&f.name.as_str()));
+        if !has_heading {
+            note!("No attitude or magADC fields in the log, writing only the time");
+        }
+    }
+
+    if args.gps_out {
+        if log.layouts.contains_key(&b'G') {
+            options.gps_out = Some(side_file_name(&side_file_base, "gps.csv").into());
+        } else {
+            note!("No GPS headers in the log, skipping --gps-out");
+        }
+    }
+
+    if let Some(path) = &args.gpx {
+        if log.layouts.contains_key(&b'G') {
+            options.gpx_out = Some(path.clone());
+        } else {
+            note!("No GPS headers in the log, skipping --gpx");
+        }
+    }
+
+    if let Some(path) = &args.kml {
+        if log.layouts.contains_key(&b'G') {
+            options.kml_out = Some(path.clone());
         } else {
-            // Stop reading headers when binary data is encountered
-            break;
+            note!("No GPS headers in the log, skipping --kml");
         }
     }
+    options.min_satellites = args.min_satellites;
 
-    // Print all headers to console
-    println!("Headers:");
-    for (index, header) in headers.iter().enumerate() {
-        println!("Header {}: {}", index + 1, header);
+    if let Some(path) = &args.dump_field_defs {
+        bbe_reader::write_field_defs_csv(&log, File::create(path)?)?;
+        note!("Wrote the field definitions to {}", path.display());
     }
 
-    // Parse field definitions from headers
-    let field_definitions = parse_field_definitions(&headers);
+    // The output file only appears once it is complete, unless it is checkpointed, when
+    // a resumed decode appends to what the stopped one wrote
+    let checkpoint_path = format!("{}.checkpoint", output_file_name);
+    if let Some(interval) = args.checkpoint_interval {
+        options.checkpoint_out = Some(checkpoint_path.clone().into());
+        options.checkpoint_interval = interval;
+    }
+    let mut checkpointed_file = match args.checkpoint_interval {
+        Some(_) if args.resume => {
+            let checkpoint = Checkpoint::read(&checkpoint_path)?;
+            let mut file = File::options()
+                .read(true)
+                .write(true)
+                .open(&output_file_name)?;
+            checkpoint.truncate_output(&mut file, 1 + args.units_header as usize)?;
+            note!(
+                "Resuming at byte {} after {} rows",
+                checkpoint.offset,
+                checkpoint.rows_written
+            );
+            options.resume = Some(checkpoint);
+            Some(file)
+        }
+        Some(_) => Some(File::create(&output_file_name)?),
+        None => None,
+    };
+    let mut output_file = match to_stdout || checkpointed_file.is_some() {
+        true => None,
+        false => Some(AtomicFile::create(&output_file_name)?),
+    };
+    let output: Box<dyn io::Write + Send> = match (&mut output_file, &mut checkpointed_file) {
+        (Some(file), _) => Box::new(file),
+        (None, Some(file)) => Box::new(file),
+        (None, None) => Box::new(io::stdout()),
+    };
+    let output_name = match to_stdout {
+        true => "stdout",
+        false => &output_file_name,
+    };
 
-    // Print internal column definitions
-    println!("Internal Column Definitions:");
-    for (i, field) in field_definitions.iter().enumerate() {
-        println!(
-            "Column {}: Name=\"{}\", Signed={}, Predictor={}, Encoding={}",
-            i + 1,
-            field.name,
-            field.signed,
-            field.predictor,
-            field.encoding
-        );
+    if let Some(path) = &args.polars_schema {
+        let schema = io::BufWriter::new(File::create(path)?);
+        bbe_reader::write_polars_schema(&log, schema, &options)?;
+    }
+    if args.schema_csv {
+        bbe_reader::write_schema_csv(&log, output, &options)?;
+        output_file.map(AtomicFile::commit).transpose()?;
+        note!("Wrote the header row to {}", output_name);
+        return Ok(());
     }
 
-    // Create a map of field names to their definitions
-    let field_map: HashMap<String, FieldDefinition> = field_definitions
-        .into_iter()
-        .map(|f| (f.name.clone(), f))
-        .collect();
+    // Decode the binary data after the headers as it is read and write the output
+    let stats = bbe_reader::stream_csv(&log, reader, output, &options)?;
+    output_file.map(AtomicFile::commit).transpose()?;
+    if let Some(file) = checkpointed_file {
+        file.sync_all()?;
+        // The output is complete, there is nothing left to resume
+        match std::fs::remove_file(&checkpoint_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
 
-    // Write CSV header (only desired Field I data)
-    let mut csv_header: Vec<String> = Vec::new();
+    if let Some(stopped_at) = &stats.stopped_at {
+        note!("Stopped at {}", stopped_at);
+    }
+    note!(
+        "Decoded {} frames, {} gaps totaling {:.1}% missing",
+        stats.main_frames(),
+        stats.gaps,
+        stats.missing_fraction().unwrap_or(0.0) * 100.0
+    );
+    warn_missing_frames(&log, &stats);
 
-    for field_name in &desired_fields {
-        if field_map.contains_key(*field_name) {
-            csv_header.push(field_name.to_string());
+    if args.report_anomalies {
+        report_motor_anomalies(&log, &stats);
+    }
+    if args.stats {
+        print_stats(&log, &stats, args.detect_loop_overrun, args.detect_clipping);
+        print_column_stats(&stats);
+    }
+    if args.encoding_stats {
+        print_encoding_stats(&stats);
+    }
+    if let Some(window) = args.vibration {
+        if stats.vibration.is_empty() {
+            status!(
+                "Vibration: unknown (needs accSmooth[0..3] and at least {} main frames)",
+                window
+            );
+        } else {
+            status!("Vibration per {} main frames:", window);
+            for (index, vibration) in stats.vibration.iter().enumerate() {
+                status!("  window {}: {:.1}", index + 1, vibration);
+            }
         }
     }
-    writer.write_record(&csv_header)?;
 
-    // Process the binary data after the headers
-    let mut buffer = Vec::new();
-    reader.read_to_end(&mut buffer)?;
+    if args.meta {
+        let meta = File::create(side_file_name(&side_file_base, "meta.json"))?;
+        bbe_reader::write_metadata_json(&log, &stats, io::BufWriter::new(meta))?;
+    }
 
-    // Decode binary data and write to CSV (only desired Field I data)
-    decode_binary_data(&buffer, &field_map, &mut writer, &desired_fields, &headers)?;
+    if args.summarize_events {
+        eprintln!("Events:");
+        for event in &stats.events {
+            eprintln!("  {}", event);
+        }
+    }
 
-    writer.flush()?; // Ensure all data is written to the file
+    if let Some(reference) = &args.reference {
+        let decoded = File::open(&output_file_name)?;
+        match bbe_reader::first_mismatch(decoded, File::open(reference)?)? {
+            Some(mismatch) => {
+                eprintln!(
+                    "{} differs from {}: {}",
+                    output_file_name,
+                    reference.display(),
+                    mismatch
+                );
+                std::process::exit(1);
+            }
+            None => note!("{} matches {}", output_file_name, reference.display()),
+        }
+    }
 
     Ok(())
 }
 
-/// Parses field definitions from the plaintext headers.
-fn parse_field_definitions(headers: &[String]) -> Vec<FieldDefinition> {
-    let mut field_names = Vec::new();
-    let mut encoding_types = Vec::new();
-    let mut signed_flags = Vec::new();
-    let mut predictor_types = Vec::new();
-
-    for header in headers {
-        if header.starts_with("H Field I name:") {
-            field_names = header["H Field I name:".len()..]
-                .split(',')
-                .map(|s| s.trim().to_string())
-                .collect();
-        } else if header.starts_with("H Field I encoding:") {
-            encoding_types = header["H Field I encoding:".len()..]
-                .split(',')
-                .map(|s| s.trim().parse::<u8>().unwrap_or(0))
-                .collect();
-        } else if header.starts_with("H Field I signed:") {
-            signed_flags = header["H Field I signed:".len()..]
-                .split(',')
-                .map(|s| s.trim() == "1")
-                .collect();
-        } else if header.starts_with("H Field I predictor:") {
-            predictor_types = header["H Field I predictor:".len()..]
-                .split(',')
-                .map(|s| s.trim().parse::<u8>().unwrap_or(0))
-                .collect();
-        }
-    }
-
-    // Combine parsed fields into a list of `FieldDefinition`
-    field_names
-        .into_iter()
-        .enumerate()
-        .map(|(i, name)| FieldDefinition {
-            name,
-            encoding: *encoding_types.get(i).unwrap_or(&0),
-            signed: *signed_flags.get(i).unwrap_or(&false),
-            predictor: *predictor_types.get(i).unwrap_or(&0),
-        })
-        .collect()
-}
+/// Decodes each log of an input on a thread of its own, writing log N to
+/// <stem>.NN.<extension>. With --detect-and-split-on-format-change, consecutive logs with
+/// the same field layout are decoded together into one file.
+fn decode_split(
+    args: &Args,
+    input: &str,
+    file_stem: &str,
+    extension: &str,
+    options: &DecodeOptions,
+) -> Result<(), BblError> {
+    let mut data = Vec::new();
+    match input {
+        "-" => io::stdin().read_to_end(&mut data)?,
+        _ => open_input(input)?.read_to_end(&mut data)?,
+    };
+    let (sessions, part) = match args.detect_and_split_on_format_change {
+        true => (
+            bbe_reader::split_on_layout_change(&data, &options.header),
+            "layout",
+        ),
+        false => (bbe_reader::split_sessions(&data), "log"),
+    };
+    if sessions.is_empty() {
+        return Err(BblError::NotABlackboxLog);
+    }
 
-/// Decodes binary data and writes selected fields to CSV.
-fn decode_binary_data(
-    data: &[u8],
-    field_map: &HashMap<String, FieldDefinition>,
-    writer: &mut Writer<File>,
-    desired_fields: &[&str],
-    headers: &[String],
-) -> io::Result<()> {
-    let mut cursor = 0;
-    let h_field_i_name_line = headers
-        .iter()
-        .find(|header| header.starts_with("H Field I name:"))
-        .expect("H Field I name: header not found");
-    let field_names: Vec<&str> = h_field_i_name_line["H Field I name:".len()..]
-        .split(',')
-        .map(|s| s.trim())
-        .collect();
-    let h_field_i_encoding_line = headers
-        .iter()
-        .find(|header| header.starts_with("H Field I encoding:"))
-        .expect("H Field I encoding: header not found");
-    let encoding_types: Vec<&str> = h_field_i_encoding_line["H Field I encoding:".len()..]
-        .split(',')
-        .map(|s| s.trim())
-        .collect();
-    let h_field_i_signed_line = headers
-        .iter()
-        .find(|header| header.starts_with("H Field I signed:"))
-        .expect("H Field I signed: header not found");
-    let signed_types: Vec<&str> = h_field_i_signed_line["H Field I signed:".len()..]
-        .split(',')
-        .map(|s| s.trim())
-        .collect();
-    let h_field_i_predictor_line = headers
-        .iter()
-        .find(|header| header.starts_with("H Field I predictor:"))
-        .expect("H Field I predictor: header not found");
-    let predictor_types: Vec<&str> = h_field_i_predictor_line["H Field I predictor:".len()..]
-        .split(',')
-        .map(|s| s.trim())
-        .collect();
-
-    while cursor < data.len() {
-        let mut record: Vec<String> = Vec::new();
-        let mut valid_record = true;
-
-        for field_name in desired_fields {
-            let field_index = field_names.iter().position(|&r| r == *field_name);
-
-            if let Some(index) = field_index {
-                let encoding: u8 = encoding_types[index].parse().unwrap_or(0);
-                let signed: bool = signed_types[index] == "1";
-                let predictor: u8 = predictor_types[index].parse().unwrap_or(0);
-
-                if let Some(_field) = field_map.get(*field_name) {
-                    // Check if there is enough data before reading
-                    let bytes_needed = match encoding {
-                        0 | 1 => 1, // VLQ encoding needs at least 1 byte
-                        _ => 1,     // other encodings also need at least 1 byte
-                    };
-
-                    if cursor + bytes_needed > data.len() {
-                        valid_record = false;
-                        break;
+    let directory = args.output.as_deref().map(std::path::Path::new);
+    let results: Vec<Result<_, BblError>> = std::thread::scope(|scope| {
+        let decoders: Vec<_> = sessions
+            .iter()
+            .enumerate()
+            .map(|(index, &session)| {
+                let name = format!("{}.{:02}.{}", file_stem, index + 1, extension);
+                let path = match directory {
+                    Some(directory) => directory.join(name),
+                    None => name.into(),
+                };
+                scope.spawn(move || {
+                    let log = BblLog::from_bytes_with(session, &options.header)?;
+                    let mut options = options.clone();
+                    if !args.field_indices.is_empty() {
+                        options.fields = fields_by_index(&log, &args.field_indices);
                     }
+                    let mut output = AtomicFile::create(&path)?;
+                    let data = &session[log.data_offset..];
+                    let stats = bbe_reader::stream_csv(&log, data, &mut output, &options)?;
+                    output.commit()?;
+                    Ok((path, stats))
+                })
+            })
+            .collect();
+        decoders
+            .into_iter()
+            .map(|decoder| decoder.join().expect("decoder thread panicked"))
+            .collect()
+    });
 
-                    let value = match encoding {
-                        0 => {
-                            let val = read_signed_vlq(data, &mut cursor);
-                            if signed {
-                                val
-                            } else {
-                                val.abs()
-                            }
-                        }
-                        1 => {
-                            let val = read_unsigned_vlq(data, &mut cursor) as i32;
-                            if signed {
-                                val
-                            } else {
-                                val.abs()
-                            }
-                        }
-                        _ => {
-                            valid_record = false;
-                            break;
-                        }
-                    };
-                    record.push(value.to_string());
-                } else {
-                    valid_record = false;
-                    break;
-                }
-            } else {
-                valid_record = false;
-                break;
+    let mut failed = 0;
+    for (index, result) in results.iter().enumerate() {
+        match result {
+            Ok((path, stats)) => note!(
+                "Wrote {} rows of {} {} to {}",
+                stats.rows_written,
+                part,
+                index + 1,
+                path.display()
+            ),
+            Err(e) => {
+                eprintln!("Error: {} {}: {}", part, index + 1, e);
+                failed += 1;
             }
         }
+    }
+    if failed > 0 {
+        let message = format!(
+            "{} of {} {}s failed to decode",
+            failed,
+            results.len(),
+            part
+        );
+        return Err(io::Error::other(message).into());
+    }
+    Ok(())
+}
 
-        if valid_record && record.len() == desired_fields.len() {
-            if let Err(_e) = writer.write_record(&record) {
-                break;
+/// Resolves --force-int and --force-float into a global type and per-field overrides,
+/// exiting with a usage error if both claim the same columns.
+fn parse_column_types(args: &Args) -> (Option<ColumnType>, HashMap<String, ColumnType>) {
+    let mut force_type = None;
+    let mut force_types = HashMap::new();
+
+    for (fields, column_type) in [
+        (&args.force_int, ColumnType::Int),
+        (&args.force_float, ColumnType::Float),
+    ] {
+        let Some(fields) = fields else {
+            continue;
+        };
+
+        if fields.is_empty() {
+            if force_type.is_some() {
+                Args::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "--force-int and --force-float cannot both apply to every column",
+                    )
+                    .exit();
+            }
+            force_type = Some(column_type);
+        }
+
+        for field in fields {
+            if force_types.insert(field.clone(), column_type).is_some() {
+                Args::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        format!("{} is given to both --force-int and --force-float", field),
+                    )
+                    .exit();
             }
         }
     }
 
-    Ok(())
+    (force_type, force_types)
 }
 
-/// Reads a signed variable-length quantity (VLQ) from the data buffer.
-fn read_signed_vlq(data: &[u8], cursor: &mut usize) -> i32 {
-    let value = read_unsigned_vlq(data, cursor);
-    let sign = (value & 1) as i32;
-    let magnitude = (value >> 1) as i32;
+/// Returns the names of the main frame fields at the given positions, exiting with a
+/// usage error if an index is past the last field.
+fn fields_by_index(log: &BblLog, indices: &[usize]) -> Vec<String> {
+    let fields = log.main_fields();
+    indices
+        .iter()
+        .map(|&index| match fields.get(index) {
+            Some(field) => field.name.clone(),
+            None => Args::command()
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!(
+                        "field index {} is out of range, the log has {} fields (0-{})",
+                        index,
+                        fields.len(),
+                        fields.len().saturating_sub(1)
+                    ),
+                )
+                .exit(),
+        })
+        .collect()
+}
 
-    if sign != 0 {
-        -magnitude
+/// Names a side file of `output`: in the same directory, with the same stem and
+/// `suffix` in place of the extension, so `logs/flight.csv` gets `logs/flight.gps.csv`.
+fn side_file_name(output: &str, suffix: &str) -> String {
+    let path = std::path::Path::new(output);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}", stem, suffix))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Whether an --output path names a directory, existing or ending in a separator.
+fn is_directory(output: &str) -> bool {
+    output.ends_with(['/', std::path::MAIN_SEPARATOR]) || std::path::Path::new(output).is_dir()
+}
+
+/// Opens the input as a byte stream, downloading it when given an HTTP(S) URL.
+fn open_input(input: &str) -> io::Result<Box<dyn Read>> {
+    if input.starts_with("http://") || input.starts_with("https://") {
+        open_url(input)
     } else {
-        magnitude
+        Ok(Box::new(File::open(input)?))
     }
 }
 
-/// Reads an unsigned variable-length quantity (VLQ) from the data buffer.
-fn read_unsigned_vlq(data: &[u8], cursor: &mut usize) -> u32 {
-    let mut value: u32 = 0;
-    let mut shift: u32 = 0;
+/// Streams the response body of a URL, following redirects.
+#[cfg(feature = "http")]
+fn open_url(url: &str) -> io::Result<Box<dyn Read>> {
+    let response = ureq::get(url).call().map_err(|e| match e {
+        ureq::Error::Status(code, response) => io::Error::other(format!(
+            "HTTP {} {} fetching {}",
+            code,
+            response.status_text(),
+            url
+        )),
+        ureq::Error::Transport(transport) => {
+            io::Error::other(format!("failed to fetch {}: {}", url, transport))
+        }
+    })?;
+    Ok(Box::new(response.into_reader()))
+}
 
-    loop {
-        if *cursor >= data.len() {
-            break;
+#[cfg(not(feature = "http"))]
+fn open_url(url: &str) -> io::Result<Box<dyn Read>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("cannot fetch {}: built without the `http` feature", url),
+    ))
+}
+
+/// Warns about the motors that left the minthrottle..maxthrottle range, or the
+/// motorOutput range for digital motors.
+fn report_motor_anomalies(log: &BblLog, stats: &DecodeStats) {
+    let config = bbe_reader::parse_system_config(&log.headers);
+    let range = match config.digital_motors {
+        true => config.motor_output,
+        false => config.throttle_range,
+    };
+    let Some((min, max)) = range else {
+        eprintln!("Warning: no minthrottle and maxthrottle headers, cannot report anomalies");
+        return;
+    };
+    for (motor, &count) in stats.anomalous_samples.iter().enumerate() {
+        if count > 0 {
+            eprintln!(
+                "Warning: motor[{}] outside {}..{} in {} of {} frames, likely a decode error",
+                motor, min, max, count, stats.motor_samples
+            );
         }
+    }
+}
 
-        let byte = data[*cursor] as u32;
-        *cursor += 1;
+/// Fraction of missing main frames tolerated when the log does not name its blackbox
+/// device.
+const EXPECTED_MISSING_FRACTION: f64 = 0.01;
 
-        value |= (byte & 0x7F) << shift;
-        shift += 7;
+/// Warns when more frames are missing than the log's blackbox device usually drops.
+fn warn_missing_frames(log: &BblLog, stats: &DecodeStats) {
+    let device = log.system_config().blackbox_device;
+    let expected = device.map_or(EXPECTED_MISSING_FRACTION, |device| {
+        device.expected_missing_fraction()
+    });
+    let Some(missing) = stats.missing_fraction().filter(|&missing| missing > expected) else {
+        return;
+    };
+    let logs = device.map_or("logs".to_string(), |device| format!("{} logs", device));
+    eprintln!(
+        "Warning: {:.1}% of frames missing, more than the {:.1}% usual for {}",
+        missing * 100.0,
+        expected * 100.0,
+        logs
+    );
+}
 
-        if (byte & 0x80) == 0 {
-            break;
+/// Prints the counters collected by the decoder.
+fn print_stats(log: &BblLog, stats: &DecodeStats, loop_overrun: bool, clipping: bool) {
+    status!("Decode statistics:");
+    match log.system_config().blackbox_device {
+        Some(device) => status!("  Blackbox device: {}", device),
+        None => status!("  Blackbox device: unknown"),
+    }
+    for (frame_type, count) in &stats.frame_counts {
+        status!("  {}-frames: {}", frame_type, count);
+    }
+    match stats.frames_per_interval {
+        Some(frames_per_interval) => status!(
+            "  I-interval deviations: {} (expected a keyframe every {} frames)",
+            stats.i_interval_deviations, frames_per_interval
+        ),
+        None => status!("  I-interval deviations: unknown (no I interval header)"),
+    }
+    status!(
+        "  Missing frames: {} in {} gaps",
+        stats.missing_frames, stats.gaps
+    );
+    status!("  Skipped bytes: {}", stats.skipped_bytes);
+    status!("  Oversized frames: {}", stats.oversized_frames);
+    status!("  Resyncs at the next I-frame: {}", stats.resyncs);
+    status!("  P-frames before the first I-frame: {}", stats.unanchored_frames);
+    if loop_overrun {
+        match stats.overrun_fraction() {
+            Some(fraction) => status!(
+                "  Loop overruns: {} of {} intervals ({:.1}%)",
+                stats.overrun_intervals,
+                stats.timed_intervals,
+                fraction * 100.0
+            ),
+            None => status!("  Loop overruns: unknown (needs time, loopIteration and looptime)"),
         }
     }
+    if let (Some(median), Some(declared), Some(deviation)) = (
+        stats.median_iteration_time(),
+        stats.declared_loop_period,
+        stats.loop_period_deviation(),
+    ) {
+        status!(
+            "  Loop period: {} µs median, {:.0} µs declared ({:+.1}%)",
+            median,
+            declared,
+            deviation * 100.0
+        );
+        if deviation.abs() > bbe_reader::OVERRUN_TOLERANCE {
+            status!("  Warning: the logged loop period does not match the looptime header, the flight controller may have been overloaded");
+        }
+    }
+    if clipping {
+        let fractions = stats.clipping_fractions();
+        if fractions.is_empty() {
+            status!("  Motor clipping: unknown (needs motor fields and motorOutput)");
+        } else {
+            status!("  Motor clipping ({} frames):", stats.motor_samples);
+            for (motor, fraction) in fractions.iter().enumerate() {
+                status!(
+                    "    motor[{}]: {} ({:.1}%)",
+                    motor,
+                    stats.clipped_samples[motor],
+                    fraction * 100.0
+                );
+            }
+        }
+    }
+    status!("  Logging pauses: {}", stats.paused_spans.len());
+    for span in &stats.paused_spans {
+        match span.duration() {
+            Some(duration) => status!(
+                "    {} µs pause, resumed at iteration {} ({} µs)",
+                duration, span.resume_iteration, span.resume_time
+            ),
+            None => status!(
+                "    resumed at iteration {} ({} µs)",
+                span.resume_iteration, span.resume_time
+            ),
+        }
+    }
+}
+
+/// Prints an aligned table of the aggregates of each output column, leaving out columns
+/// with no numeric cell and those that are zero throughout.
+fn print_column_stats(stats: &DecodeStats) {
+    let columns: Vec<_> = stats.columns.iter().filter(|c| !c.is_blank()).collect();
+    if columns.is_empty() {
+        return;
+    }
+    let width = columns.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    status!("Column statistics:");
+    status!(
+        "  {:<width$} {:>14} {:>14} {:>14} {:>14}",
+        "column", "min", "max", "mean", "stddev"
+    );
+    for column in columns {
+        status!(
+            "  {:<width$} {:>14.3} {:>14.3} {:>14.3} {:>14.3}",
+            column.name,
+            column.min,
+            column.max,
+            column.mean,
+            column.std_dev()
+        );
+    }
+}
 
-    value
+/// Prints the field values read and bytes consumed per encoding.
+fn print_encoding_stats(stats: &DecodeStats) {
+    let total_bytes: usize = stats.encodings.values().map(|usage| usage.bytes).sum();
+    status!("Encodings:");
+    for (&encoding, usage) in &stats.encodings {
+        status!(
+            "  {} ({}): {} fields, {} bytes ({:.2} bytes/field, {:.1}% of field bytes)",
+            bbe_reader::encoding_name(encoding).unwrap_or("unknown"),
+            encoding,
+            usage.fields,
+            usage.bytes,
+            usage.bytes as f64 / usage.fields.max(1) as f64,
+            usage.bytes as f64 * 100.0 / total_bytes.max(1) as f64
+        );
+    }
 }
@@ -0,0 +1,214 @@
+use std::io::{self, Write};
+
+use csv::Writer;
+
+use crate::decode::{predictor_name, DecodeStats};
+use crate::encoding::encoding_name;
+use crate::error::BblError;
+use crate::header::BblLog;
+
+/// Writes a JSON summary of a decoded log: identifying headers such as the product, the
+/// [`SystemConfig`](crate::SystemConfig) scales, the number of main frame fields and the
+/// decode statistics, including the count of each frame type and the logged time in
+/// seconds. Headers the log lacks are written as `null`.
+///
+/// ```
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Firmware revision:Betaflight 4.5.1\n");
+/// log.extend_from_slice(b"H Blackbox device:SDCARD\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration,time\n");
+/// log.extend_from_slice(b"H Field I encoding:1,1\n");
+/// // Iteration 2 is missing
+/// log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01, b'I', 3, 0x90, 0x03]);
+/// log.extend_from_slice(&[b'E', 255]);
+///
+/// let parsed = bbe_reader::BblLog::from_bytes(&log)?;
+/// let stats = bbe_reader::write_csv(
+///     &parsed,
+///     &log[parsed.data_offset..],
+///     std::io::sink(),
+///     &Default::default(),
+/// )?;
+/// let mut json = Vec::new();
+/// bbe_reader::write_metadata_json(&parsed, &stats, &mut json)?;
+/// let json = String::from_utf8(json).unwrap();
+///
+/// assert!(json.contains(r#""product": "Blackbox flight data recorder by Nicholas Sherlock""#));
+/// assert!(json.contains(r#""firmware_revision": "Betaflight 4.5.1""#));
+/// assert!(json.contains(r#""craft_name": null"#));
+/// assert!(json.contains(r#""gyro_scale": null"#));
+/// assert!(json.contains(r#""blackbox_device": "SD card""#));
+/// assert!(json.contains(r#""frame_counts": {"E": 1, "I": 3}"#));
+/// assert!(json.contains(r#""decoded_frames": 4"#));
+/// assert!(json.contains(r#""missing_frames": 1"#));
+/// assert!(json.contains(r#""duration_s": 0.0003"#));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// Header values are quoted, so a craft name with commas or quotes stays one value:
+///
+/// ```
+/// let log = b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n\
+///             H Craft name:Quad, \"5 inch\"\n\
+///             H Field I name:loopIteration\n\
+///             H Field I encoding:1\n";
+/// let parsed = bbe_reader::BblLog::from_bytes(log)?;
+/// let mut json = Vec::new();
+/// bbe_reader::write_metadata_json(&parsed, &Default::default(), &mut json)?;
+///
+/// assert!(String::from_utf8(json)
+///     .unwrap()
+///     .contains(r#""craft_name": "Quad, \"5 inch\"","#));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn write_metadata_json<W: Write>(
+    log: &BblLog,
+    stats: &DecodeStats,
+    mut writer: W,
+) -> io::Result<()> {
+    let config = log.system_config();
+    let text = |value: &Option<String>| value.as_deref().map_or("null".to_string(), json_string);
+    let number = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+    let frame_counts: Vec<String> = stats
+        .frame_counts
+        .iter()
+        .map(|(frame_type, count)| format!("{}: {}", json_string(&frame_type.to_string()), count))
+        .collect();
+
+    writeln!(writer, "{{")?;
+    let product = log.header_value("Product").map(str::to_string);
+    writeln!(writer, "  \"product\": {},", text(&product))?;
+    writeln!(
+        writer,
+        "  \"firmware_revision\": {},",
+        text(&config.firmware_revision)
+    )?;
+    writeln!(
+        writer,
+        "  \"board_information\": {},",
+        text(&config.board_information)
+    )?;
+    writeln!(writer, "  \"craft_name\": {},", text(&config.craft_name))?;
+    let data_version = config.data_version.map(|version| version.to_string());
+    writeln!(writer, "  \"data_version\": {},", number(data_version))?;
+    let looptime = config.looptime.map(|looptime| looptime.to_string());
+    writeln!(writer, "  \"looptime\": {},", number(looptime))?;
+    // NaN and infinity have no JSON representation
+    let gyro_scale = config.gyro_scale.filter(|scale| scale.is_finite());
+    writeln!(
+        writer,
+        "  \"gyro_scale\": {},",
+        number(gyro_scale.map(|scale| scale.to_string()))
+    )?;
+    let acc_1g = config.acc_1g.map(|acc_1g| acc_1g.to_string());
+    writeln!(writer, "  \"acc_1g\": {},", number(acc_1g))?;
+    let vbat_scale = config.vbat_scale.map(|scale| scale.to_string());
+    writeln!(writer, "  \"vbat_scale\": {},", number(vbat_scale))?;
+    let blackbox_device = config.blackbox_device.map(|device| device.to_string());
+    writeln!(writer, "  \"blackbox_device\": {},", text(&blackbox_device))?;
+    writeln!(writer, "  \"field_count\": {},", log.main_fields().len())?;
+    writeln!(
+        writer,
+        "  \"frame_counts\": {{{}}},",
+        frame_counts.join(", ")
+    )?;
+    let decoded_frames: usize = stats.frame_counts.values().sum();
+    writeln!(writer, "  \"decoded_frames\": {},", decoded_frames)?;
+    writeln!(writer, "  \"rows_written\": {},", stats.rows_written)?;
+    writeln!(writer, "  \"missing_frames\": {},", stats.missing_frames)?;
+    writeln!(
+        writer,
+        "  \"i_interval_deviations\": {},",
+        stats.i_interval_deviations
+    )?;
+    writeln!(writer, "  \"skipped_bytes\": {},", stats.skipped_bytes)?;
+    writeln!(
+        writer,
+        "  \"duration_s\": {}",
+        stats.logged_time as f64 / 1e6
+    )?;
+    writeln!(writer, "}}")?;
+    writer.flush()
+}
+
+/// Writes the field definitions of every frame type of a log as CSV, one row per field in
+/// frame type and layout order, with the encoding and predictor both as the header's number
+/// and the firmware's name. Unknown encodings and predictors have an empty name.
+///
+/// ```
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:loopIteration,axisP[0]\n");
+/// log.extend_from_slice(b"H Field I signed:0,1\n");
+/// log.extend_from_slice(b"H Field I encoding:1,0\n");
+/// log.extend_from_slice(b"H Field P predictor:6,1\n");
+/// log.extend_from_slice(b"H Field P encoding:9,0\n");
+///
+/// let mut csv = Vec::new();
+/// bbe_reader::write_field_defs_csv(&bbe_reader::BblLog::from_bytes(&log)?, &mut csv)?;
+///
+/// assert_eq!(
+///     String::from_utf8(csv).unwrap(),
+///     "frame,name,encoding,encoding_name,signed,predictor,predictor_name\n\
+///      I,loopIteration,1,UNSIGNED_VB,0,0,ZERO\n\
+///      I,axisP[0],0,SIGNED_VB,1,0,ZERO\n\
+///      P,loopIteration,9,NULL,0,6,INC\n\
+///      P,axisP[0],0,SIGNED_VB,1,1,PREVIOUS\n"
+/// );
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+pub fn write_field_defs_csv<W: Write>(log: &BblLog, writer: W) -> Result<(), BblError> {
+    let mut writer = Writer::from_writer(writer);
+    writer.write_record([
+        "frame",
+        "name",
+        "encoding",
+        "encoding_name",
+        "signed",
+        "predictor",
+        "predictor_name",
+    ])?;
+    let mut frame_types: Vec<&u8> = log.layouts.keys().collect();
+    frame_types.sort_by_key(|&&marker| frame_order(marker));
+    for &marker in frame_types {
+        for field in &log.layouts[&marker] {
+            writer.write_record([
+                (marker as char).to_string(),
+                field.name.clone(),
+                field.encoding.to_string(),
+                encoding_name(field.encoding).unwrap_or("").to_string(),
+                (field.signed as u8).to_string(),
+                field.predictor.to_string(),
+                predictor_name(field.predictor).unwrap_or("").to_string(),
+            ])?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Sorts frame types as in [`crate::FRAME_TYPES`], unknown ones last by marker.
+fn frame_order(marker: u8) -> (usize, u8) {
+    let position = crate::FRAME_TYPES.iter().position(|&t| t == marker as char);
+    (position.unwrap_or(crate::FRAME_TYPES.len()), marker)
+}
+
+/// Quotes a string for JSON.
+pub(crate) fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
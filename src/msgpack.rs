@@ -0,0 +1,115 @@
+use std::io::{self, Write};
+
+use crate::output::RecordSink;
+
+/// Writes the output as a MessagePack array with one map per row, keyed by the header
+/// row. Cells are stored as integers or floats where they parse as one, empty cells as
+/// nil and anything else as strings.
+///
+/// An array starts with its length, so the encoded rows are kept in memory until
+/// [`RecordSink::finish`].
+pub(crate) struct MessagePackSink<W: Write> {
+    writer: W,
+    header: Option<Vec<String>>,
+    rows: usize,
+    body: Vec<u8>,
+}
+
+impl<W: Write> MessagePackSink<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        MessagePackSink {
+            writer,
+            header: None,
+            rows: 0,
+            body: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> RecordSink for MessagePackSink<W> {
+    fn write_record(&mut self, record: &[String]) -> io::Result<()> {
+        let Some(header) = &self.header else {
+            self.header = Some(record.to_vec());
+            return Ok(());
+        };
+        write_length(&mut self.body, record.len(), [0x80, 0xde, 0xdf], 15);
+        for (name, cell) in header.iter().zip(record) {
+            write_str(&mut self.body, name);
+            write_cell(&mut self.body, cell);
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Nothing can be written before the number of rows is known
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut array = Vec::new();
+        write_length(&mut array, self.rows, [0x90, 0xdc, 0xdd], 15);
+        self.writer.write_all(&array)?;
+        self.writer.write_all(&self.body)?;
+        self.writer.flush()
+    }
+}
+
+/// Writes the length prefix of a map, array or string, given the markers of its fix,
+/// 16-bit and 32-bit forms and the longest length the fix form holds.
+fn write_length(out: &mut Vec<u8>, length: usize, [fix, len16, len32]: [u8; 3], fix_max: usize) {
+    if length <= fix_max {
+        out.push(fix | length as u8);
+    } else if let Ok(length) = u16::try_from(length) {
+        out.push(len16);
+        out.extend_from_slice(&length.to_be_bytes());
+    } else {
+        out.push(len32);
+        out.extend_from_slice(&(length as u32).to_be_bytes());
+    }
+}
+
+fn write_str(out: &mut Vec<u8>, value: &str) {
+    write_length(out, value.len(), [0xa0, 0xda, 0xdb], 31);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn write_cell(out: &mut Vec<u8>, cell: &str) {
+    if cell.is_empty() {
+        out.push(0xc0);
+    } else if let Ok(value) = cell.parse::<i64>() {
+        write_int(out, value);
+    } else if let Ok(value) = cell.parse::<f64>() {
+        out.push(0xcb);
+        out.extend_from_slice(&value.to_be_bytes());
+    } else {
+        write_str(out, cell);
+    }
+}
+
+/// Writes an integer in the smallest MessagePack form that holds it.
+fn write_int(out: &mut Vec<u8>, value: i64) {
+    if (-32..=127).contains(&value) {
+        // Positive and negative fixints
+        out.push(value as u8);
+    } else if let Ok(value) = u8::try_from(value) {
+        out.extend_from_slice(&[0xcc, value]);
+    } else if let Ok(value) = u16::try_from(value) {
+        out.push(0xcd);
+        out.extend_from_slice(&value.to_be_bytes());
+    } else if let Ok(value) = u32::try_from(value) {
+        out.push(0xce);
+        out.extend_from_slice(&value.to_be_bytes());
+    } else if let Ok(value) = i8::try_from(value) {
+        out.extend_from_slice(&[0xd0, value as u8]);
+    } else if let Ok(value) = i16::try_from(value) {
+        out.push(0xd1);
+        out.extend_from_slice(&value.to_be_bytes());
+    } else if let Ok(value) = i32::try_from(value) {
+        out.push(0xd2);
+        out.extend_from_slice(&value.to_be_bytes());
+    } else {
+        out.push(0xd3);
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
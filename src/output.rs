@@ -0,0 +1,679 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::SyncSender;
+
+use csv::Writer;
+
+use crate::decode::ColumnSummary;
+use crate::flags::{format_flags, FlagNames};
+use crate::units::Scale;
+
+/// Destination for decoded CSV records.
+pub(crate) trait RecordSink {
+    fn write_record(&mut self, record: &[String]) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Called before the rows of each keyframe and the P-frames that follow it.
+    fn start_segment(&mut self) {}
+
+    /// Called once after the last record.
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: Write> RecordSink for Writer<W> {
+    fn write_record(&mut self, record: &[String]) -> io::Result<()> {
+        Writer::write_record(self, record)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Writer::flush(self)
+    }
+}
+
+impl<S: RecordSink + ?Sized> RecordSink for &mut S {
+    fn write_record(&mut self, record: &[String]) -> io::Result<()> {
+        (**self).write_record(record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn start_segment(&mut self) {
+        (**self).start_segment()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
+    }
+}
+
+/// Keeps the records in memory.
+impl RecordSink for Vec<Vec<String>> {
+    fn write_record(&mut self, record: &[String]) -> io::Result<()> {
+        self.push(record.to_vec());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Messages sent from the decoder to the writer thread.
+pub(crate) enum SinkMessage {
+    Record(Vec<String>),
+    Flush,
+}
+
+/// Forwards records over a bounded channel to a writer thread, blocking the decoder
+/// only when the writer falls behind by a full channel.
+pub(crate) struct ChannelSink(pub(crate) SyncSender<SinkMessage>);
+
+impl RecordSink for ChannelSink {
+    fn write_record(&mut self, record: &[String]) -> io::Result<()> {
+        self.0
+            .send(SinkMessage::Record(record.to_vec()))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "writer thread stopped"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0
+            .send(SinkMessage::Flush)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "writer thread stopped"))
+    }
+}
+
+/// Holds records back per keyframe segment so they can be written last segment first.
+#[derive(Default)]
+pub(crate) struct ReversedSegments(Vec<Vec<Vec<String>>>);
+
+impl ReversedSegments {
+    /// Writes the buffered segments to `sink` in reverse order, each in its own order.
+    pub(crate) fn write_to(self, sink: &mut dyn RecordSink) -> io::Result<()> {
+        for record in self.0.into_iter().rev().flatten() {
+            sink.write_record(&record)?;
+        }
+        Ok(())
+    }
+}
+
+impl RecordSink for ReversedSegments {
+    fn write_record(&mut self, record: &[String]) -> io::Result<()> {
+        if self.0.is_empty() {
+            self.0.push(Vec::new());
+        }
+        self.0.last_mut().unwrap().push(record.to_vec());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Nothing is written until every segment is decoded
+        Ok(())
+    }
+
+    fn start_segment(&mut self) {
+        self.0.push(Vec::new());
+    }
+}
+
+/// Writes every record to the full output and the header rows plus every `every`th row
+/// to a preview, so both come from a single decode.
+pub(crate) struct WithPreview<F, P> {
+    full: F,
+    preview: Option<P>,
+    every: usize,
+    /// Header rows still to be copied to the preview before the data rows
+    header_rows: usize,
+    rows: usize,
+}
+
+impl<F, P> WithPreview<F, P> {
+    pub(crate) fn new(full: F, preview: Option<P>, every: usize, header_rows: usize) -> Self {
+        WithPreview {
+            full,
+            preview,
+            every: every.max(1),
+            header_rows,
+            rows: 0,
+        }
+    }
+}
+
+impl<F: RecordSink, P: RecordSink> RecordSink for WithPreview<F, P> {
+    fn write_record(&mut self, record: &[String]) -> io::Result<()> {
+        self.full.write_record(record)?;
+        let Some(preview) = self.preview.as_mut() else {
+            return Ok(());
+        };
+        if self.header_rows > 0 {
+            self.header_rows -= 1;
+            return preview.write_record(record);
+        }
+        self.rows += 1;
+        if (self.rows - 1).is_multiple_of(self.every) {
+            preview.write_record(record)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.full.flush()?;
+        self.preview.as_mut().map_or(Ok(()), RecordSink::flush)
+    }
+
+    fn start_segment(&mut self) {
+        self.full.start_segment();
+        if let Some(preview) = self.preview.as_mut() {
+            preview.start_segment();
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.full.finish()?;
+        self.preview.as_mut().map_or(Ok(()), RecordSink::finish)
+    }
+}
+
+/// Passes records on to another sink, adding the numeric cells of each column to its
+/// summary when column statistics are collected.
+pub(crate) struct Summarizing<'a, S> {
+    sink: S,
+    summaries: Option<&'a mut Vec<ColumnSummary>>,
+}
+
+impl<'a, S> Summarizing<'a, S> {
+    pub(crate) fn new(sink: S, summaries: Option<&'a mut Vec<ColumnSummary>>) -> Self {
+        Summarizing { sink, summaries }
+    }
+}
+
+impl<S: RecordSink> RecordSink for Summarizing<'_, S> {
+    fn write_record(&mut self, record: &[String]) -> io::Result<()> {
+        if let Some(summaries) = self.summaries.as_mut() {
+            for (summary, cell) in summaries.iter_mut().zip(record) {
+                if let Ok(value) = cell.parse::<f64>() {
+                    summary.add(value);
+                }
+            }
+        }
+        self.sink.write_record(record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+    fn start_segment(&mut self) {
+        self.sink.start_segment()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.sink.finish()
+    }
+}
+
+/// Fields with one value per roll, pitch and yaw axis in indices 0 to 2, unpivoted by
+/// [`LongFormat`]. Other indices, like the throttle in `setpoint[3]`, stay columns.
+pub(crate) const AXIS_FIELDS: &[&str] = &[
+    "axisP",
+    "axisI",
+    "axisD",
+    "axisF",
+    "gyroADC",
+    "gyroUnfilt",
+    "setpoint",
+    "rcCommand",
+    "accSmooth",
+    "magADC",
+];
+
+/// Where the cells of each long format row are taken from in a wide record.
+struct LongLayout {
+    /// Positions of the columns repeated on every axis row
+    wide: Vec<usize>,
+    /// Axis indices found, each written as a row
+    axes: Vec<usize>,
+    /// For each per-axis field, the position of its column on each axis
+    fields: Vec<Vec<Option<usize>>>,
+}
+
+/// Reshapes wide records into long format when enabled: each record becomes one row
+/// per axis, with an `axis` column and a column per field of [`AXIS_FIELDS`], and the
+/// other columns repeated on every row. The first record is taken as the header row,
+/// and the next `header_rows - 1` as further header rows, like units, written once.
+/// Records without per-axis columns are passed through unchanged.
+pub(crate) struct LongFormat<S> {
+    sink: S,
+    enabled: bool,
+    header_rows: usize,
+    layout: Option<LongLayout>,
+}
+
+impl<S> LongFormat<S> {
+    pub(crate) fn new(sink: S, enabled: bool, header_rows: usize) -> Self {
+        LongFormat {
+            sink,
+            enabled,
+            header_rows,
+            layout: None,
+        }
+    }
+}
+
+/// Splits a column name like `axisP[1]` into a field of [`AXIS_FIELDS`] and an axis.
+fn axis_column(name: &str) -> Option<(&str, usize)> {
+    let (field, index) = name.strip_suffix(']')?.split_once('[')?;
+    let axis = index.parse().ok().filter(|&axis| axis < 3)?;
+    AXIS_FIELDS.contains(&field).then_some((field, axis))
+}
+
+impl<S: RecordSink> LongFormat<S> {
+    fn write_header(&mut self, header: &[String]) -> io::Result<()> {
+        let mut layout = LongLayout {
+            wide: Vec::new(),
+            axes: Vec::new(),
+            fields: Vec::new(),
+        };
+        let mut names: Vec<&str> = Vec::new();
+        for (position, name) in header.iter().enumerate() {
+            let Some((field, axis)) = axis_column(name) else {
+                layout.wide.push(position);
+                continue;
+            };
+            let index = match names.iter().position(|&other| other == field) {
+                Some(index) => index,
+                None => {
+                    names.push(field);
+                    layout.fields.push(vec![None; 3]);
+                    names.len() - 1
+                }
+            };
+            layout.fields[index][axis] = Some(position);
+            if !layout.axes.contains(&axis) {
+                layout.axes.push(axis);
+            }
+        }
+        layout.axes.sort_unstable();
+        if names.is_empty() {
+            self.layout = Some(layout);
+            return self.sink.write_record(header);
+        }
+
+        let mut record: Vec<String> = layout.wide.iter().map(|&i| header[i].clone()).collect();
+        record.push("axis".to_string());
+        record.extend(names.iter().map(|name| name.to_string()));
+        self.layout = Some(layout);
+        self.sink.write_record(&record)
+    }
+}
+
+impl<S: RecordSink> RecordSink for LongFormat<S> {
+    fn write_record(&mut self, record: &[String]) -> io::Result<()> {
+        if !self.enabled {
+            return self.sink.write_record(record);
+        }
+        let Some(layout) = &self.layout else {
+            self.header_rows = self.header_rows.saturating_sub(1);
+            return self.write_header(record);
+        };
+        if layout.fields.is_empty() {
+            return self.sink.write_record(record);
+        }
+        let cell =
+            |position: Option<usize>| position.map_or_else(String::new, |i| record[i].clone());
+        let wide = layout.wide.iter().map(|&i| record[i].clone());
+        if self.header_rows > 0 {
+            // Further header rows take the cells of the first column of each field
+            self.header_rows -= 1;
+            let mut row: Vec<String> = wide.collect();
+            row.push(String::new());
+            row.extend(
+                layout
+                    .fields
+                    .iter()
+                    .map(|columns| cell(columns.iter().flatten().next().copied())),
+            );
+            return self.sink.write_record(&row);
+        }
+        for &axis in &layout.axes {
+            let mut row: Vec<String> = wide.clone().collect();
+            row.push(axis.to_string());
+            row.extend(layout.fields.iter().map(|columns| cell(columns[axis])));
+            self.sink.write_record(&row)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+    fn start_segment(&mut self) {
+        self.sink.start_segment()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.sink.finish()
+    }
+}
+
+/// Number of records that may be queued for the writer thread.
+pub(crate) const WRITER_QUEUE_LEN: usize = 1024;
+
+/// File format written by [`crate::write_csv`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// JSON Lines: a header object with the log's firmware, craft name and data version,
+    /// then one object per row, from column name to value. Numbers are written as JSON
+    /// numbers, empty cells as `null`. The units row is not written.
+    JsonLines,
+    /// A MessagePack array with one map per row, from column name to value. The units
+    /// row is not written.
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    /// An SQLite database with a `frames` table of the rows, one column per output
+    /// column, and a `headers` table of the log's header lines as `name` and `value`.
+    /// Columns are declared `INTEGER`, `REAL` or `TEXT` from the values written to
+    /// them. The units row is not written.
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+/// Numeric type forced on an output column.
+///
+/// ```
+/// use bbe_reader::{ColumnType, DecodeOptions};
+///
+/// let mut log = Vec::new();
+/// log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+/// log.extend_from_slice(b"H Field I name:vbatLatest\n");
+/// log.extend_from_slice(b"H Field I encoding:1\n");
+/// // vbatLatest of 1650 and 1649, i.e. 16.50 V and 16.49 V
+/// log.extend_from_slice(&[b'I', 0xf2, 0x0c, b'I', 0xf1, 0x0c]);
+///
+/// let options = DecodeOptions {
+///     fields: vec!["vbatLatest".to_string()],
+///     physical_units: true,
+///     force_type: Some(ColumnType::Int),
+///     ..DecodeOptions::default()
+/// };
+/// let mut csv = Vec::new();
+/// bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+///
+/// assert_eq!(String::from_utf8(csv).unwrap(), "vbatLatest\n17\n16\n");
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    /// Whole numbers; scaled values are rounded to the nearest integer, with halves
+    /// rounded away from zero (`2.5` becomes `3`, `-2.5` becomes `-3`)
+    Int,
+    /// Always written with a decimal point, e.g. `100.0`
+    Float,
+}
+
+/// Where the `altitude` column takes its values from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AltitudeSource {
+    /// The barometer's `baroAlt` main frame field
+    Baro,
+    /// The `GPS_altitude` field of the latest GPS (G) frame, logged by Betaflight in
+    /// decimetres and converted to centimetres
+    Gps,
+    /// `baroAlt` in sessions that log it, and `GPS_altitude` in those that do not
+    Merge,
+}
+
+/// How long the values of a slow (S) frame are carried forward before they are stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowMaxAge {
+    /// Twice the session's S-frame interval, from the `S interval` header or, without
+    /// one, 256 times the `I interval`
+    Auto,
+    /// This many loop iterations
+    Iterations(u64),
+}
+
+/// How far `loopIteration` has to skip ahead before the frames in between count as a
+/// gap rather than jitter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GapThreshold {
+    /// More than this many loop iterations past the next logged one
+    Iterations(u64),
+    /// Any skip whose frames are more than this many µs apart
+    Micros(i64),
+}
+
+/// How the values of one field are converted and formatted.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ColumnFormat {
+    pub(crate) scale: Option<Scale>,
+    pub(crate) column_type: Option<ColumnType>,
+    /// Digits after the decimal point for float values, `None` for the shortest exact form
+    pub(crate) precision: Option<usize>,
+    /// Names written instead of the number, for flag fields
+    pub(crate) flags: Option<FlagNames>,
+}
+
+/// Appends a value to a CSV record, scaled (and optionally also raw) when a
+/// physical-unit conversion applies.
+pub(crate) fn push_value(
+    record: &mut Vec<String>,
+    value: i64,
+    format: &ColumnFormat,
+    keep_raw: bool,
+) {
+    match format.scale {
+        Some(scale) => {
+            if keep_raw {
+                record.push(format_raw(value, format));
+            }
+            record.push(format_scaled(
+                value as f64 * scale.factor + scale.offset,
+                format,
+            ));
+        }
+        None => record.push(format_raw(value, format)),
+    }
+}
+
+/// Appends the Polars dtype of each cell [`push_value`] writes for a column: `String` for
+/// flag names, `Float64` for scaled values and forced floats, and `Int64` otherwise.
+pub(crate) fn push_dtypes(dtypes: &mut Vec<&'static str>, format: &ColumnFormat, keep_raw: bool) {
+    let raw = match (format.flags, format.column_type) {
+        (Some(_), _) => "String",
+        (None, Some(ColumnType::Float)) => "Float64",
+        (None, _) => "Int64",
+    };
+    match format.scale {
+        Some(_) => {
+            if keep_raw {
+                dtypes.push(raw);
+            }
+            dtypes.push(match format.column_type {
+                Some(ColumnType::Int) => "Int64",
+                _ => "Float64",
+            });
+        }
+        None => dtypes.push(raw),
+    }
+}
+
+/// Appends empty cells in place of a value that is missing from a row.
+pub(crate) fn push_empty(record: &mut Vec<String>, format: &ColumnFormat, keep_raw: bool) {
+    let cells = if format.scale.is_some() && keep_raw {
+        2
+    } else {
+        1
+    };
+    record.extend(std::iter::repeat_n(String::new(), cells));
+}
+
+/// Formats a raw field value, which is an integer unless floats are forced.
+fn format_raw(value: i64, format: &ColumnFormat) -> String {
+    if let Some(names) = format.flags {
+        return format_flags(value, names);
+    }
+    match format.column_type {
+        Some(ColumnType::Float) => format_float(value as f64, format.precision),
+        _ => value.to_string(),
+    }
+}
+
+/// Formats a scaled field value, which is a float unless integers are forced.
+fn format_scaled(value: f64, format: &ColumnFormat) -> String {
+    match (format.column_type, format.precision) {
+        (Some(ColumnType::Int), _) => (value.round() as i64).to_string(),
+        (Some(ColumnType::Float), precision) => format_float(value, precision),
+        (None, Some(precision)) => format!("{:.*}", precision, value),
+        (None, None) => value.to_string(),
+    }
+}
+
+/// Formats a float that must keep its decimal point.
+fn format_float(value: f64, precision: Option<usize>) -> String {
+    match precision {
+        Some(precision) => format!("{:.*}", precision, value),
+        // `Debug` keeps the decimal point on whole numbers
+        None => format!("{:?}", value),
+    }
+}
+
+/// Makes a file name stem safe to create on any common filesystem by replacing the
+/// characters Windows rejects (`<>:"/\|?*` and control characters) with `_`, and
+/// dropping trailing dots and spaces. Output files are named after the input, whose
+/// name may come from another system or a URL.
+///
+/// ```
+/// assert_eq!(bbe_reader::sanitize_file_stem("LOG 12:30 \"race\""), "LOG 12_30 _race_");
+/// assert_eq!(bbe_reader::sanitize_file_stem("btfl_001."), "btfl_001");
+/// assert_eq!(bbe_reader::sanitize_file_stem("btfl_001"), "btfl_001");
+/// ```
+pub fn sanitize_file_stem(stem: &str) -> String {
+    let stem: String = stem
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    stem.trim_end_matches(['.', ' ']).to_string()
+}
+
+/// Returns the file name stem of an input path or URL to name its output files after,
+/// made safe with [`sanitize_file_stem`], or `None` if it has no usable file name. The
+/// query string and fragment of a URL are left out.
+///
+/// ```
+/// use bbe_reader::input_stem;
+///
+/// assert_eq!(input_stem("logs/LOG00001.BBL").as_deref(), Some("LOG00001"));
+/// assert_eq!(input_stem("https://example.com/btfl_002.bbl?raw=1").as_deref(), Some("btfl_002"));
+/// for path in [".", "..", "/", "logs/...", ""] {
+///     assert_eq!(input_stem(path), None, "{:?}", path);
+/// }
+/// ```
+pub fn input_stem(input: &str) -> Option<String> {
+    let path = Path::new(input.split(['?', '#']).next().unwrap_or_default());
+    let stem = sanitize_file_stem(&path.file_stem()?.to_string_lossy());
+    (!stem.is_empty()).then_some(stem)
+}
+
+/// An output file written under a temporary name in the same directory and renamed to
+/// its path by [`AtomicFile::commit`], so the path only ever holds complete output. If
+/// it is dropped without being committed, as when decoding fails part way, the
+/// temporary file is removed and the path is left as it was.
+///
+/// ```
+/// use std::io::Write;
+///
+/// use bbe_reader::AtomicFile;
+///
+/// let dir = std::env::temp_dir().join(format!("bbe_reader_atomic_{}", std::process::id()));
+/// std::fs::create_dir_all(&dir)?;
+/// let path = dir.join("LOG00001.csv");
+///
+/// // Decoding fails after the header row
+/// let result: std::io::Result<()> = (|| {
+///     let mut file = AtomicFile::create(&path)?;
+///     file.write_all(b"loopIteration,time\n")?;
+///     Err(std::io::Error::other("truncated frame"))?;
+///     file.commit()
+/// })();
+/// assert!(result.is_err());
+/// assert!(!path.exists());
+/// assert_eq!(std::fs::read_dir(&dir)?.count(), 0);
+///
+/// let mut file = AtomicFile::create(&path)?;
+/// file.write_all(b"loopIteration,time\n0,100\n")?;
+/// file.commit()?;
+/// assert_eq!(std::fs::read_to_string(&path)?, "loopIteration,time\n0,100\n");
+///
+/// std::fs::remove_dir_all(&dir)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct AtomicFile {
+    /// `None` once committed
+    file: Option<File>,
+    temp_path: PathBuf,
+    path: PathBuf,
+}
+
+impl AtomicFile {
+    /// Creates the temporary file for `path`, a hidden `.partial` file next to it.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file_name = path.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a file path", path.display()),
+            )
+        })?;
+        let temp_path = path.with_file_name(format!(
+            ".{}.{}.partial",
+            file_name.to_string_lossy(),
+            std::process::id()
+        ));
+        let file = File::create(&temp_path)?;
+        Ok(AtomicFile {
+            file: Some(file),
+            temp_path,
+            path,
+        })
+    }
+
+    /// Flushes the output to disk and renames it to its path, replacing any file there.
+    pub fn commit(mut self) -> io::Result<()> {
+        if let Some(file) = self.file.take() {
+            file.sync_all()?;
+        }
+        std::fs::rename(&self.temp_path, &self.path)
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.as_mut().expect("written after commit").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().expect("flushed after commit").flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = std::fs::remove_file(&self.temp_path);
+        }
+    }
+}
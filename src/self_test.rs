@@ -0,0 +1,175 @@
+use std::fmt;
+
+use crate::compare::{first_mismatch, Mismatch};
+use crate::encoding::{read_signed_vlq, read_unsigned_vlq, write_signed_vlq, write_unsigned_vlq};
+use crate::error::BblError;
+use crate::DecodeOptions;
+
+/// Header block of the built-in log: loop iteration, time, a PID term and a motor
+/// output, with P-frames predicted from the previous frame.
+const SELF_TEST_HEADERS: &str = "\
+H Product:Blackbox flight data recorder by Nicholas Sherlock
+H Data version:2
+H I interval:32
+H P interval:1/1
+H Field I name:loopIteration,time,axisP[0],motor[0]
+H Field I signed:0,0,1,0
+H Field I predictor:0,0,0,0
+H Field I encoding:1,1,0,1
+H Field P predictor:6,1,1,1
+H Field P encoding:0,0,0,0
+";
+
+/// Frames of the built-in log: an arming beep, one I-frame, two P-frames and the end of
+/// the log.
+const SELF_TEST_FRAMES: &[u8] = &[
+    b'E', 0, 0x32, // Armed at 50 µs
+    b'I', 0, 0xe8, 0x07, 0x18, 0xb0, 0x09, // 0, 1000 µs, 12, 1200
+    b'P', 0, 0xfa, 0x01, 0x03, 0x08, // +1, +125 µs, -2, +4
+    b'P', 0, 0xfa, 0x01, 0x02, 0x0b, // +1, +125 µs, +1, -6
+    b'E', 255,
+];
+
+/// CSV the built-in log decodes to.
+const SELF_TEST_CSV: &str = "\
+loopIteration,time,axisP[0],motor[0]
+0,1000,12,1200
+1,1125,10,1204
+2,1250,11,1198
+";
+
+/// Decodes a small log compiled into the crate and compares the output with the values
+/// it is known to hold, returning the first difference. `None` means the decoder works.
+///
+/// ```
+/// assert_eq!(bbe_reader::self_test()?, None);
+/// # Ok::<(), bbe_reader::BblError>(())
+/// ```
+pub fn self_test() -> Result<Option<Mismatch>, BblError> {
+    let mut log = SELF_TEST_HEADERS.as_bytes().to_vec();
+    log.extend_from_slice(SELF_TEST_FRAMES);
+    log.extend_from_slice(b"End of log\0");
+
+    let options = DecodeOptions {
+        fields: ["loopIteration", "time", "axisP[0]", "motor[0]"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+        ..DecodeOptions::default()
+    };
+    let mut csv = Vec::new();
+    crate::decode_to_writer(&log[..], &mut csv, options)?;
+    first_mismatch(&csv[..], SELF_TEST_CSV.as_bytes())
+}
+
+/// Unsigned values [`fuzz_vlq`] always round-trips: each end of every VLQ length.
+const UNSIGNED_VLQ_BOUNDARIES: &[u32] = &[
+    0,
+    1,
+    0x7f,
+    0x80,
+    0x3fff,
+    0x4000,
+    0x1f_ffff,
+    0x20_0000,
+    0xfff_ffff,
+    0x1000_0000,
+    u32::MAX - 1,
+    u32::MAX,
+];
+
+/// Signed values [`fuzz_vlq`] always round-trips: each end of every VLQ length once
+/// zigzag encoded, and the ends of the 16- and 32-bit ranges.
+const SIGNED_VLQ_BOUNDARIES: &[i32] = &[
+    0,
+    -1,
+    1,
+    63,
+    -64,
+    64,
+    -65,
+    8191,
+    -8192,
+    i16::MIN as i32,
+    i16::MAX as i32,
+    i32::MIN,
+    i32::MIN + 1,
+    i32::MAX - 1,
+    i32::MAX,
+];
+
+/// A value that did not survive a VLQ round trip in [`fuzz_vlq`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VlqMismatch {
+    /// The value was written as a signed, zigzag encoded VLQ
+    pub signed: bool,
+    /// The value written
+    pub written: i64,
+    /// The value read back, `None` if the reader rejected the bytes
+    pub read: Option<i64>,
+}
+
+impl fmt::Display for VlqMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = if self.signed { "signed" } else { "unsigned" };
+        match self.read {
+            Some(read) => write!(f, "{} VLQ {} read back as {}", kind, self.written, read),
+            None => write!(f, "{} VLQ {} could not be read back", kind, self.written),
+        }
+    }
+}
+
+/// Writes the VLQ boundary values and `count` pseudo-random `u32` and `i32` values from
+/// `seed` with [`write_unsigned_vlq`] and [`write_signed_vlq`], one after another, reads
+/// them back with the decoder's readers and returns the first value that differs. `None`
+/// means every value round-tripped.
+///
+/// ```
+/// // The boundary values alone, i32::MIN, i32::MAX and u32::MAX among them
+/// assert_eq!(bbe_reader::fuzz_vlq(0, 1), None);
+/// assert_eq!(bbe_reader::fuzz_vlq(10_000, 1), None);
+/// ```
+pub fn fuzz_vlq(count: usize, seed: u64) -> Option<VlqMismatch> {
+    // xorshift64*, whose state must not be zero
+    let mut state = seed | 1;
+    let mut random = move || {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        (state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32) as u32
+    };
+    let mut unsigned = UNSIGNED_VLQ_BOUNDARIES.to_vec();
+    let mut signed = SIGNED_VLQ_BOUNDARIES.to_vec();
+    for _ in 0..count {
+        unsigned.push(random());
+        signed.push(random() as i32);
+    }
+
+    // Interleaved in one buffer, so a reader consuming the wrong number of bytes
+    // misreads every value after it
+    let mut data = Vec::new();
+    for (&unsigned, &signed) in unsigned.iter().zip(&signed) {
+        write_unsigned_vlq(&mut data, unsigned);
+        write_signed_vlq(&mut data, signed);
+    }
+    let mut cursor = 0;
+    for (&unsigned, &signed) in unsigned.iter().zip(&signed) {
+        let read = read_unsigned_vlq(&data, &mut cursor);
+        if read != Some(unsigned) {
+            return Some(VlqMismatch {
+                signed: false,
+                written: unsigned as i64,
+                read: read.map(i64::from),
+            });
+        }
+        let read = read_signed_vlq(&data, &mut cursor);
+        if read != Some(signed) {
+            return Some(VlqMismatch {
+                signed: true,
+                written: signed as i64,
+                read: read.map(i64::from),
+            });
+        }
+    }
+    None
+}
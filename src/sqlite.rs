@@ -0,0 +1,387 @@
+use std::io::{self, Write};
+
+use crate::header::{parse_header_line, BblLog};
+use crate::output::RecordSink;
+
+/// Size of every page of the database: the largest SQLite allows, so that rows with
+/// hundreds of columns fit on a page without overflow pages.
+const PAGE_SIZE: usize = 65536;
+
+/// Largest cell a table leaf page holds without spilling to overflow pages.
+const MAX_LOCAL_PAYLOAD: usize = PAGE_SIZE - 35;
+
+/// Most children of an interior page: every cell is a 4-byte page number, a rowid
+/// varint of at most 9 bytes and a 2-byte pointer, after the 12-byte page header.
+const INTERIOR_FANOUT: usize = (PAGE_SIZE - 12) / (4 + 9 + 2);
+
+/// B-tree page type flags
+const INTERIOR_TABLE: u8 = 0x05;
+const LEAF_TABLE: u8 = 0x0d;
+
+/// Writes the output as an SQLite database with a `frames` table of the rows, keyed by
+/// the header row, and a `headers` table with the name and value of each header line of
+/// the log. Cells are stored as integers or floats where they parse as one, empty cells
+/// as NULL and anything else as text; each column is declared with the widest of the
+/// types stored in it.
+///
+/// The schema comes first in the file and depends on every row, so the pages are kept
+/// in memory until [`RecordSink::finish`].
+pub(crate) struct SqliteSink<W: Write> {
+    writer: W,
+    header: Option<Vec<String>>,
+    /// Widest type stored in each column so far, `None` while it holds only NULLs
+    types: Vec<Option<ColumnType>>,
+    /// Pages after the first, which holds the schema
+    pages: Vec<Vec<u8>>,
+    headers_table: Table,
+    frames_table: Table,
+}
+
+impl<W: Write> SqliteSink<W> {
+    pub(crate) fn new(writer: W, log: &BblLog) -> Self {
+        let mut sink = SqliteSink {
+            writer,
+            header: None,
+            types: Vec::new(),
+            pages: Vec::new(),
+            headers_table: Table::default(),
+            frames_table: Table::default(),
+        };
+        for line in &log.headers {
+            let Some((name, value)) = parse_header_line(line) else {
+                continue;
+            };
+            let record = encode_record(&[Value::Text(name), Value::Text(value)]);
+            // A header line never comes near the size of a page
+            let _ = sink.headers_table.push(&mut sink.pages, &record);
+        }
+        sink
+    }
+}
+
+impl<W: Write> RecordSink for SqliteSink<W> {
+    fn write_record(&mut self, record: &[String]) -> io::Result<()> {
+        if self.header.is_none() {
+            self.header = Some(record.to_vec());
+            self.types = vec![None; record.len()];
+            return Ok(());
+        }
+        let values: Vec<Value> = record.iter().map(|cell| Value::parse(cell)).collect();
+        for (column_type, value) in self.types.iter_mut().zip(&values) {
+            let value_type = match value {
+                Value::Null => continue,
+                Value::Integer(_) => ColumnType::Integer,
+                Value::Real(_) => ColumnType::Real,
+                Value::Text(_) => ColumnType::Text,
+            };
+            *column_type = (*column_type).max(Some(value_type));
+        }
+        self.frames_table
+            .push(&mut self.pages, &encode_record(&values))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Nothing can be written before the schema is known
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let columns: Vec<String> = self
+            .header
+            .iter()
+            .flatten()
+            .zip(&self.types)
+            .map(|(name, column_type)| match column_type {
+                Some(column_type) => format!("{} {}", quote_identifier(name), column_type.name()),
+                None => quote_identifier(name),
+            })
+            .collect();
+        let frames_sql = format!("CREATE TABLE frames({})", columns.join(", "));
+        let headers_sql = "CREATE TABLE headers(name TEXT, value TEXT)";
+        let frames_root = self.frames_table.finish(&mut self.pages);
+        let headers_root = self.headers_table.finish(&mut self.pages);
+
+        let schema: Vec<Vec<u8>> = [
+            ("frames", frames_root, frames_sql.as_str()),
+            ("headers", headers_root, headers_sql),
+        ]
+        .into_iter()
+        .zip(1..)
+        .map(|((name, root, sql), rowid)| {
+            let record = encode_record(&[
+                Value::Text("table"),
+                Value::Text(name),
+                Value::Text(name),
+                Value::Integer(i64::from(root)),
+                Value::Text(sql),
+            ]);
+            table_leaf_cell(rowid, &record)
+        })
+        .collect();
+        let schema_len: usize = schema.iter().map(|cell| cell.len() + 2).sum();
+        if 100 + 8 + schema_len > PAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many columns for the SQLite schema page",
+            ));
+        }
+        let mut first_page = btree_page(LEAF_TABLE, &schema, None, 100);
+        first_page[..100].copy_from_slice(&database_header(1 + self.pages.len() as u32));
+
+        self.writer.write_all(&first_page)?;
+        for page in &self.pages {
+            self.writer.write_all(page)?;
+        }
+        self.writer.flush()
+    }
+}
+
+/// Declared type of a column, ordered from narrowest to widest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ColumnType {
+    Integer,
+    Real,
+    Text,
+}
+
+impl ColumnType {
+    fn name(self) -> &'static str {
+        match self {
+            ColumnType::Integer => "INTEGER",
+            ColumnType::Real => "REAL",
+            ColumnType::Text => "TEXT",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Value<'a> {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(&'a str),
+}
+
+impl<'a> Value<'a> {
+    fn parse(cell: &'a str) -> Self {
+        if cell.is_empty() {
+            Value::Null
+        } else if let Ok(value) = cell.parse::<i64>() {
+            Value::Integer(value)
+        } else if let Some(value) = cell.parse::<f64>().ok().filter(|value| value.is_finite()) {
+            Value::Real(value)
+        } else {
+            Value::Text(cell)
+        }
+    }
+}
+
+/// The leaf pages of one table, filled in rowid order, and the cells of the leaf being
+/// filled.
+#[derive(Default)]
+struct Table {
+    /// Page number and largest rowid of each full leaf
+    leaves: Vec<(u32, i64)>,
+    cells: Vec<Vec<u8>>,
+    cells_len: usize,
+    rowid: i64,
+}
+
+impl Table {
+    /// Appends a record as the next row, writing out the current leaf to `pages` first
+    /// when the record doesn't fit on it.
+    fn push(&mut self, pages: &mut Vec<Vec<u8>>, record: &[u8]) -> io::Result<()> {
+        if record.len() > MAX_LOCAL_PAYLOAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "a row of {} bytes is too long for an SQLite page",
+                    record.len()
+                ),
+            ));
+        }
+        self.rowid += 1;
+        let cell = table_leaf_cell(self.rowid, record);
+        if 8 + self.cells_len + cell.len() + 2 > PAGE_SIZE {
+            self.write_leaf(pages);
+        }
+        self.cells_len += cell.len() + 2;
+        self.cells.push(cell);
+        Ok(())
+    }
+
+    fn write_leaf(&mut self, pages: &mut Vec<Vec<u8>>) {
+        pages.push(btree_page(LEAF_TABLE, &self.cells, None, 0));
+        // The rows so far, minus the one being added
+        let last_rowid = self.rowid - 1;
+        self.leaves.push((page_number(pages), last_rowid));
+        self.cells.clear();
+        self.cells_len = 0;
+    }
+
+    /// Writes the last leaf and the interior pages above the leaves, and returns the
+    /// page number of the root.
+    fn finish(&mut self, pages: &mut Vec<Vec<u8>>) -> u32 {
+        if !self.cells.is_empty() || self.leaves.is_empty() {
+            pages.push(btree_page(LEAF_TABLE, &self.cells, None, 0));
+            self.leaves.push((page_number(pages), self.rowid));
+        }
+        let mut children = std::mem::take(&mut self.leaves);
+        while children.len() > 1 {
+            // Spread the children evenly so that no interior page is left with one
+            let parents = children.len().div_ceil(INTERIOR_FANOUT);
+            let per_parent = children.len().div_ceil(parents);
+            children = children
+                .chunks(per_parent)
+                .map(|group| {
+                    let (&(right_child, last_rowid), left) = group.split_last().unwrap();
+                    let cells: Vec<Vec<u8>> = left
+                        .iter()
+                        .map(|&(child, rowid)| {
+                            let mut cell = child.to_be_bytes().to_vec();
+                            write_varint(&mut cell, rowid as u64);
+                            cell
+                        })
+                        .collect();
+                    pages.push(btree_page(INTERIOR_TABLE, &cells, Some(right_child), 0));
+                    (page_number(pages), last_rowid)
+                })
+                .collect();
+        }
+        children[0].0
+    }
+}
+
+/// Page number of the last page in `pages`, which follow the schema page.
+fn page_number(pages: &[Vec<u8>]) -> u32 {
+    1 + pages.len() as u32
+}
+
+/// Lays out a b-tree page with its header at `header_offset`, the cell pointers after
+/// it and the cells packed at the end of the page.
+fn btree_page(
+    flag: u8,
+    cells: &[Vec<u8>],
+    right_child: Option<u32>,
+    header_offset: usize,
+) -> Vec<u8> {
+    let mut page = vec![0; PAGE_SIZE];
+    let header_len = if right_child.is_some() { 12 } else { 8 };
+    let mut content_start = PAGE_SIZE;
+    for (index, cell) in cells.iter().enumerate() {
+        content_start -= cell.len();
+        page[content_start..content_start + cell.len()].copy_from_slice(cell);
+        let pointer = header_offset + header_len + 2 * index;
+        page[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+    }
+    let header = &mut page[header_offset..header_offset + header_len];
+    header[0] = flag;
+    header[3..5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    // A content area starting at 65536 is stored as 0
+    header[5..7].copy_from_slice(&((content_start % PAGE_SIZE) as u16).to_be_bytes());
+    if let Some(right_child) = right_child {
+        header[8..12].copy_from_slice(&right_child.to_be_bytes());
+    }
+    page
+}
+
+/// The 100-byte header at the start of the database file.
+fn database_header(page_count: u32) -> [u8; 100] {
+    let mut header = [0; 100];
+    header[..16].copy_from_slice(b"SQLite format 3\0");
+    // 65536 doesn't fit the two bytes and is stored as 1
+    header[16..18].copy_from_slice(&1u16.to_be_bytes());
+    // Legacy (rollback journal) file format
+    header[18] = 1;
+    header[19] = 1;
+    // Maximum, minimum and leaf payload fractions, fixed by the format
+    header[21] = 64;
+    header[22] = 32;
+    header[23] = 32;
+    // File change counter
+    header[24..28].copy_from_slice(&1u32.to_be_bytes());
+    header[28..32].copy_from_slice(&page_count.to_be_bytes());
+    // Schema cookie and schema format number
+    header[40..44].copy_from_slice(&1u32.to_be_bytes());
+    header[44..48].copy_from_slice(&4u32.to_be_bytes());
+    // UTF-8 text encoding
+    header[56..60].copy_from_slice(&1u32.to_be_bytes());
+    // The change counter the page count is valid for, and the writing SQLite version
+    header[92..96].copy_from_slice(&1u32.to_be_bytes());
+    header[96..100].copy_from_slice(&3_045_000u32.to_be_bytes());
+    header
+}
+
+/// A table leaf cell: the record length, the rowid and the record.
+fn table_leaf_cell(rowid: i64, record: &[u8]) -> Vec<u8> {
+    let mut cell = Vec::with_capacity(record.len() + 6);
+    write_varint(&mut cell, record.len() as u64);
+    write_varint(&mut cell, rowid as u64);
+    cell.extend_from_slice(record);
+    cell
+}
+
+/// Encodes a row in the SQLite record format: the length of the record header, the
+/// serial type of each value, then the values.
+fn encode_record(values: &[Value]) -> Vec<u8> {
+    let mut types = Vec::new();
+    let mut body = Vec::new();
+    for value in values {
+        let serial_type = match *value {
+            Value::Null => 0,
+            Value::Integer(0) => 8,
+            Value::Integer(1) => 9,
+            Value::Integer(value) => {
+                let (serial_type, len) = match value {
+                    -0x80..=0x7f => (1, 1),
+                    -0x8000..=0x7fff => (2, 2),
+                    -0x80_0000..=0x7f_ffff => (3, 3),
+                    -0x8000_0000..=0x7fff_ffff => (4, 4),
+                    -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+                    _ => (6, 8),
+                };
+                body.extend_from_slice(&value.to_be_bytes()[8 - len..]);
+                serial_type
+            }
+            Value::Real(value) => {
+                body.extend_from_slice(&value.to_be_bytes());
+                7
+            }
+            Value::Text(value) => {
+                body.extend_from_slice(value.as_bytes());
+                value.len() as u64 * 2 + 13
+            }
+        };
+        write_varint(&mut types, serial_type);
+    }
+    // The header length counts its own varint
+    let mut header_len = types.len() + 1;
+    while types.len() + varint_len(header_len as u64) > header_len {
+        header_len = types.len() + varint_len(header_len as u64);
+    }
+    let mut record = Vec::with_capacity(header_len + body.len());
+    write_varint(&mut record, header_len as u64);
+    record.extend_from_slice(&types);
+    record.extend_from_slice(&body);
+    record
+}
+
+/// Writes an SQLite varint: big-endian groups of 7 bits, the high bit set on all but
+/// the last byte. Lengths and rowids here stay far below the 2^56 that would need the
+/// 9-byte form.
+fn write_varint(out: &mut Vec<u8>, value: u64) {
+    let len = varint_len(value);
+    for index in (1..len).rev() {
+        out.push(0x80 | ((value >> (7 * index)) as u8 & 0x7f));
+    }
+    out.push(value as u8 & 0x7f);
+}
+
+fn varint_len(value: u64) -> usize {
+    (64 - value.leading_zeros() as usize).div_ceil(7).max(1)
+}
+
+/// Quotes a column name for SQL, doubling any quotes in it.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
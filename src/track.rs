@@ -0,0 +1,281 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::header::{BblLog, FieldDefinition};
+
+/// The file formats a GPS track can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TrackFormat {
+    /// A GPX `<trk>` with a `<trkseg>` per session
+    Gpx,
+    /// A KML document with a `<LineString>` placemark per session
+    Kml,
+}
+
+/// Writes the GPS frames as a track, one segment per session. `time` counts from boot
+/// and `Log start datetime` is when the session's first main frame was logged, so GPX
+/// points are placed at the GPS frame's `time` since that frame after the header. They
+/// are written without a time when the log does not record when it started.
+pub(crate) struct TrackWriter {
+    writer: BufWriter<File>,
+    format: TrackFormat,
+    /// Positions of the fields a track point is built from in the current session
+    fields: Option<TrackFields>,
+    /// Unix time in µs the current session started at
+    log_start: Option<i64>,
+    /// A segment is open
+    in_segment: bool,
+}
+
+/// Positions of the track point fields in a GPS frame.
+#[derive(Debug, Clone, Copy)]
+struct TrackFields {
+    time: Option<usize>,
+    latitude: usize,
+    longitude: usize,
+    altitude: Option<usize>,
+}
+
+impl TrackFields {
+    fn find(fields: &[FieldDefinition]) -> Option<Self> {
+        let position = |name| fields.iter().position(|field| field.name == name);
+        Some(TrackFields {
+            time: position("time"),
+            latitude: position("GPS_coord[0]")?,
+            longitude: position("GPS_coord[1]")?,
+            altitude: position("GPS_altitude"),
+        })
+    }
+}
+
+impl TrackWriter {
+    pub(crate) fn create(file: File, format: TrackFormat) -> io::Result<Self> {
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        match format {
+            TrackFormat::Gpx => {
+                writeln!(
+                    writer,
+                    r#"<gpx version="1.1" creator="bbe_reader_again" xmlns="http://www.topografix.com/GPX/1/1">"#
+                )?;
+                writeln!(writer, "<trk>")?;
+            }
+            TrackFormat::Kml => {
+                writeln!(writer, r#"<kml xmlns="http://www.opengis.net/kml/2.2">"#)?;
+                writeln!(writer, "<Document>")?;
+            }
+        }
+        Ok(TrackWriter {
+            writer,
+            format,
+            fields: None,
+            log_start: None,
+            in_segment: false,
+        })
+    }
+
+    /// Ends the segment of the previous session and takes the GPS layout and start
+    /// time of `log`.
+    pub(crate) fn start_session(&mut self, log: &BblLog) -> io::Result<()> {
+        self.end_segment()?;
+        let gps_fields = log.layouts.get(&b'G').map(Vec::as_slice);
+        self.fields = gps_fields.and_then(TrackFields::find);
+        self.log_start = log
+            .header_value("Log start datetime")
+            .and_then(parse_log_start);
+        if gps_fields.is_some() && self.fields.is_none() {
+            eprintln!("Warning: the GPS frames have no GPS_coord[0] and GPS_coord[1] fields");
+        }
+        Ok(())
+    }
+
+    /// Writes a track point for a GPS frame, unless it has no position fix.
+    /// `first_main_time` is the `time` of the session's first main frame.
+    pub(crate) fn write_frame(
+        &mut self,
+        values: &[i64],
+        first_main_time: Option<i64>,
+    ) -> io::Result<()> {
+        let Some(fields) = self.fields else {
+            return Ok(());
+        };
+        let (latitude, longitude) = (values[fields.latitude], values[fields.longitude]);
+        if latitude == 0 && longitude == 0 {
+            return Ok(());
+        }
+        if !self.in_segment {
+            match self.format {
+                TrackFormat::Gpx => writeln!(self.writer, "<trkseg>")?,
+                TrackFormat::Kml => {
+                    writeln!(self.writer, "<Placemark>")?;
+                    writeln!(self.writer, "<LineString>")?;
+                    writeln!(self.writer, "<altitudeMode>absolute</altitudeMode>")?;
+                    writeln!(self.writer, "<coordinates>")?;
+                }
+            }
+            self.in_segment = true;
+        }
+        // Logged in decimetres
+        let altitude = fields
+            .altitude
+            .map(|altitude| values[altitude] as f64 / 10.0);
+        match self.format {
+            TrackFormat::Gpx => {
+                write!(
+                    self.writer,
+                    r#"<trkpt lat="{}" lon="{}">"#,
+                    format_degrees(latitude),
+                    format_degrees(longitude)
+                )?;
+                if let Some(altitude) = altitude {
+                    write!(self.writer, "<ele>{:.1}</ele>", altitude)?;
+                }
+                if let (Some(start), Some(time), Some(first_main_time)) =
+                    (self.log_start, fields.time, first_main_time)
+                {
+                    write!(
+                        self.writer,
+                        "<time>{}</time>",
+                        format_utc(start + values[time] - first_main_time)
+                    )?;
+                }
+                writeln!(self.writer, "</trkpt>")
+            }
+            TrackFormat::Kml => {
+                // Longitude comes first in KML
+                write!(
+                    self.writer,
+                    "{},{}",
+                    format_degrees(longitude),
+                    format_degrees(latitude)
+                )?;
+                if let Some(altitude) = altitude {
+                    write!(self.writer, ",{:.1}", altitude)?;
+                }
+                writeln!(self.writer)
+            }
+        }
+    }
+
+    /// Closes the document.
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        self.end_segment()?;
+        match self.format {
+            TrackFormat::Gpx => {
+                writeln!(self.writer, "</trk>")?;
+                writeln!(self.writer, "</gpx>")?;
+            }
+            TrackFormat::Kml => {
+                writeln!(self.writer, "</Document>")?;
+                writeln!(self.writer, "</kml>")?;
+            }
+        }
+        self.writer.flush()
+    }
+
+    fn end_segment(&mut self) -> io::Result<()> {
+        if std::mem::take(&mut self.in_segment) {
+            match self.format {
+                TrackFormat::Gpx => writeln!(self.writer, "</trkseg>")?,
+                TrackFormat::Kml => {
+                    writeln!(self.writer, "</coordinates>")?;
+                    writeln!(self.writer, "</LineString>")?;
+                    writeln!(self.writer, "</Placemark>")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Formats a coordinate logged in 1e-7 degrees as decimal degrees.
+fn format_degrees(value: i64) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let value = value.unsigned_abs();
+    format!("{}{}.{:07}", sign, value / 10_000_000, value % 10_000_000)
+}
+
+/// Parses a `Log start datetime` header, e.g. `2024-05-12T10:20:30.123+02:00`, into
+/// Unix time in µs. Returns `None` for the year-0 date firmware logs without a clock.
+fn parse_log_start(value: &str) -> Option<i64> {
+    let (date, time) = value.trim().split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    if year == 0 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // The UTC offset follows the seconds as `Z` or `+HH:MM`/`-HH:MM`
+    let (clock, offset) = match time.find(['Z', '+', '-']) {
+        Some(at) => time.split_at(at),
+        None => (time, ""),
+    };
+    let offset_minutes = match offset.split_at_checked(1) {
+        None | Some(("Z", "")) => 0,
+        Some((sign, offset)) => {
+            let (hours, minutes) = offset.split_once(':').unwrap_or((offset, "0"));
+            let minutes = hours.parse::<i64>().ok()? * 60 + minutes.parse::<i64>().ok()?;
+            if sign == "-" {
+                -minutes
+            } else {
+                minutes
+            }
+        }
+    };
+    let mut clock = clock.splitn(3, ':');
+    let (hours, minutes) = (
+        clock.next()?.parse::<i64>().ok()?,
+        clock.next()?.parse::<i64>().ok()?,
+    );
+    let seconds: f64 = clock.next().unwrap_or("0").parse().ok()?;
+
+    let minutes =
+        days_from_civil(year, month, day) * 24 * 60 + hours * 60 + minutes - offset_minutes;
+    Some(minutes * 60_000_000 + (seconds * 1_000_000.0).round() as i64)
+}
+
+/// Formats Unix time in µs as an ISO 8601 UTC time with milliseconds.
+fn format_utc(micros: i64) -> String {
+    let millis = micros.div_euclid(1000);
+    let (days, millis) = (millis.div_euclid(86_400_000), millis.rem_euclid(86_400_000));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Days from 1970-01-01 to a date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The date `days` after 1970-01-01, as year, month and day.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
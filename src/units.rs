@@ -0,0 +1,138 @@
+use crate::header::header_value;
+
+/// A conversion from a raw field value to a physical unit.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Scale {
+    pub(crate) factor: f64,
+    /// Added after the factor is applied
+    pub(crate) offset: f64,
+    pub(crate) unit: &'static str,
+    /// Appended to the field name when the scaled column is written next to the raw one
+    pub(crate) suffix: &'static str,
+}
+
+/// Parses a `H gyro_scale:` value, either as the hex bit pattern of an IEEE-754 float
+/// (`0x3f800000`) or as a plain decimal number.
+pub fn parse_gyro_scale(value: &str) -> Option<f32> {
+    let value = value.trim();
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok().map(f32::from_bits),
+        None => value.parse().ok(),
+    }
+}
+
+/// Returns the factor converting raw gyro readings to deg/s.
+///
+/// Baseflight logs a scale that yields rad/µs, while Cleanflight-derived firmware
+/// (Betaflight, INAV) logs one that already yields deg/s.
+fn gyro_degrees_per_second(headers: &[String]) -> Option<f64> {
+    let scale = parse_gyro_scale(header_value(headers, "gyro_scale")?)? as f64;
+    if header_value(headers, "Firmware type") == Some("Baseflight") {
+        Some(scale * 1_000_000.0 * 180.0 / std::f64::consts::PI)
+    } else {
+        Some(scale)
+    }
+}
+
+/// Returns the largest raw `rssi` value. Cleanflight-derived firmware scales RSSI to
+/// 0–1023 whatever its source; other firmware is assumed to log the 8-bit 0–255 value.
+fn rssi_max(headers: &[String]) -> f64 {
+    match header_value(headers, "Firmware type") {
+        None | Some("Cleanflight") => 1023.0,
+        Some(_) => 255.0,
+    }
+}
+
+/// Returns the physical-unit conversion for a field when the headers provide one.
+///
+/// Gyro fields use `gyro_scale`, accelerometer fields are divided by `acc_1G`,
+/// `vbatLatest` is logged in 0.01 V steps, `attitude` in 0.1 degree steps, and `rssi` is
+/// converted to a percentage.
+pub(crate) fn physical_scale(name: &str, headers: &[String]) -> Option<Scale> {
+    if name.starts_with("gyroADC[") {
+        Some(Scale {
+            factor: gyro_degrees_per_second(headers)?,
+            offset: 0.0,
+            unit: "deg/s",
+            suffix: "degps",
+        })
+    } else if name.starts_with("accSmooth[") {
+        let acc_1g: f64 = header_value(headers, "acc_1G")?.parse().ok()?;
+        if acc_1g == 0.0 {
+            return None;
+        }
+        Some(Scale {
+            factor: 1.0 / acc_1g,
+            offset: 0.0,
+            unit: "g",
+            suffix: "g",
+        })
+    } else if name == "rssi" {
+        Some(Scale {
+            factor: 100.0 / rssi_max(headers),
+            offset: 0.0,
+            unit: "%",
+            suffix: "pct",
+        })
+    } else if name.starts_with("attitude[") {
+        Some(Scale {
+            factor: 0.1,
+            offset: 0.0,
+            unit: "deg",
+            suffix: "deg",
+        })
+    } else if name == "vbatLatest" {
+        Some(Scale {
+            factor: 0.01,
+            offset: 0.0,
+            unit: "V",
+            suffix: "V",
+        })
+    } else {
+        None
+    }
+}
+
+/// Returns the conversion of an `rcCommand` field to the receiver pulse width in µs.
+///
+/// Roll, pitch and yaw are logged as the stick deflection from the 1500 µs center, in
+/// -500 to 500, while throttle (`rcCommand[3]`) is logged on its own 1000 to 2000 µs
+/// range, so only the stick axes are offset.
+pub(crate) fn rc_pulse_scale(name: &str) -> Option<Scale> {
+    let offset = match name {
+        "rcCommand[0]" | "rcCommand[1]" | "rcCommand[2]" => 1500.0,
+        "rcCommand[3]" => 0.0,
+        _ => return None,
+    };
+    Some(Scale {
+        factor: 1.0,
+        offset,
+        unit: "µs",
+        suffix: "us",
+    })
+}
+
+/// Returns the header a field's physical-unit conversion is computed from, for the
+/// fields whose conversion needs one: `gyro_scale` for gyro and `acc_1G` for
+/// accelerometer fields.
+pub(crate) fn scale_header(name: &str) -> Option<&'static str> {
+    if name.starts_with("gyroADC[") {
+        Some("gyro_scale")
+    } else if name.starts_with("accSmooth[") {
+        Some("acc_1G")
+    } else {
+        None
+    }
+}
+
+/// Returns the unit of a decoded field as written to the CSV.
+pub(crate) fn field_unit(name: &str) -> &'static str {
+    match name {
+        "loopIteration" => "count",
+        "time" => "µs",
+        _ => "raw",
+    }
+}
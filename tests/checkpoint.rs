@@ -0,0 +1,132 @@
+//! Decodes a log of two sessions with checkpoints, stops the output partway through the
+//! second session as a crash would, resumes from the last checkpoint and checks the
+//! result against an uninterrupted decode, byte for byte.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use bbe_reader::{Checkpoint, DecodeOptions};
+
+use common::{gyro, push_signed, push_unsigned};
+
+mod common;
+
+const HEADERS: &str = "\
+H Product:Blackbox flight data recorder by Nicholas Sherlock
+H I interval:32
+H Field I name:loopIteration,time,gyroADC[0]
+H Field I signed:0,0,1
+H Field I encoding:1,1,0
+H Field P predictor:6,1,1
+H Field P encoding:0,0,0
+";
+
+const FRAMES_PER_SESSION: i64 = 10_000;
+
+/// Two sessions, each an I-frame every 32 frames with P-frames 125 µs apart between
+/// them, the first ending with an end-of-log event.
+fn two_session_log() -> Vec<u8> {
+    let mut log = Vec::new();
+    for session in 0..2 {
+        log.extend_from_slice(HEADERS.as_bytes());
+        for k in 0..FRAMES_PER_SESSION {
+            if k % 32 == 0 {
+                log.push(b'I');
+                push_unsigned(&mut log, k as u64);
+                push_unsigned(&mut log, (1000 + 125 * k) as u64);
+                push_signed(&mut log, gyro(k));
+            } else {
+                log.push(b'P');
+                push_signed(&mut log, 0);
+                push_signed(&mut log, 125);
+                push_signed(&mut log, gyro(k) - gyro(k - 1));
+            }
+        }
+        if session == 0 {
+            log.extend_from_slice(b"E\xffEnd of log\0");
+        }
+    }
+    log
+}
+
+/// Writes to a file until `limit` bytes are written, then fails as if the process
+/// had died there.
+struct CrashingWriter {
+    file: File,
+    limit: usize,
+}
+
+impl Write for CrashingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.limit == 0 {
+            return Err(io::Error::other("crashed"));
+        }
+        let len = buf.len().min(self.limit);
+        self.limit -= len;
+        self.file.write(&buf[..len])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("bbe_checkpoint_{}_{}", std::process::id(), name))
+}
+
+#[test]
+fn resumed_decode_matches_uninterrupted_decode() {
+    let log = two_session_log();
+    let options = DecodeOptions {
+        fields: vec![
+            "loopIteration".to_string(),
+            "time".to_string(),
+            "gyroADC[0]".to_string(),
+        ],
+        sample_index: true,
+        ..DecodeOptions::default()
+    };
+    let mut expected = Vec::new();
+    bbe_reader::decode_to_writer(&log[..], &mut expected, options.clone()).unwrap();
+
+    let output_path = temp_path("output.csv");
+    let checkpoint_path = temp_path("output.csv.checkpoint");
+    let options = DecodeOptions {
+        checkpoint_out: Some(checkpoint_path.clone()),
+        checkpoint_interval: 1000,
+        ..options
+    };
+
+    // The output stops three quarters of the way through, in the second session
+    let crashing = CrashingWriter {
+        file: File::create(&output_path).unwrap(),
+        limit: expected.len() * 3 / 4,
+    };
+    let _ = bbe_reader::decode_to_writer(&log[..], crashing, options.clone());
+
+    let checkpoint = Checkpoint::read(&checkpoint_path).unwrap();
+    assert_eq!(checkpoint.session, 1);
+    assert!(checkpoint.rows_written > FRAMES_PER_SESSION as usize);
+    assert_eq!(log[checkpoint.offset], b'I');
+
+    let mut output = File::options()
+        .read(true)
+        .write(true)
+        .open(&output_path)
+        .unwrap();
+    checkpoint.truncate_output(&mut output, 1).unwrap();
+    let options = DecodeOptions {
+        resume: Some(checkpoint),
+        ..options
+    };
+    let stats = bbe_reader::decode_to_writer(&log[..], &mut output, options).unwrap();
+    drop(output);
+
+    let resumed = std::fs::read(&output_path).unwrap();
+    std::fs::remove_file(&output_path).unwrap();
+    std::fs::remove_file(&checkpoint_path).unwrap();
+    assert_eq!(stats.rows_written, 2 * FRAMES_PER_SESSION as usize);
+    assert!(resumed == expected, "the resumed output differs");
+}
@@ -0,0 +1,378 @@
+//! Runs the command-line program on logs written to a temporary directory and checks
+//! the files it leaves there.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use common::{fixture, push_unsigned, Log};
+
+mod common;
+
+const BIN: &str = env!("CARGO_BIN_EXE_bbe_reader_again");
+
+/// Creates an empty directory for one test to run the program in.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bbe_cli_{}_{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn unsigned_field_past_i32_max_is_written_without_overflow() {
+    let fields = [
+        "loopIteration",
+        "time",
+        "axisP[0]",
+        "axisP[1]",
+        "axisP[2]",
+        "axisI[0]",
+        "axisI[1]",
+        "axisI[2]",
+        "axisD[0]",
+        "axisD[1]",
+        "axisF[0]",
+        "axisF[1]",
+        "axisF[2]",
+    ];
+    let mut log = Vec::new();
+    log.extend_from_slice(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n");
+    log.extend_from_slice(format!("H Field I name:{}\n", fields.join(",")).as_bytes());
+    log.extend_from_slice(format!("H Field I signed:{}\n", ["0"; 13].join(",")).as_bytes());
+    log.extend_from_slice(format!("H Field I predictor:{}\n", ["0"; 13].join(",")).as_bytes());
+    log.extend_from_slice(format!("H Field I encoding:{}\n", ["1"; 13].join(",")).as_bytes());
+    // Two keyframes of unsigned values with 2^31, which is i32::MIN when cast, in
+    // axisP[1]. The time of 10 is a line feed byte.
+    for iteration in 200..202 {
+        log.push(b'I');
+        for value in [iteration, 10, 0, 1 << 31, 0, 0, 0, 0, 0, 0, 0, 0, 0] {
+            push_unsigned(&mut log, value);
+        }
+    }
+
+    let dir = temp_dir("unsigned");
+    std::fs::write(dir.join("unsigned.bbl"), &log).unwrap();
+    let status = Command::new(BIN)
+        .current_dir(&dir)
+        .args(["--input", "unsigned.bbl"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let csv = std::fs::read_to_string(dir.join("unsigned.csv")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(csv
+        .lines()
+        .skip(1)
+        .any(|line| line.split(',').any(|cell| cell == "2147483648")));
+}
+
+/// Serves `tests/fixtures/golden.bbl` at `/golden.bbl`, a redirect to it at `/moved.bbl`
+/// and a 404 for every other path, returning the server's base URL.
+#[cfg(feature = "http")]
+fn serve_golden_log() -> String {
+    use std::io::{BufRead, BufReader, Write};
+
+    let log = std::fs::read(fixture("golden.bbl")).unwrap();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = String::new();
+            let mut reader = BufReader::new(&stream);
+            reader.read_line(&mut request).unwrap();
+            // Read the rest of the request headers up to the blank line
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let target = request.split_whitespace().nth(1).unwrap_or("");
+            let path = target.split('?').next().unwrap();
+            let (status, headers, body) = match path {
+                "/golden.bbl" => ("200 OK", String::new(), &log[..]),
+                "/moved.bbl" => (
+                    "302 Found",
+                    "Location: /golden.bbl\r\n".to_string(),
+                    &[][..],
+                ),
+                _ => ("404 Not Found", String::new(), &b"not found"[..]),
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                headers,
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(body).unwrap();
+        }
+    });
+    base
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn url_input_is_downloaded_and_decoded() {
+    let base = serve_golden_log();
+    let expected = std::fs::read_to_string(fixture("golden.csv")).unwrap();
+
+    for path in ["golden.bbl", "moved.bbl"] {
+        let dir = temp_dir(&format!("url_{}", path));
+        let url = format!("{}/{}?download=1", base, path);
+        let status = Command::new(BIN)
+            .current_dir(&dir)
+            .args(["--input", &url, "--all-fields"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // The output is named after the last path segment without the query string
+        let stem = path.trim_end_matches(".bbl");
+        let csv = std::fs::read_to_string(dir.join(format!("{}.csv", stem))).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(csv, expected);
+    }
+
+    let dir = temp_dir("url_missing");
+    let output = Command::new(BIN)
+        .current_dir(&dir)
+        .args(["--input", &format!("{}/missing.bbl", base)])
+        .output()
+        .unwrap();
+    let files = std::fs::read_dir(&dir).unwrap().count();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("HTTP 404"));
+    assert_eq!(files, 0);
+}
+
+/// Sorted names of the files in `dir`.
+fn file_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn side_files_are_written_next_to_the_output() {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.header("Field H name", "GPS_home[0],GPS_home[1]");
+    log.header("Field H signed", "1,1");
+    log.header("Field H encoding", "0,0");
+    log.header("Field G name", "time,GPS_numSat,GPS_coord[0],GPS_coord[1]");
+    log.header("Field G signed", "0,0,1,1");
+    log.header("Field G predictor", "10,0,7,7");
+    log.header("Field G encoding", "1,1,0,0");
+    log.extend_from_slice(&[b'I', 0, 0xe8, 0x07, b'H', 0xe8, 0x07, 0xd7, 0x04]);
+    log.extend_from_slice(&[b'G', 0x14, 9, 0x06, 0x03]);
+
+    let dir = temp_dir("side_files");
+    let work = dir.join("work");
+    for subdir in [&work, &dir.join("outdir"), &dir.join("renamed")] {
+        std::fs::create_dir(subdir).unwrap();
+    }
+    std::fs::write(work.join("flight.bbl"), &*log).unwrap();
+    let side_files = ["--with-preview", "--gps-out", "--meta"];
+
+    // A directory output names every file after the input
+    let status = Command::new(BIN)
+        .current_dir(&work)
+        .args(["--input", "flight.bbl", "-o", "../outdir/"])
+        .args(side_files)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let outdir = file_names(&dir.join("outdir"));
+
+    // A file output names them after itself
+    let status = Command::new(BIN)
+        .current_dir(&work)
+        .args(["--input", "flight.bbl", "-o", "../renamed/run.csv"])
+        .args(side_files)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let renamed = file_names(&dir.join("renamed"));
+    let work = file_names(&work);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        outdir,
+        [
+            "flight.csv",
+            "flight.gps.csv",
+            "flight.meta.json",
+            "flight.preview.csv"
+        ]
+    );
+    assert_eq!(
+        renamed,
+        ["run.csv", "run.gps.csv", "run.meta.json", "run.preview.csv"]
+    );
+    assert_eq!(work, ["flight.bbl"]);
+}
+
+#[test]
+fn field_indices_select_fields_by_position() {
+    let dir = temp_dir("field_indices");
+    std::fs::copy(fixture("golden.bbl"), dir.join("golden.bbl")).unwrap();
+
+    let status = Command::new(BIN)
+        .current_dir(&dir)
+        .args(["--input", "golden.bbl", "--field-indices", "4,0"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let csv = std::fs::read_to_string(dir.join("golden.csv")).unwrap();
+    std::fs::remove_file(dir.join("golden.csv")).unwrap();
+
+    // The log has 8 fields, so 8 is one past the last
+    let output = Command::new(BIN)
+        .current_dir(&dir)
+        .args(["--input", "golden.bbl", "--field-indices", "0,8"])
+        .output()
+        .unwrap();
+    let files = file_names(&dir);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // gyroADC[0] and loopIteration of every golden row
+    let golden = std::fs::read_to_string(fixture("golden.csv")).unwrap();
+    let expected: String = golden
+        .lines()
+        .map(|line| {
+            let cells: Vec<&str> = line.split(',').collect();
+            format!("{},{}\n", cells[4], cells[0])
+        })
+        .collect();
+    assert!(expected.starts_with("gyroADC[0],loopIteration\n"));
+    assert_eq!(csv, expected);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("field index 8 is out of range, the log has 8 fields (0-7)"));
+    assert_eq!(files, ["golden.bbl"]);
+}
+
+#[test]
+fn input_list_decodes_each_listed_input() {
+    let dir = temp_dir("input_list");
+    std::fs::create_dir(dir.join("logs")).unwrap();
+    std::fs::create_dir(dir.join("out")).unwrap();
+    for name in ["first.bbl", "second.bbl"] {
+        std::fs::copy(fixture("golden.bbl"), dir.join("logs").join(name)).unwrap();
+    }
+    // The commented-out input does not exist and would fail the run if decoded
+    let list =
+        "# flights of the day\n\nlogs/first.bbl\n   \n# logs/missing.bbl\n  logs/second.bbl  \n";
+    std::fs::write(dir.join("inputs.txt"), list).unwrap();
+
+    let status = Command::new(BIN)
+        .current_dir(&dir)
+        .args([
+            "--input-list",
+            "inputs.txt",
+            "--all-fields",
+            "-o",
+            "out/{stem}.csv",
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let outputs = file_names(&dir.join("out"));
+    let first = std::fs::read_to_string(dir.join("out/first.csv")).unwrap();
+    let second = std::fs::read_to_string(dir.join("out/second.csv")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let golden = std::fs::read_to_string(fixture("golden.csv")).unwrap();
+    assert_eq!(outputs, ["first.csv", "second.csv"]);
+    assert_eq!(first, golden);
+    assert_eq!(second, golden);
+}
+
+/// Parses a `{"bytes":..,"frames":..,"percent":..}` progress line.
+fn parse_progress(line: &str) -> (usize, usize, f64) {
+    let fields = line
+        .strip_prefix('{')
+        .and_then(|line| line.strip_suffix('}'))
+        .unwrap_or_else(|| panic!("not a JSON object: {}", line));
+    let values: Vec<(&str, &str)> = fields
+        .split(',')
+        .map(|field| field.split_once(':').unwrap())
+        .collect();
+    let [("\"bytes\"", bytes), ("\"frames\"", frames), ("\"percent\"", percent)] = values[..]
+    else {
+        panic!("unexpected progress fields: {}", line);
+    };
+    (
+        bytes.parse().unwrap(),
+        frames.parse().unwrap(),
+        percent.parse().unwrap(),
+    )
+}
+
+#[test]
+fn progress_json_lines_count_up_to_the_whole_input() {
+    let output = Command::new(BIN)
+        .args(["--input"])
+        .arg(fixture("golden.bbl"))
+        .args(["--progress-json", "--decimate", "4", "-o", "-"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let progress: Vec<(usize, usize, f64)> = stderr
+        .lines()
+        .filter(|line| line.starts_with('{'))
+        .map(parse_progress)
+        .collect();
+    assert!(progress.len() > 1);
+    assert!(progress.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    assert!(progress.windows(2).all(|pair| pair[0].2 < pair[1].2));
+    // Every main frame is counted, not only the quarter of them written
+    let (_, frames, percent) = *progress.last().unwrap();
+    assert_eq!(frames, 16);
+    assert_eq!(percent, 100.0);
+    let rows = String::from_utf8(output.stdout).unwrap().lines().count() - 1;
+    assert_eq!(rows, 4);
+}
+
+#[test]
+fn columns_from_writes_the_reference_log_columns() {
+    // A reference log from another firmware, whose frames are not needed
+    let mut reference = Log::new();
+    reference.header("Field I name", "time,gyroADC[0],debug[0],loopIteration");
+    reference.header("Field I encoding", "1,0,0,1");
+
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time,gyroADC[0]");
+    log.header("Field I signed", "0,0,1");
+    log.header("Field I encoding", "1,1,0");
+    // gyroADC[0] of -3, then 5
+    log.extend_from_slice(&[b'I', 0, 100, 0x05, b'I', 1, 0xc8, 0x01, 0x0a]);
+
+    let dir = temp_dir("columns_from");
+    std::fs::write(dir.join("ref.bbl"), &*reference).unwrap();
+    std::fs::write(dir.join("flight.bbl"), &*log).unwrap();
+    let status = Command::new(BIN)
+        .current_dir(&dir)
+        .args(["--input", "flight.bbl", "--columns-from", "ref.bbl"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let csv = std::fs::read_to_string(dir.join("flight.csv")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // In the reference order, with debug[0], which the input lacks, left empty
+    assert_eq!(
+        csv,
+        "time,gyroADC[0],debug[0],loopIteration\n100,-3,,0\n200,5,,1\n"
+    );
+}
@@ -0,0 +1,83 @@
+//! Helpers shared by the integration tests for writing blackbox logs.
+
+// Each test file uses only some of the helpers
+#![allow(dead_code)]
+
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+
+use bbe_reader::{BblError, DecodeOptions};
+
+/// The path of a log or CSV checked in under `tests/fixtures`.
+pub fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// A path in the temporary directory named after this test process, so test runs in
+/// parallel don't write each other's files.
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("bbe_reader_{}_{}", std::process::id(), name))
+}
+
+pub fn push_unsigned(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+pub fn push_signed(bytes: &mut Vec<u8>, value: i64) {
+    push_unsigned(bytes, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+/// The value of `gyroADC[0]` in frame `k`, which wanders too much for a straight line
+/// through the two frames before it to predict.
+pub fn gyro(k: i64) -> i64 {
+    (k * k) % 2001 - 1000
+}
+
+/// A log written for a test: the `H Product:` line every log starts with, the headers
+/// added with [`Log::header`] and whatever bytes are pushed after them.
+pub struct Log(Vec<u8>);
+
+impl Log {
+    pub fn new() -> Self {
+        Log(b"H Product:Blackbox flight data recorder by Nicholas Sherlock\n".to_vec())
+    }
+
+    /// Appends an `H name:value` header line.
+    pub fn header(&mut self, name: &str, value: &str) {
+        self.0
+            .extend_from_slice(format!("H {}:{}\n", name, value).as_bytes());
+    }
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Log::new()
+    }
+}
+
+impl Deref for Log {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.0
+    }
+}
+
+impl DerefMut for Log {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.0
+    }
+}
+
+/// Decodes `log` with `options` into CSV text.
+pub fn decode_csv(log: &[u8], options: DecodeOptions) -> Result<String, BblError> {
+    let mut csv = Vec::new();
+    bbe_reader::decode_to_writer(log, &mut csv, options)?;
+    Ok(String::from_utf8(csv).unwrap())
+}
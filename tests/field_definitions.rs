@@ -0,0 +1,401 @@
+//! Decodes small logs written with `common::Log` to check how each field encoding,
+//! signedness and predictor is read, and how header problems are handled.
+
+use bbe_reader::{BblError, BblLog, HeaderOptions};
+
+use common::{decode_csv, Log};
+
+mod common;
+
+#[test]
+fn tag8_8svb_stores_only_non_zero_fields() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "gyroADC[0],gyroADC[1],gyroADC[2]");
+    log.header("Field I encoding", "6,6,6");
+    // Fields 0 and 2 are non-zero: 5 and -3
+    log.extend_from_slice(&[b'I', 0b101, 0x0a, 0x05]);
+
+    let options = bbe_reader::DecodeOptions {
+        fields: "gyroADC[0],gyroADC[1],gyroADC[2]"
+            .split(',')
+            .map(String::from)
+            .collect(),
+        ..Default::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "gyroADC[0],gyroADC[1],gyroADC[2]\n5,0,-3\n");
+    Ok(())
+}
+
+#[test]
+fn tag8_8svb_zero_fields_still_go_through_their_predictor() -> Result<(), Box<dyn std::error::Error>>
+{
+    // A field whose bit is clear stores a zero, which still goes through its predictor.
+    // in a P-frame, a field predicted from the previous frame carries its value forward,
+    // and one with no predictor is 0.
+    let mut log = Log::new();
+    log.header(
+        "Field I name",
+        "loopIteration,gyroADC[0],gyroADC[1],debug[0]",
+    );
+    log.header("Field I encoding", "1,0,0,0");
+    log.header("Field P predictor", "6,1,1,0");
+    log.header("Field P encoding", "0,6,6,6");
+    log.extend_from_slice(&[b'I', 0, 0x14, 0x28, 0x3c]);
+    // Only gyroADC[0] is flagged, 2 up
+    log.extend_from_slice(&[b'P', 0, 0b001, 0x04]);
+
+    let options = bbe_reader::DecodeOptions {
+        fields: "gyroADC[0],gyroADC[1],debug[0]"
+            .split(',')
+            .map(String::from)
+            .collect(),
+        ..Default::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "gyroADC[0],gyroADC[1],debug[0]\n10,20,30\n12,20,0\n");
+    Ok(())
+}
+
+#[test]
+fn tag2_3s32_packs_three_fields() -> Result<(), Box<dyn std::error::Error>> {
+    // TAG2_3S32 (7) packs 3 fields into as few bytes as their largest value allows.
+    let mut log = Log::new();
+    log.header("Field I name", "axisD[0],axisD[1],axisD[2]");
+    log.header("Field I encoding", "7,7,7");
+    // 2 bits each, then 8, 16 and 32 bits little-endian
+    log.extend_from_slice(&[
+        b'I', 0x1e, b'I', 0xf4, 0x9c, 0xe8, 0x03, 0x90, 0xee, 0xfe, 0xff,
+    ]);
+
+    let options = bbe_reader::DecodeOptions {
+        fields: "axisD[0],axisD[1],axisD[2]"
+            .split(',')
+            .map(String::from)
+            .collect(),
+        ..Default::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(
+        csv,
+        "axisD[0],axisD[1],axisD[2]\n1,-1,-2\n-100,1000,-70000\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn tag8_4s16_packs_four_fields_by_nibble() -> Result<(), Box<dyn std::error::Error>> {
+    // TAG8_4S16 (8) gives each of 4 fields zero, 4, 8 or 16 bits, packed as nibbles.
+    let mut log = Log::new();
+    log.header("Field I name", "motor[0],motor[1],motor[2],motor[3]");
+    log.header("Field I encoding", "8,8,8,8");
+    // 4, 8, zero and 16 bits: nibbles e, 6 4, f e d 4
+    log.extend_from_slice(&[b'I', 0xc9, 0xe6, 0x4f, 0xed, 0x40]);
+
+    let options = bbe_reader::DecodeOptions {
+        fields: "motor[0],motor[1],motor[2],motor[3]"
+            .split(',')
+            .map(String::from)
+            .collect(),
+        ..Default::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "motor[0],motor[1],motor[2],motor[3]\n-2,100,0,-300\n");
+    Ok(())
+}
+
+#[test]
+fn neg_14bit_negates_a_14_bit_value() -> Result<(), Box<dyn std::error::Error>> {
+    // NEG_14BIT (3) stores the negation of a 14-bit two's complement number as an
+    // unsigned variable-byte number.
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,vbatLatest");
+    log.header("Field I encoding", "1,3");
+    // 100, then 0x3fff, which is -1 in 14 bits
+    log.extend_from_slice(&[b'I', 0, 0x64, b'I', 1, 0xff, 0x7f]);
+
+    let options = bbe_reader::DecodeOptions {
+        fields: vec!["vbatLatest".to_string()],
+        ..Default::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "vbatLatest\n-100\n1\n");
+    Ok(())
+}
+
+#[test]
+fn null_encoding_takes_no_bytes() -> Result<(), Box<dyn std::error::Error>> {
+    // NULL (9) takes no bytes, leaving the field at the value its predictor gives.
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.header("Field P predictor", "6,1");
+    log.header("Field P encoding", "9,9");
+    // A P-frame of no bytes counts the iteration up and repeats the time
+    log.extend_from_slice(&[b'I', 0, 100, b'P', b'P']);
+
+    let options = bbe_reader::DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        ..Default::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "loopIteration,time\n0,100\n1,100\n2,100\n");
+    Ok(())
+}
+
+#[test]
+fn unsigned_signed_vb_field_keeps_values_past_i32_max() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "time");
+    log.header("Field I signed", "0");
+    log.header("Field I encoding", "0");
+    log.header("Field P predictor", "1");
+    log.header("Field P encoding", "0");
+    // A time of 3,000,000,000 µs, past i32::MAX, then one 5 µs earlier
+    log.extend_from_slice(&[b'I', 0xff, 0x87, 0xfd, 0xd2, 0x09, b'P', 0x09]);
+
+    let options = bbe_reader::DecodeOptions {
+        fields: vec!["time".to_string()],
+        ..Default::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "time\n3000000000\n2999999995\n");
+    Ok(())
+}
+
+#[test]
+fn unsigned_vb_field_keeps_the_full_u32_range() -> Result<(), Box<dyn std::error::Error>> {
+    // Unsigned values with the unsigned encoding keep the full `u32` range too.
+    let mut log = Log::new();
+    log.header("Field I name", "time");
+    log.header("Field I signed", "0");
+    log.header("Field I encoding", "1");
+    // 3,000,000,000 and u32::MAX
+    log.extend_from_slice(&[b'I', 0x80, 0xbc, 0xc1, 0x96, 0x0b]);
+    log.extend_from_slice(&[b'I', 0xff, 0xff, 0xff, 0xff, 0x0f]);
+
+    let options = bbe_reader::DecodeOptions {
+        fields: vec!["time".to_string()],
+        ..Default::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "time\n3000000000\n4294967295\n");
+    Ok(())
+}
+
+#[test]
+fn previous_value_predictor() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,axisP[0]");
+    log.header("Field I encoding", "1,0");
+    log.header("Field P predictor", "1,1");
+    log.header("Field P encoding", "0,0");
+    // Iteration 10 with axisP[0] at 40, then one iteration on with axisP[0] 3 lower
+    log.extend_from_slice(&[b'I', 10, 0x50, b'P', 0x02, 0x05]);
+
+    let options = bbe_reader::DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "axisP[0]".to_string()],
+        ..Default::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "loopIteration,axisP[0]\n10,40\n11,37\n");
+    Ok(())
+}
+
+#[test]
+fn increment_predictor_steps_by_the_logged_interval() -> Result<(), Box<dyn std::error::Error>> {
+    // A counter like `loopIteration` is predicted to step by the iterations between
+    // logged frames, here 2 with every other iteration logged.
+    let mut log = Log::new();
+    log.header("I interval", "32");
+    log.header("P interval", "1/2");
+    log.header("Field I name", "loopIteration,axisI[0]");
+    log.header("Field I encoding", "1,0");
+    log.header("Field P predictor", "6,1");
+    log.header("Field P encoding", "0,0");
+    log.extend_from_slice(&[b'I', 0, 0x14, b'P', 0, 0x02, b'P', 0, 0x01]);
+
+    let options = bbe_reader::DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "axisI[0]".to_string()],
+        ..Default::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "loopIteration,axisI[0]\n0,10\n2,11\n4,10\n");
+    Ok(())
+}
+
+#[test]
+fn straight_line_and_average_predictors() -> Result<(), Box<dyn std::error::Error>> {
+    // A smoothly varying field can be predicted to carry on in a straight line from the
+    // two previous frames, or to sit at their average. After a keyframe, both previous
+    // frames are the I-frame.
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,gyroADC[0],axisD[0]");
+    log.header("Field I encoding", "1,0,0");
+    log.header("Field P predictor", "6,2,3");
+    log.header("Field P encoding", "0,0,0");
+    // gyroADC[0] at 100 and axisD[0] at 40
+    log.extend_from_slice(&[b'I', 0, 0xc8, 0x01, 0x50]);
+    // +10 and +4 on the I-frame's values, then on the line and average exactly,
+    // then -5 and -1 off them
+    log.extend_from_slice(&[b'P', 0, 0x14, 0x08, b'P', 0, 0, 0, b'P', 0, 0x09, 0x01]);
+
+    let options = bbe_reader::DecodeOptions {
+        fields: vec!["gyroADC[0]".to_string(), "axisD[0]".to_string()],
+        ..Default::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "gyroADC[0],axisD[0]\n100,40\n110,44\n120,42\n125,42\n");
+    Ok(())
+}
+
+#[test]
+fn vbatref_predictor() -> Result<(), Box<dyn std::error::Error>> {
+    // Fields predicted from `vbatref` store their difference from the header's value.
+    let mut log = Log::new();
+    log.header("vbatref", "420");
+    log.header("Field I name", "vbatLatest");
+    log.header("Field I predictor", "9");
+    log.header("Field I encoding", "0");
+    // 3 below the reference, then 2 above it
+    log.extend_from_slice(&[b'I', 0x05, b'I', 0x04]);
+
+    let options = bbe_reader::DecodeOptions {
+        fields: vec!["vbatLatest".to_string()],
+        ..Default::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "vbatLatest\n417\n422\n");
+    Ok(())
+}
+
+#[test]
+fn strict_rejects_field_lists_of_different_lengths() -> Result<(), Box<dyn std::error::Error>> {
+    let mut bytes = Log::new();
+    bytes.header("Field I name", "loopIteration,time,vbatLatest");
+    bytes.header("Field I signed", "0,0");
+    bytes.header("Field I encoding", "1,1,1");
+
+    assert_eq!(BblLog::from_bytes(&bytes)?.main_fields().len(), 3);
+
+    let options = HeaderOptions {
+        strict: true,
+        ..HeaderOptions::default()
+    };
+    match BblLog::from_bytes_with(&bytes, &options) {
+        Err(BblError::MalformedHeader(line)) => assert_eq!(line, "H Field I signed:0,0"),
+        other => panic!("unexpected {:?}", other),
+    }
+    Ok(())
+}
+
+/// Runs the program on `log` fed through stdin, with the output written to stdout.
+fn run_cli(log: &[u8], args: &[&str]) -> std::process::Output {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bbe_reader_again"))
+        .args(["--input", "-"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(log).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn unexpected_signed_flags_are_warned_about() {
+    let mut log = Log::new();
+    log.header("Field I name", "debug[0],debug[1],debug[2],debug[3]");
+    log.header("Field I signed", "true,false,2,yes");
+    log.header("Field I encoding", "1,1,1,1");
+    // u32::MAX in every field, which is -1 in a signed field
+    log.push(b'I');
+    for _ in 0..4 {
+        log.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0x0f]);
+    }
+
+    let output = run_cli(&log, &["--all-fields"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let warnings: Vec<&str> = stderr
+        .lines()
+        .filter(|line| line.contains("signed flag"))
+        .collect();
+
+    // A non-zero number is taken as signed and anything else as unsigned
+    assert_eq!(
+        warnings,
+        [
+            "Warning: unexpected signed flag \"2\" for field index 2, treating it as signed",
+            "Warning: unexpected signed flag \"yes\" for field index 3, treating it as unsigned",
+        ]
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "debug[0],debug[1],debug[2],debug[3]\n-1,4294967295,-1,4294967295\n"
+    );
+}
+
+#[test]
+fn short_encoding_list_is_warned_about() {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time,debug[0]");
+    log.header("Field I encoding", "1,1");
+    // debug[0] is 4 unsigned, or 2 zig-zag encoded
+    log.extend_from_slice(&[b'I', 0, 100, 0x04]);
+
+    for (args, encoding, value) in [(&[][..], 0, 2), (&["--default-encoding", "1"][..], 1, 4)] {
+        let output = run_cli(&log, &[&["--all-fields"], args].concat());
+        assert!(output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let warning = format!(
+            "Warning: H Field I encoding lists 2 of 3 fields, using encoding {} for the rest",
+            encoding
+        );
+        assert!(stderr.lines().any(|line| line == warning), "{}", stderr);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!("loopIteration,time,debug[0]\n0,100,{}\n", value)
+        );
+    }
+}
+
+#[test]
+fn duplicate_field_names_are_warned_about() {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time,debug[0],debug[0]");
+    log.header("Field I signed", "0,0,1,1");
+    log.header("Field I encoding", "1,1,0,0");
+    // The first debug[0] is 3 and the second -3
+    log.extend_from_slice(&[b'I', 0, 100, 0x06, 0x05]);
+
+    let output = run_cli(&log, &["--fields", "time,debug[0]"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.lines().any(|line| line
+            == "Warning: H Field I name lists debug[0] at index 2 and 3, only index 2 is written"),
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "time,debug[0]\n100,3\n"
+    );
+}
@@ -0,0 +1,273 @@
+//! Decodes small logs written with `common::Log` into each of the output formats.
+
+use bbe_reader::{DecodeOptions, OutputFormat};
+
+#[cfg(feature = "sqlite")]
+use common::push_unsigned;
+use common::Log;
+
+mod common;
+
+#[test]
+fn json_lines() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Data version", "2");
+    log.header("Firmware revision", "Betaflight 4.5.1");
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        format: OutputFormat::JsonLines,
+        ..DecodeOptions::default()
+    };
+    let mut json = Vec::new();
+    bbe_reader::decode_to_writer(&log[..], &mut json, options)?;
+
+    assert_eq!(
+        String::from_utf8(json).unwrap(),
+        concat!(
+            "{\"type\": \"header\", \"firmware\": \"Betaflight 4.5.1\", ",
+            "\"craft_name\": null, \"data_version\": 2}\n",
+            "{\"loopIteration\": 0, \"time\": 100}\n",
+            "{\"loopIteration\": 1, \"time\": 200}\n",
+        )
+    );
+    Ok(())
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn message_pack() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        format: OutputFormat::MessagePack,
+        ..DecodeOptions::default()
+    };
+    let mut packed = Vec::new();
+    bbe_reader::decode_to_writer(&log[..], &mut packed, options)?;
+
+    let mut expected = vec![0x92];
+    for (iteration, time) in [(0, &[100][..]), (1, &[0xcc, 200])] {
+        expected.push(0x82);
+        expected.push(0xad);
+        expected.extend_from_slice(b"loopIteration");
+        expected.push(iteration);
+        expected.push(0xa4);
+        expected.extend_from_slice(b"time");
+        expected.extend_from_slice(time);
+    }
+    assert_eq!(packed, expected);
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        format: OutputFormat::Sqlite,
+        ..DecodeOptions::default()
+    };
+    let mut db = Vec::new();
+    bbe_reader::decode_to_writer(&log[..], &mut db, options)?;
+
+    assert_eq!(&db[..16], b"SQLite format 3\0");
+    // The schema page, a page for each table's rows
+    assert_eq!(db.len(), 3 * 65536);
+    let schema = br#"CREATE TABLE frames("loopIteration" INTEGER, "time" INTEGER)"#;
+    assert!(db.windows(schema.len()).any(|window| window == schema));
+    let (root, rows) = sqlite::read_table(&db, "frames");
+    assert_eq!(sqlite::page_type(&db, root), sqlite::LEAF_TABLE);
+    assert_eq!(rows, [[Some(0), Some(100)], [Some(1), Some(200)]]);
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_rows_over_several_pages() -> Result<(), Box<dyn std::error::Error>> {
+    // Far more rows than a 64 KiB leaf page holds, so the table needs an interior page
+    const ROWS: u64 = 20_000;
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    for iteration in 0..ROWS {
+        log.push(b'I');
+        push_unsigned(&mut log, iteration);
+        push_unsigned(&mut log, iteration * 125);
+    }
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        format: OutputFormat::Sqlite,
+        ..DecodeOptions::default()
+    };
+    let mut db = Vec::new();
+    bbe_reader::decode_to_writer(&log[..], &mut db, options)?;
+
+    let (root, rows) = sqlite::read_table(&db, "frames");
+    assert_eq!(sqlite::page_type(&db, root), sqlite::INTERIOR_TABLE);
+    assert_eq!(rows.len(), ROWS as usize);
+    for (iteration, row) in rows.iter().enumerate() {
+        let iteration = iteration as i64;
+        assert_eq!(row, &[Some(iteration), Some(iteration * 125)]);
+    }
+    Ok(())
+}
+
+/// Just enough of the SQLite file format to read back the integer tables written by
+/// [`OutputFormat::Sqlite`].
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    const PAGE_SIZE: usize = 65536;
+    pub const INTERIOR_TABLE: u8 = 0x05;
+    pub const LEAF_TABLE: u8 = 0x0d;
+
+    enum Value {
+        Null,
+        Integer(i64),
+        Text(String),
+    }
+
+    /// Returns the root page of `table` and its rows in rowid order, checking that the
+    /// rowids run 1, 2, 3, ...
+    pub fn read_table(db: &[u8], table: &str) -> (u32, Vec<Vec<Option<i64>>>) {
+        // Each schema row is type, name, table name, root page and SQL
+        let root = table_rows(db, 1)
+            .into_iter()
+            .find_map(|row| match &row[..] {
+                [_, Value::Text(name), _, Value::Integer(root), _] if name == table => {
+                    Some(*root as u32)
+                }
+                _ => None,
+            })
+            .expect("table in the schema");
+        let rows = table_rows(db, root)
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|value| match value {
+                        Value::Null => None,
+                        Value::Integer(value) => Some(value),
+                        Value::Text(text) => panic!("text {:?} in an integer table", text),
+                    })
+                    .collect()
+            })
+            .collect();
+        (root, rows)
+    }
+
+    pub fn page_type(db: &[u8], page: u32) -> u8 {
+        let (page, header) = page_at(db, page);
+        page[header]
+    }
+
+    /// The bytes of a page and the offset of its b-tree header, after the database
+    /// header on the first page.
+    fn page_at(db: &[u8], page: u32) -> (&[u8], usize) {
+        let start = (page as usize - 1) * PAGE_SIZE;
+        let header = if page == 1 { 100 } else { 0 };
+        (&db[start..start + PAGE_SIZE], header)
+    }
+
+    fn table_rows(db: &[u8], page: u32) -> Vec<Vec<Value>> {
+        let mut rows = Vec::new();
+        collect_rows(db, page, &mut rows);
+        rows
+    }
+
+    fn collect_rows(db: &[u8], page_number: u32, rows: &mut Vec<Vec<Value>>) {
+        let (page, header) = page_at(db, page_number);
+        let flag = page[header];
+        let cell_count = u16::from_be_bytes([page[header + 3], page[header + 4]]) as usize;
+        let header_len = if flag == INTERIOR_TABLE { 12 } else { 8 };
+        for index in 0..cell_count {
+            let pointer = header + header_len + 2 * index;
+            let mut pos = u16::from_be_bytes([page[pointer], page[pointer + 1]]) as usize;
+            match flag {
+                INTERIOR_TABLE => {
+                    let child = u32::from_be_bytes(page[pos..pos + 4].try_into().unwrap());
+                    collect_rows(db, child, rows);
+                }
+                LEAF_TABLE => {
+                    let len = read_varint(page, &mut pos) as usize;
+                    let rowid = read_varint(page, &mut pos);
+                    assert_eq!(rowid, rows.len() as u64 + 1);
+                    rows.push(read_record(&page[pos..pos + len]));
+                }
+                _ => panic!("page {} has type {:#x}", page_number, flag),
+            }
+        }
+        if flag == INTERIOR_TABLE {
+            let right_child = u32::from_be_bytes(page[header + 8..header + 12].try_into().unwrap());
+            collect_rows(db, right_child, rows);
+        }
+    }
+
+    fn read_record(record: &[u8]) -> Vec<Value> {
+        let mut pos = 0;
+        let header_len = read_varint(record, &mut pos) as usize;
+        let mut serial_types = Vec::new();
+        while pos < header_len {
+            serial_types.push(read_varint(record, &mut pos));
+        }
+        let mut body = &record[header_len..];
+        serial_types
+            .into_iter()
+            .map(|serial_type| {
+                let len = match serial_type {
+                    0 | 8 | 9 => 0,
+                    1..=4 => serial_type as usize,
+                    5 => 6,
+                    6 => 8,
+                    _ if serial_type >= 13 && serial_type % 2 == 1 => {
+                        (serial_type as usize - 13) / 2
+                    }
+                    _ => panic!("unexpected serial type {}", serial_type),
+                };
+                let (bytes, rest) = body.split_at(len);
+                body = rest;
+                match serial_type {
+                    0 => Value::Null,
+                    8 => Value::Integer(0),
+                    9 => Value::Integer(1),
+                    1..=6 => {
+                        // Sign-extend the big-endian bytes
+                        let fill = if bytes[0] & 0x80 != 0 { 0xff } else { 0 };
+                        let mut value = [fill; 8];
+                        value[8 - len..].copy_from_slice(bytes);
+                        Value::Integer(i64::from_be_bytes(value))
+                    }
+                    _ => Value::Text(String::from_utf8(bytes.to_vec()).unwrap()),
+                }
+            })
+            .collect()
+    }
+
+    fn read_varint(bytes: &[u8], pos: &mut usize) -> u64 {
+        let mut value = 0;
+        for index in 0..9 {
+            let byte = bytes[*pos];
+            *pos += 1;
+            if index == 8 {
+                return (value << 8) | u64::from(byte);
+            }
+            value = (value << 7) | u64::from(byte & 0x7f);
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        value
+    }
+}
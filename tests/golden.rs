@@ -0,0 +1,348 @@
+//! Decodes the logs in `tests/fixtures` and compares the output with the CSV checked in
+//! next to each, byte for byte.
+//!
+//! `golden.bbl` is a synthetic log of 16 main frames, two I-frames each followed by
+//! seven P-frames, with a slow frame and the arming and end-of-log events. After a
+//! deliberate change to the output, regenerate its CSV with
+//! `cargo run -- --input tests/fixtures/golden.bbl --all-fields -o tests/fixtures/golden.csv`.
+
+use std::io::{self, Read, Write};
+
+use bbe_reader::{AtomicFile, BblError, BblLog, DecodeOptions};
+
+use common::{fixture, push_unsigned, Log};
+
+mod common;
+
+#[test]
+fn golden_log_decodes_to_golden_csv() {
+    let path = fixture("golden.bbl");
+    let log = BblLog::from_bytes(&std::fs::read(&path).unwrap()).unwrap();
+    let options = DecodeOptions {
+        fields: log.main_field_names(),
+        ..DecodeOptions::default()
+    };
+    let decoded = bbe_reader::decode_file_with(&path, &options).unwrap();
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(&decoded.columns).unwrap();
+    for record in &decoded.records {
+        writer.write_record(record).unwrap();
+    }
+    let csv = writer.into_inner().unwrap();
+
+    let expected = std::fs::read(fixture("golden.csv")).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        String::from_utf8(expected).unwrap()
+    );
+    assert_eq!(decoded.stats.main_frames(), 16);
+}
+
+#[test]
+fn golden_log_exports_exactly_its_declared_fields() {
+    let path = fixture("golden.bbl");
+    let log = BblLog::from_bytes(&std::fs::read(&path).unwrap()).unwrap();
+    let options = DecodeOptions {
+        only_fields_present: true,
+        ..DecodeOptions::default()
+    };
+
+    let decoded = bbe_reader::decode_file_with(&path, &options).unwrap();
+    assert_eq!(decoded.columns, log.main_field_names());
+    assert!(decoded
+        .records
+        .iter()
+        .all(|record| record.len() == decoded.columns.len()));
+
+    let mut csv = Vec::new();
+    bbe_reader::decode_to_writer(std::fs::File::open(&path).unwrap(), &mut csv, options).unwrap();
+    let expected = std::fs::read(fixture("golden.csv")).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        String::from_utf8(expected).unwrap()
+    );
+}
+
+#[test]
+fn golden_log_follows_the_requested_field_order() {
+    let path = fixture("golden.bbl");
+    let log = BblLog::from_bytes(&std::fs::read(&path).unwrap()).unwrap();
+    let mut fields = log.main_field_names();
+    fields.reverse();
+    let options = DecodeOptions {
+        fields: fields.clone(),
+        ..DecodeOptions::default()
+    };
+
+    // Every golden row with its cells reversed, as the header order is
+    let expected = std::fs::read_to_string(fixture("golden.csv")).unwrap();
+    let expected: String = expected
+        .lines()
+        .map(|line| {
+            let mut cells: Vec<&str> = line.split(',').collect();
+            cells.reverse();
+            cells.join(",") + "\n"
+        })
+        .collect();
+
+    let decoded = bbe_reader::decode_file_with(&path, &options).unwrap();
+    assert_eq!(decoded.columns, fields);
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(&decoded.columns).unwrap();
+    for record in &decoded.records {
+        writer.write_record(record).unwrap();
+    }
+    assert_eq!(
+        String::from_utf8(writer.into_inner().unwrap()).unwrap(),
+        expected
+    );
+
+    // The streaming decoder assembles its rows separately
+    let mut csv = Vec::new();
+    bbe_reader::decode_to_writer(std::fs::File::open(&path).unwrap(), &mut csv, options).unwrap();
+    assert_eq!(String::from_utf8(csv).unwrap(), expected);
+}
+
+#[test]
+fn threaded_writer_matches_the_single_threaded_output() {
+    let path = fixture("golden.bbl");
+    let log = BblLog::from_bytes(&std::fs::read(&path).unwrap()).unwrap();
+    let decode = |threaded| {
+        let options = DecodeOptions {
+            fields: log.main_field_names(),
+            threaded,
+            ..DecodeOptions::default()
+        };
+        let mut csv = Vec::new();
+        let stats =
+            bbe_reader::decode_to_writer(std::fs::File::open(&path).unwrap(), &mut csv, options)
+                .unwrap();
+        (csv, stats.rows_written)
+    };
+
+    let (single, rows) = decode(false);
+    let (threaded, threaded_rows) = decode(true);
+    assert!(single == threaded);
+    assert_eq!(threaded_rows, rows);
+    assert!(single == std::fs::read(fixture("golden.csv")).unwrap());
+}
+
+/// Takes `capacity` bytes and then fails every write, like a full disk.
+struct FullDisk {
+    capacity: usize,
+}
+
+impl Write for FullDisk {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.capacity == 0 {
+            return Err(io::Error::other("disk full"));
+        }
+        let written = buf.len().min(self.capacity);
+        self.capacity -= written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn threaded_writer_passes_its_error_back() {
+    let path = fixture("golden.bbl");
+    for threaded in [false, true] {
+        let options = DecodeOptions {
+            threaded,
+            flush_every: 1,
+            ..DecodeOptions::default()
+        };
+        let result = bbe_reader::decode_to_writer(
+            std::fs::File::open(&path).unwrap(),
+            FullDisk { capacity: 100 },
+            options,
+        );
+        match result {
+            Err(BblError::Io(error)) => assert!(error.to_string().contains("disk full")),
+            other => panic!(
+                "threaded: {}, expected the write error, got {:?}",
+                threaded, other
+            ),
+        }
+    }
+}
+
+/// Reads the first `len` bytes of `data` and then fails, like a disk or connection
+/// giving out part way through a log.
+struct FailingRead<'a> {
+    data: &'a [u8],
+    len: usize,
+}
+
+impl Read for FailingRead<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.len == 0 {
+            return Err(io::Error::other("device went away"));
+        }
+        let count = buf.len().min(self.len);
+        buf[..count].copy_from_slice(&self.data[..count]);
+        self.data = &self.data[count..];
+        self.len -= count;
+        Ok(count)
+    }
+}
+
+#[test]
+fn failed_read_leaves_no_output_file() {
+    let log = std::fs::read(fixture("golden.bbl")).unwrap();
+    let dir = std::env::temp_dir().join(format!("bbe_golden_failed_read_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("golden.csv");
+
+    // Past the headers and into the frames, so some rows are written before the error
+    let input = FailingRead {
+        data: &log,
+        len: log.len() - 20,
+    };
+    let mut output = AtomicFile::create(&path).unwrap();
+    let result = bbe_reader::decode_to_writer(input, &mut output, DecodeOptions::default());
+    // Like the program, which commits the output only after a complete decode
+    drop(output);
+    let files = std::fs::read_dir(&dir).unwrap().count();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    match result {
+        Err(BblError::Io(error)) => assert!(error.to_string().contains("device went away")),
+        other => panic!("expected the read error, got {:?}", other),
+    }
+    assert!(!path.exists());
+    // Not even the hidden .partial file is left
+    assert_eq!(files, 0);
+}
+
+/// Passes writes on to `inner` but fails the one numbered `fail_at`, counting from 0,
+/// like an output that recovers from a transient error.
+struct FailsOnce<W> {
+    inner: W,
+    writes: usize,
+    fail_at: usize,
+}
+
+impl<W: Write> Write for FailsOnce<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writes += 1;
+        if self.writes - 1 == self.fail_at {
+            return Err(io::Error::other("transient write error"));
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A log of far more rows than the CSV writer buffers, so rows reach the output
+/// between flushes.
+fn long_log() -> Log {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    for iteration in 0..20_000 {
+        log.push(b'I');
+        push_unsigned(&mut log, iteration);
+        push_unsigned(&mut log, iteration * 125);
+    }
+    log
+}
+
+#[test]
+fn write_error_is_returned_even_if_the_writer_recovers() {
+    let log = long_log();
+
+    for threaded in [false, true] {
+        let options = DecodeOptions {
+            threaded,
+            flush_every: 0,
+            ..DecodeOptions::default()
+        };
+        let output = FailsOnce {
+            inner: io::sink(),
+            writes: 0,
+            fail_at: 1,
+        };
+        let result = bbe_reader::decode_to_writer(&log[..], output, options);
+        match result {
+            Err(BblError::Io(error)) => {
+                assert!(error.to_string().contains("transient write error"))
+            }
+            other => panic!(
+                "threaded: {}, expected the write error, got {:?}",
+                threaded, other
+            ),
+        }
+    }
+}
+
+#[test]
+fn failed_write_leaves_no_output_file() {
+    let log = long_log();
+    let dir = std::env::temp_dir().join(format!("bbe_golden_failed_write_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("failed.csv");
+
+    // Like the program, commit the output only after a complete decode
+    let mut output = AtomicFile::create(&path).unwrap();
+    let failing = FailsOnce {
+        inner: &mut output,
+        writes: 0,
+        fail_at: 1,
+    };
+    // Without periodic flushes the error comes from writing a row, not from a flush
+    let options = DecodeOptions {
+        flush_every: 0,
+        ..DecodeOptions::default()
+    };
+    let result = bbe_reader::decode_to_writer(&log[..], failing, options);
+    match result {
+        Ok(_) => output.commit().unwrap(),
+        Err(_) => drop(output),
+    }
+    let files = std::fs::read_dir(&dir).unwrap().count();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    match result {
+        Err(BblError::Io(error)) => {
+            assert!(error.to_string().contains("transient write error"))
+        }
+        other => panic!("expected the write error, got {:?}", other),
+    }
+    // A truncated output is never committed as if it were complete
+    assert!(!path.exists());
+    assert_eq!(files, 0);
+}
+
+#[test]
+fn golden_log_decodes_after_a_binary_preamble() {
+    // A 16-byte container header: magic, version, flags and a length, with a stray `H`
+    // and line feed that do not start the headers
+    let mut bytes = vec![
+        0x89, b'B', b'B', b'X', 0x0d, 0x0a, 0x1a, 0x00, 0x01, 0x00, b'H', 0x0a, 0xff, 0xfe, 0x00,
+        0x10,
+    ];
+    bytes.extend_from_slice(&std::fs::read(fixture("golden.bbl")).unwrap());
+
+    let log = BblLog::from_bytes(&bytes).unwrap();
+    assert_eq!(log.preamble_len, 16);
+    let options = DecodeOptions {
+        fields: log.main_field_names(),
+        ..DecodeOptions::default()
+    };
+    let mut csv = Vec::new();
+    bbe_reader::decode_to_writer(&bytes[..], &mut csv, options).unwrap();
+    let expected = std::fs::read(fixture("golden.csv")).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        String::from_utf8(expected).unwrap()
+    );
+}
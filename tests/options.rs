@@ -0,0 +1,1534 @@
+//! Decodes small logs written with `common::Log` under each of the `DecodeOptions`
+//! and checks the rows they give.
+
+use std::io::{self, Write};
+
+use bbe_reader::{
+    AltitudeSource, BblError, DecodeOptions, EncodingUsage, Event, GapThreshold, OutputFormat,
+    SlowMaxAge,
+};
+
+use common::{decode_csv, temp_path, Log};
+
+mod common;
+
+#[test]
+fn only_fields_present() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "time,escTemperature,loopIteration");
+    log.header("Field I signed", "0,1,0");
+    log.header("Field I encoding", "1,0,1");
+    // escTemperature of -3
+    log.extend_from_slice(&[b'I', 100, 5, 0]);
+
+    let options = DecodeOptions {
+        only_fields_present: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "time,escTemperature,loopIteration\n100,-3,0\n");
+    Ok(())
+}
+
+#[test]
+fn physical_units() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Firmware type", "Cleanflight");
+    log.header("Field I name", "rssi");
+    log.header("Field I encoding", "1");
+    // rssi of 512 out of 1023
+    log.extend_from_slice(&[b'I', 0x80, 0x04]);
+
+    let options = DecodeOptions {
+        fields: vec!["rssi".to_string()],
+        physical_units: true,
+        units_header: true,
+        precision: Some(1),
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "rssi\n%\n50.0\n");
+    Ok(())
+}
+
+#[test]
+fn physical_units_without_gyro_scale() -> Result<(), Box<dyn std::error::Error>> {
+    // Without a `gyro_scale` header, only the gyro stays raw.
+    let mut log = Log::new();
+    log.header("Field I name", "gyroADC[0],vbatLatest");
+    log.header("Field I encoding", "0,1");
+    // gyroADC[0] of 100 and vbatLatest of 1650
+    log.extend_from_slice(&[b'I', 0xc8, 0x01, 0xf2, 0x0c]);
+
+    let options = DecodeOptions {
+        fields: vec!["gyroADC[0]".to_string(), "vbatLatest".to_string()],
+        physical_units: true,
+        units_header: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "gyroADC[0],vbatLatest\nraw,V\n100,16.5\n");
+    Ok(())
+}
+
+#[test]
+fn rc_as_us() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header(
+        "Field I name",
+        "rcCommand[0],rcCommand[1],rcCommand[2],rcCommand[3]",
+    );
+    log.header("Field I signed", "1,1,1,0");
+    log.header("Field I encoding", "0,0,0,1");
+    // Roll -100, pitch 50 and yaw 0 from center, throttle 1200
+    log.extend_from_slice(&[b'I', 0xc7, 0x01, 0x64, 0x00, 0xb0, 0x09]);
+
+    let options = DecodeOptions {
+        fields: (0..4).map(|axis| format!("rcCommand[{}]", axis)).collect(),
+        rc_as_us: true,
+        keep_raw: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(
+        csv,
+        "rcCommand[0],rcCommand[0]_us,rcCommand[1],rcCommand[1]_us,\
+         rcCommand[2],rcCommand[2]_us,rcCommand[3],rcCommand[3]_us\n\
+         -100,1400,50,1550,0,1500,1200,1200\n"
+    );
+    Ok(())
+}
+
+/// Counts the bytes written to it and records the count at each flush.
+#[derive(Default)]
+struct FlushRecorder {
+    written: usize,
+    flushed_at: Vec<usize>,
+}
+
+impl Write for FlushRecorder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flushed_at.push(self.written);
+        Ok(())
+    }
+}
+
+#[test]
+fn flush_every() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    for iteration in 0..10 {
+        log.extend_from_slice(&[b'I', iteration, iteration]);
+    }
+    let flushes = |flush_every| -> Result<Vec<usize>, BblError> {
+        let options = DecodeOptions {
+            flush_every,
+            ..DecodeOptions::default()
+        };
+        let mut output = FlushRecorder::default();
+        bbe_reader::decode_to_writer(&log[..], &mut output, options)?;
+        assert_eq!(output.written, 59);
+        // Finishing the output may flush it more than once
+        output.flushed_at.dedup();
+        Ok(output.flushed_at)
+    };
+
+    // The 19 byte header row, then 4 bytes a row, flushed after rows 4 and 8 and at
+    // the end
+    assert_eq!(flushes(4)?, [35, 51, 59]);
+    assert_eq!(flushes(0)?, [59]);
+    Ok(())
+}
+
+#[test]
+fn first_i_frame() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.header("Field P predictor", "6,1");
+    log.header("Field P encoding", "0,0");
+    // A sync beep at 73 µs, whose time byte is an `I`, ahead of the keyframe at
+    // iteration 5 and 1000 µs and a P-frame after it
+    log.extend_from_slice(&[b'E', 0x00, b'I']);
+    log.extend_from_slice(&[b'I', 5, 0xe8, 0x07, b'P', 0, 0x14]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        first_i_frame: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "loopIteration,time\n5,1000\n");
+    Ok(())
+}
+
+#[test]
+fn reverse() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.header("Field P predictor", "0,0");
+    log.header("Field P encoding", "1,1");
+    log.extend_from_slice(&[b'I', 0, 100, b'P', 1, 0xc8, 0x01]);
+    log.extend_from_slice(&[b'I', 2, 0xac, 0x02, b'P', 3, 0x90, 0x03]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        reverse: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "loopIteration,time\n2,300\n3,400\n0,100\n1,200\n");
+    Ok(())
+}
+
+#[test]
+fn flight_only() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.extend_from_slice(&[b'I', 0, 100]);
+    // Armed at 150 µs
+    log.extend_from_slice(&[b'E', 0, 0x96, 0x01]);
+    log.extend_from_slice(&[b'I', 1, 0xc8, 0x01, b'I', 2, 0xac, 0x02]);
+    // Disarmed
+    log.extend_from_slice(&[b'E', 15, 0]);
+    log.extend_from_slice(&[b'I', 3, 0x90, 0x03, b'E', 255]);
+    log.extend_from_slice(b"End of log\0");
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        flight_only: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "loopIteration,time\n1,0\n2,100\n");
+    Ok(())
+}
+
+#[test]
+fn frame_types() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("I interval", "3");
+    log.header("Field I name", "loopIteration,time,axisP[0]");
+    log.header("Field I encoding", "1,1,0");
+    log.header("Field P predictor", "6,1,1");
+    log.header("Field P encoding", "0,0,0");
+    // A keyframe at 100 µs with axisP[0] of 10, two P-frames each 100 µs later and 5
+    // up, then a keyframe at 1000 µs with 50 and one more P-frame
+    log.extend_from_slice(&[b'I', 0, 100, 20]);
+    log.extend_from_slice(&[b'P', 0, 0xc8, 0x01, 10, b'P', 0, 0xc8, 0x01, 10]);
+    log.extend_from_slice(&[b'I', 3, 0xe8, 0x07, 0x64, b'P', 0, 0xc8, 0x01, 10]);
+    let decode = |frame_types: &[char]| {
+        let options = DecodeOptions {
+            fields: vec![
+                "loopIteration".to_string(),
+                "time".to_string(),
+                "axisP[0]".to_string(),
+            ],
+            frame_types: frame_types.to_vec(),
+            ..DecodeOptions::default()
+        };
+        decode_csv(&log, options)
+    };
+
+    assert_eq!(
+        decode(&['I'])?,
+        "loopIteration,time,axisP[0]\n0,100,10\n3,1000,50\n"
+    );
+    // The P-frames are still predicted from the keyframes left out of the output
+    assert_eq!(
+        decode(&['P'])?,
+        "loopIteration,time,axisP[0]\n1,200,15\n2,300,20\n4,1100,55\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn synthesize_time() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("looptime", "125");
+    log.header("pid_process_denom", "2");
+    log.header("I interval", "8");
+    log.header("P interval", "2");
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    // A frame every other 250 µs loop, with the logged time jittering by a few µs
+    log.extend_from_slice(&[b'I', 0, 0xe8, 0x07]);
+    log.extend_from_slice(&[b'I', 2, 0xdf, 0x0b]);
+    log.extend_from_slice(&[b'I', 4, 0xd2, 0x0f]);
+
+    let options = DecodeOptions {
+        fields: vec!["time".to_string()],
+        synthesize_time: true,
+        ..DecodeOptions::default()
+    };
+    let mut csv = Vec::new();
+    bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+
+    let csv = String::from_utf8(csv).unwrap();
+    for row in csv.lines().skip(1) {
+        let (time, synthesized) = row.split_once(',').unwrap();
+        let (time, synthesized): (i64, i64) = (time.parse()?, synthesized.parse()?);
+        assert!((time - synthesized).abs() <= 10, "{}", row);
+    }
+    Ok(())
+}
+
+#[test]
+fn sample_index() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    // loopIteration restarts, which the sample index does not
+    log.extend_from_slice(&[b'I', 7, 100, b'I', 8, 0xc8, 0x01, b'I', 0, 0xac, 0x02]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string()],
+        sample_index: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "sample,loopIteration\n0,7\n1,8\n2,0\n");
+    Ok(())
+}
+
+#[test]
+fn relative_time() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    // 1 s and 1.5 s after boot, then the clock resets to 100 µs
+    log.extend_from_slice(&[b'I', 0, 0xc0, 0x84, 0x3d, b'I', 1, 0xe0, 0xc6, 0x5b]);
+    log.extend_from_slice(&[b'I', 2, 0x64, b'I', 3, 0xf4, 0xa1, 0x0f]);
+
+    let options = DecodeOptions {
+        fields: vec!["time".to_string()],
+        relative_time: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(
+        csv,
+        "time,time_s\n\
+         1000000,0.000000\n\
+         1500000,0.500000\n\
+         100,0.500000\n\
+         250100,0.750000\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn validity_column() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.header("Field P predictor", "6,1");
+    log.header("Field P encoding", "0,0");
+    // Iterations 0 and 1 at 100 µs and 200 µs, then a corrupt byte
+    log.extend_from_slice(&[b'I', 0, 0x64, b'P', 0, 0xc8, 0x01, 0xff]);
+    // Iterations 4 and 5 at 500 µs and 600 µs, recovered
+    log.extend_from_slice(&[b'I', 4, 0xf4, 0x03, b'P', 0, 0xc8, 0x01]);
+    // Iteration 6 at 700 µs, then 9 at 1000 µs after a gap
+    log.extend_from_slice(&[b'I', 6, 0xbc, 0x05, b'I', 9, 0xe8, 0x07]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        validity_column: true,
+        gap_markers: true,
+        ..DecodeOptions::default()
+    };
+    let mut csv = Vec::new();
+    let stats = bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+
+    assert_eq!(stats.resyncs, 1);
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "loopIteration,time,valid\n\
+         0,100,1\n\
+         1,200,1\n\
+         4,500,0\n\
+         5,600,0\n\
+         6,700,1\n\
+         ,850,0\n\
+         9,1000,1\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn gap_markers() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    // Iteration 2 is missing
+    log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01, b'I', 3, 0x90, 0x03]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        gap_markers: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "loopIteration,time\n0,100\n1,200\n,300\n3,400\n");
+    Ok(())
+}
+
+#[test]
+fn gap_threshold() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    // Iteration 2 is missing 150 µs apart, then 4 to 6 over 450 µs
+    log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01, b'I', 3, 0xde, 0x02]);
+    log.extend_from_slice(&[b'I', 7, 0xa0, 0x06]);
+
+    for threshold in [GapThreshold::Iterations(2), GapThreshold::Micros(300)] {
+        let options = DecodeOptions {
+            gap_threshold: Some(threshold),
+            ..DecodeOptions::default()
+        };
+        let stats = bbe_reader::decode_to_writer(&log[..], std::io::sink(), options)?;
+        assert_eq!((stats.gaps, stats.missing_frames), (1, 3));
+    }
+    Ok(())
+}
+
+#[test]
+fn per_iteration() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.header("Field S name", "flightModeFlags");
+    log.header("Field S encoding", "1");
+    log.extend_from_slice(&[b'I', 0, 100, b'S', 4, b'I', 1, 0xc8, 0x01]);
+    // Iteration 1 again, then a new slow frame
+    log.extend_from_slice(&[b'I', 1, 0xc8, 0x01, b'S', 5, b'I', 2, 0xac, 0x02]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        per_iteration: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(
+        csv,
+        "loopIteration,time,flightModeFlags\n0,100,\n1,200,4\n2,300,5\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn only_changed_rows() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time,rcCommand[3],vbatLatest");
+    log.header("Field I encoding", "1,1,1,1");
+    log.header("Field P predictor", "6,1,1,1");
+    log.header("Field P encoding", "0,0,0,0");
+    // Throttle at 1000 and the battery at 17.68 V
+    log.extend_from_slice(&[b'I', 0, 100, 0xe8, 0x07, 0xe8, 0x0d]);
+    // Unchanged twice, then the battery sags by 0.01 V, then unchanged again
+    log.extend_from_slice(&[b'P', 0, 0xc8, 0x01, 0, 0, b'P', 0, 0xc8, 0x01, 0, 0]);
+    log.extend_from_slice(&[b'P', 0, 0xc8, 0x01, 0, 0x01, b'P', 0, 0xc8, 0x01, 0, 0]);
+
+    let options = DecodeOptions {
+        fields: "loopIteration,time,rcCommand[3],vbatLatest"
+            .split(',')
+            .map(String::from)
+            .collect(),
+        only_changed_rows: true,
+        ..DecodeOptions::default()
+    };
+    let mut csv = Vec::new();
+    let stats = bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+
+    assert_eq!(stats.rows_written, 2);
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "loopIteration,time,rcCommand[3],vbatLatest\n0,100,1000,1768\n3,400,1000,1767\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn include_slow() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration");
+    log.header("Field I encoding", "1");
+    log.header("Field S name", "flightModeFlags,failsafePhase");
+    log.header("Field S encoding", "1,1");
+    // Iteration 1 is logged twice, and failsafe starts after it
+    log.extend_from_slice(&[b'I', 0, b'S', 4, 0, b'I', 1, b'I', 1, b'S', 4, 2, b'I', 2]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string()],
+        include_slow: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(
+        csv,
+        "loopIteration,flightModeFlags,failsafePhase\n0,,\n1,4,0\n1,4,0\n2,4,2\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn slow_max_age() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration");
+    log.header("Field I encoding", "1");
+    log.header("Field S name", "flightModeFlags");
+    log.header("Field S encoding", "1");
+    // A slow frame before iteration 0, then none until after iteration 3
+    log.extend_from_slice(&[b'S', 4, b'I', 0, b'I', 1, b'I', 2, b'I', 3]);
+    log.extend_from_slice(&[b'S', 5, b'I', 4]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string()],
+        per_iteration: true,
+        slow_max_age: Some(SlowMaxAge::Iterations(2)),
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(
+        csv,
+        "loopIteration,flightModeFlags\n0,4\n1,4\n2,4\n3,\n4,5\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn flag_names() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "flightModeFlags");
+    log.header("Field I signed", "1");
+    log.header("Field I encoding", "1");
+    // Angle and horizon mode, then angle mode and bit 31
+    log.extend_from_slice(&[b'I', 3, b'I', 0x81, 0x80, 0x80, 0x80, 0x08]);
+
+    let mut options = DecodeOptions {
+        fields: vec!["flightModeFlags".to_string()],
+        ..DecodeOptions::default()
+    };
+    let mut csv = Vec::new();
+    bbe_reader::decode_to_writer(&log[..], &mut csv, options.clone())?;
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "flightModeFlags\n3\n2147483649\n"
+    );
+
+    options.flag_names = true;
+    let mut csv = Vec::new();
+    bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "flightModeFlags\nANGLE_MODE|HORIZON_MODE\nANGLE_MODE|BIT31\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn flag_names_for_arming_disable_flags() -> Result<(), Box<dyn std::error::Error>> {
+    // The reasons the craft would not arm, logged in slow frames by firmwares that
+    // record them, are named the same way.
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration");
+    log.header("Field I encoding", "1");
+    log.header("Field S name", "armingDisableFlags");
+    log.header("Field S encoding", "1");
+    // No gyro, failsafe and throttle: bits 0, 1 and 7
+    log.extend_from_slice(&[b'I', 0, b'S', 0x83, 0x01, b'I', 1]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string()],
+        include_slow: true,
+        flag_names: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+    assert_eq!(
+        csv,
+        "loopIteration,armingDisableFlags\n0,\n1,NO_GYRO|FAILSAFE|THROTTLE\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn start_time() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time,axisP[0]");
+    log.header("Field I encoding", "1,1,0");
+    log.header("Field P predictor", "6,1,1");
+    log.header("Field P encoding", "0,0,0");
+    // Frames 100 µs apart, with axisP[0] climbing by 1 from 10
+    log.extend_from_slice(&[b'I', 0, 100, 0x14]);
+    for _ in 0..4 {
+        log.extend_from_slice(&[b'P', 0, 0xc8, 0x01, 0x02]);
+    }
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".into(), "time".into(), "axisP[0]".into()],
+        start_time: Some(250),
+        end_time: Some(400),
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "loopIteration,time,axisP[0]\n2,300,12\n3,400,13\n");
+    Ok(())
+}
+
+#[test]
+fn export_interval() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.header("Field P predictor", "6,1");
+    log.header("Field P encoding", "0,0");
+    // Frames 100 µs apart, from 100 to 1000 µs
+    log.extend_from_slice(&[b'I', 0, 100]);
+    for _ in 0..9 {
+        log.extend_from_slice(&[b'P', 0, 0xc8, 0x01]);
+    }
+
+    let options = DecodeOptions {
+        fields: vec!["time".into()],
+        export_interval: Some(250),
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "time\n100\n400\n700\n1000\n");
+    Ok(())
+}
+
+#[test]
+fn decimate() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.header("Field P predictor", "6,1");
+    log.header("Field P encoding", "0,0");
+    // Iterations 0 to 9, 100 µs apart
+    log.extend_from_slice(&[b'I', 0, 100]);
+    for _ in 0..9 {
+        log.extend_from_slice(&[b'P', 0, 0xc8, 0x01]);
+    }
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".into(), "time".into()],
+        decimate: Some(3),
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "loopIteration,time\n0,100\n3,400\n6,700\n9,1000\n");
+    Ok(())
+}
+
+#[test]
+fn decimate_to() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    // An 8 kHz loop, logging every other iteration
+    log.header("looptime", "125");
+    log.header("I interval", "32");
+    log.header("P interval", "1/2");
+    log.header("Field I name", "loopIteration");
+    log.header("Field I encoding", "1");
+    log.header("Field P predictor", "6");
+    log.header("Field P encoding", "9");
+    log.extend_from_slice(&[b'I', 0]);
+    log.extend_from_slice(&[b'P'; 9]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".into()],
+        decimate_to: Some(1000.0),
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    // 4 kHz of frames down to 1 kHz keeps every 4th
+    assert_eq!(csv, "loopIteration\n0\n8\n16\n");
+    Ok(())
+}
+
+#[test]
+fn stop_at_event() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration");
+    log.header("Field I encoding", "1");
+    log.extend_from_slice(&[b'I', 0, b'E', 30, 1, 0, b'I', 1]);
+    // Failsafe mode is entered after iteration 1
+    log.extend_from_slice(&[b'E', 30, 0x81, 0x08, 1, b'I', 2]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string()],
+        stop_at_event: Some("failsafe".to_string()),
+        ..DecodeOptions::default()
+    };
+    let mut csv = Vec::new();
+    let stats = bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+
+    assert_eq!(String::from_utf8(csv).unwrap(), "loopIteration\n0\n1\n");
+    let stopped_at = stats.stopped_at.expect("failsafe was entered");
+    assert_eq!(
+        stopped_at.event,
+        Event::FlightMode {
+            flags: 0x401,
+            last_flags: 1
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn max_frame_size() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.extend_from_slice(&[b'I', 0, 100]);
+    // A corrupt time spanning 4 bytes
+    log.extend_from_slice(&[b'I', 1, 0xff, 0xff, 0xff, 0x0f]);
+    log.extend_from_slice(&[b'I', 2, 0xac, 0x02]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        max_frame_size: 4,
+        ..DecodeOptions::default()
+    };
+    let mut csv = Vec::new();
+    let stats = bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "loopIteration,time\n0,100\n2,300\n"
+    );
+    assert_eq!((stats.oversized_frames, stats.skipped_bytes), (1, 6));
+    Ok(())
+}
+
+#[test]
+fn altitude_source() -> Result<(), Box<dyn std::error::Error>> {
+    // A session with a barometer at 100 cm
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,baroAlt");
+    log.header("Field I encoding", "1,1");
+    log.header("Field G name", "GPS_altitude");
+    log.header("Field G encoding", "1");
+    log.extend_from_slice(&[b'G', 7, b'I', 0, 100]);
+    // A session without one, with the GPS at 15 dm
+    log.header(
+        "Product",
+        "Blackbox flight data recorder by Nicholas Sherlock",
+    );
+    log.header("Field I name", "loopIteration");
+    log.header("Field I encoding", "1");
+    log.header("Field G name", "GPS_altitude");
+    log.header("Field G encoding", "1");
+    log.extend_from_slice(&[b'I', 0, b'G', 15, b'I', 1]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string()],
+        altitude_source: Some(AltitudeSource::Merge),
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "loopIteration,altitude\n0,100\n0,\n1,150\n");
+    Ok(())
+}
+
+#[test]
+fn vibration_window() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header(
+        "Field I name",
+        "loopIteration,accSmooth[0],accSmooth[1],accSmooth[2]",
+    );
+    log.header("Field I encoding", "1,1,1,1");
+    // The X axis swings 10 either side of 100, the others hold still
+    for iteration in 0..9 {
+        let x = if iteration % 2 == 0 { 110 } else { 90 };
+        log.extend_from_slice(&[b'I', iteration, x, 0, 0x80, 0x04]);
+    }
+
+    let options = DecodeOptions {
+        vibration_window: Some(4),
+        ..DecodeOptions::default()
+    };
+    let stats = bbe_reader::decode_to_writer(&log[..], std::io::sink(), options)?;
+
+    assert_eq!(stats.vibration, vec![10.0, 10.0]);
+    Ok(())
+}
+
+#[test]
+fn session() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01]);
+    log.header(
+        "Product",
+        "Blackbox flight data recorder by Nicholas Sherlock",
+    );
+    log.header("Field I name", "loopIteration,axisP[0]");
+    log.header("Field I encoding", "1,1");
+    log.extend_from_slice(&[b'I', 0, 7]);
+    // A third session armed but logged no frames
+    log.header(
+        "Product",
+        "Blackbox flight data recorder by Nicholas Sherlock",
+    );
+    log.header("Field I name", "loopIteration,axisP[0]");
+    log.header("Field I encoding", "1,1");
+
+    let decode = |session| {
+        let options = DecodeOptions {
+            fields: vec!["loopIteration".into(), "time".into(), "axisP[0]".into()],
+            session: Some(session),
+            ..DecodeOptions::default()
+        };
+        let mut csv = Vec::new();
+        bbe_reader::decode_to_writer(&log[..], &mut csv, options).map(|_| csv)
+    };
+
+    assert_eq!(decode(2)?, b"loopIteration,axisP[0]\n0,7\n");
+    assert_eq!(decode(3)?, b"loopIteration,axisP[0]\n");
+    assert!(matches!(
+        decode(4),
+        Err(BblError::NoSuchSession {
+            requested: 4,
+            sessions: 3
+        })
+    ));
+    Ok(())
+}
+
+#[test]
+fn gps_out() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.header("Field H name", "GPS_home[0],GPS_home[1]");
+    log.header("Field H signed", "1,1");
+    log.header("Field H encoding", "0,0");
+    log.header("Field G name", "time,GPS_numSat,GPS_coord[0],GPS_coord[1]");
+    log.header("Field G signed", "0,0,1,1");
+    log.header("Field G predictor", "10,0,7,7");
+    log.header("Field G encoding", "1,1,0,0");
+    // Home at 500, -300, then a fix 20 µs after the main frame, 3 north and 2 west
+    log.extend_from_slice(&[b'I', 0, 0xe8, 0x07, b'H', 0xe8, 0x07, 0xd7, 0x04]);
+    log.extend_from_slice(&[b'G', 0x14, 9, 0x06, 0x03]);
+
+    let path = temp_path("gps.csv");
+    let options = DecodeOptions {
+        gps_out: Some(path.clone()),
+        ..DecodeOptions::default()
+    };
+    bbe_reader::decode_to_writer(&log[..], std::io::sink(), options)?;
+
+    let csv = std::fs::read_to_string(&path)?;
+    std::fs::remove_file(&path)?;
+    assert_eq!(
+        csv,
+        "time,GPS_numSat,GPS_coord[0],GPS_coord[1]\n1020,9,503,-302\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn gps_out_with_inav_fields() -> Result<(), Box<dyn std::error::Error>> {
+    // INAV logs its ground course, dilution of precision, position error estimates and
+    // velocity with each fix
+    let mut log = Log::new();
+    log.header("Firmware type", "Cleanflight");
+    log.header("Firmware revision", "INAV 7.1.0 (1f3b9d4a) SPEEDYBEEF405V4");
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.header("Field H name", "GPS_home[0],GPS_home[1]");
+    log.header("Field H signed", "1,1");
+    log.header("Field H encoding", "0,0");
+    log.header(
+        "Field G name",
+        "time,GPS_numSat,GPS_fixType,GPS_coord[0],GPS_coord[1],GPS_altitude,GPS_speed,\
+         GPS_ground_course,GPS_hdop,GPS_eph,GPS_epv,GPS_velned[0],GPS_velned[1],GPS_velned[2]",
+    );
+    log.header("Field G signed", "0,0,0,1,1,0,0,0,0,0,0,1,1,1");
+    log.header("Field G predictor", "10,0,0,7,7,0,0,0,0,0,0,0,0,0");
+    log.header("Field G encoding", "1,1,1,0,0,1,1,1,1,1,1,0,0,0");
+    // Home at 500, -300, then a 3D fix of 12 satellites 20 µs after the main frame, 3
+    // north and 2 west at 123.4 m, 2.5 m/s on a course of 180.0° with an HDOP of 1.2,
+    // 1.5 m and 2 m position errors and 10, -5 and 0 cm/s north, east and down
+    log.extend_from_slice(&[b'I', 0, 0xe8, 0x07, b'H', 0xe8, 0x07, 0xd7, 0x04]);
+    log.extend_from_slice(&[b'G', 0x14, 12, 2, 0x06, 0x03, 0xd2, 0x09, 0xfa, 0x01]);
+    log.extend_from_slice(&[0x88, 0x0e, 0x78, 0x96, 0x01, 0xc8, 0x01, 20, 9, 0]);
+
+    let path = temp_path("inav_gps.csv");
+    let options = DecodeOptions {
+        gps_out: Some(path.clone()),
+        ..DecodeOptions::default()
+    };
+    bbe_reader::decode_to_writer(&log[..], std::io::sink(), options)?;
+
+    let csv = std::fs::read_to_string(&path)?;
+    std::fs::remove_file(&path)?;
+    assert_eq!(
+        csv,
+        concat!(
+            "time,GPS_numSat,GPS_fixType,GPS_coord[0],GPS_coord[1],GPS_altitude,GPS_speed,",
+            "GPS_ground_course,GPS_hdop,GPS_eph,GPS_epv,GPS_velned[0],GPS_velned[1],",
+            "GPS_velned[2]\n",
+            "1020,12,2,503,-302,1234,250,1800,120,150,200,10,-5,0\n",
+        )
+    );
+    Ok(())
+}
+
+#[test]
+fn gpx_out() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Log start datetime", "2024-05-12T10:20:30.000+02:00");
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.header(
+        "Field G name",
+        "time,GPS_coord[0],GPS_coord[1],GPS_altitude",
+    );
+    log.header("Field G signed", "0,1,1,0");
+    log.header("Field G predictor", "10,0,0,0");
+    log.header("Field G encoding", "1,0,0,1");
+    // A main frame at 1.5 s, then fixes 0.5 s and 1.5 s later, 123.4 m and 125 m up.
+    // Coordinates are in 1e-7 degrees: 47.3769 N 8.5417 E, then 2 units further on
+    log.extend_from_slice(&[b'I', 0, 0xe0, 0xc6, 0x5b]);
+    log.extend_from_slice(&[b'G', 0xa0, 0xc2, 0x1e, 0xd0, 0x90, 0xe9, 0xc3, 0x03]);
+    log.extend_from_slice(&[0xd0, 0xf0, 0xba, 0x51, 0xd2, 0x09]);
+    log.extend_from_slice(&[b'G', 0xe0, 0xc6, 0x5b, 0xd4, 0x90, 0xe9, 0xc3, 0x03]);
+    log.extend_from_slice(&[0xd4, 0xf0, 0xba, 0x51, 0xe2, 0x09]);
+
+    let path = temp_path("track.gpx");
+    let options = DecodeOptions {
+        gpx_out: Some(path.clone()),
+        ..DecodeOptions::default()
+    };
+    bbe_reader::decode_to_writer(&log[..], std::io::sink(), options)?;
+
+    let gpx = std::fs::read_to_string(&path)?;
+    std::fs::remove_file(&path)?;
+    // Logging started with the main frame, so the fixes are 0.5 s and 1.5 s after the
+    // start datetime, 08:20:30 UTC
+    let points: Vec<&str> = gpx
+        .lines()
+        .filter(|line| line.starts_with("<trkpt"))
+        .collect();
+    assert_eq!(
+        points,
+        [
+            "<trkpt lat=\"47.3769000\" lon=\"8.5417000\"><ele>123.4</ele>\
+             <time>2024-05-12T08:20:30.500Z</time></trkpt>",
+            "<trkpt lat=\"47.3769002\" lon=\"8.5417002\"><ele>125.0</ele>\
+             <time>2024-05-12T08:20:31.500Z</time></trkpt>",
+        ]
+    );
+    assert!(gpx.trim_end().ends_with("</trkseg>\n</trk>\n</gpx>"));
+    Ok(())
+}
+
+#[test]
+fn kml_out() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.header(
+        "Field G name",
+        "time,GPS_coord[0],GPS_coord[1],GPS_altitude",
+    );
+    log.header("Field G signed", "0,1,1,0");
+    log.header("Field G predictor", "10,0,0,0");
+    log.header("Field G encoding", "1,0,0,1");
+    // A fix without a position, then 47.3769 N 8.5417 E at 123.4 m and 2 units on at 125 m
+    log.extend_from_slice(&[b'I', 0, 0xe0, 0xc6, 0x5b]);
+    log.extend_from_slice(&[b'G', 0x00, 0x00, 0x00, 0x00]);
+    log.extend_from_slice(&[b'G', 0xa0, 0xc2, 0x1e, 0xd0, 0x90, 0xe9, 0xc3, 0x03]);
+    log.extend_from_slice(&[0xd0, 0xf0, 0xba, 0x51, 0xd2, 0x09]);
+    log.extend_from_slice(&[b'G', 0xe0, 0xc6, 0x5b, 0xd4, 0x90, 0xe9, 0xc3, 0x03]);
+    log.extend_from_slice(&[0xd4, 0xf0, 0xba, 0x51, 0xe2, 0x09]);
+
+    let path = temp_path("track.kml");
+    let options = DecodeOptions {
+        kml_out: Some(path.clone()),
+        ..DecodeOptions::default()
+    };
+    bbe_reader::decode_to_writer(&log[..], std::io::sink(), options)?;
+
+    let kml = std::fs::read_to_string(&path)?;
+    std::fs::remove_file(&path)?;
+    let coordinates = kml.split("<coordinates>\n").nth(1).unwrap();
+    let coordinates = coordinates.split("</coordinates>").next().unwrap();
+    assert_eq!(
+        coordinates,
+        "8.5417000,47.3769000,123.4\n8.5417002,47.3769002,125.0\n"
+    );
+    assert!(kml
+        .trim_end()
+        .ends_with("</Placemark>\n</Document>\n</kml>"));
+    Ok(())
+}
+
+#[test]
+fn min_satellites() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.header("Field G name", "time,GPS_numSat,GPS_coord[0],GPS_coord[1]");
+    log.header("Field G signed", "0,0,1,1");
+    log.header("Field G predictor", "10,0,0,0");
+    log.header("Field G encoding", "1,1,0,0");
+    // Fixes 10, 20 and 30 µs after the main frame, with 9, 3 and 5 satellites
+    log.extend_from_slice(&[b'I', 0, 0xe8, 0x07]);
+    log.extend_from_slice(&[b'G', 0x0a, 9, 0x06, 0x03]);
+    log.extend_from_slice(&[b'G', 0x14, 3, 0x08, 0x05]);
+    log.extend_from_slice(&[b'G', 0x1e, 5, 0x0a, 0x07]);
+
+    let csv_path = temp_path("min_satellites.csv");
+    let gpx_path = temp_path("min_satellites.gpx");
+    let options = DecodeOptions {
+        gps_out: Some(csv_path.clone()),
+        gpx_out: Some(gpx_path.clone()),
+        min_satellites: Some(5),
+        ..DecodeOptions::default()
+    };
+    bbe_reader::decode_to_writer(&log[..], std::io::sink(), options)?;
+
+    let csv = std::fs::read_to_string(&csv_path)?;
+    let gpx = std::fs::read_to_string(&gpx_path)?;
+    std::fs::remove_file(&csv_path)?;
+    std::fs::remove_file(&gpx_path)?;
+    assert_eq!(
+        csv,
+        "time,GPS_numSat,GPS_coord[0],GPS_coord[1]\n1010,9,3,-2\n1030,5,5,-4\n"
+    );
+    let points: Vec<&str> = gpx
+        .lines()
+        .filter(|line| line.starts_with("<trkpt"))
+        .collect();
+    assert_eq!(
+        points,
+        [
+            "<trkpt lat=\"0.0000003\" lon=\"-0.0000002\"></trkpt>",
+            "<trkpt lat=\"0.0000005\" lon=\"-0.0000004\"></trkpt>",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn detect_loop_overrun() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("looptime", "125");
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    // Loops at 0, 125, 300 and 425 µs: the second interval takes 175 µs
+    log.extend_from_slice(&[b'I', 0, 0, b'I', 1, 0x7d]);
+    log.extend_from_slice(&[b'I', 2, 0xac, 0x02, b'I', 3, 0xa9, 0x03]);
+
+    let options = DecodeOptions {
+        detect_loop_overrun: true,
+        ..DecodeOptions::default()
+    };
+    let stats = bbe_reader::decode_to_writer(&log[..], std::io::sink(), options)?;
+
+    assert_eq!((stats.overrun_intervals, stats.timed_intervals), (1, 3));
+    assert_eq!(stats.overrun_fraction(), Some(1.0 / 3.0));
+    Ok(())
+}
+
+#[test]
+fn detect_clipping() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("motorOutput", "48,2047");
+    log.header("Field I name", "loopIteration,motor[0],motor[1]");
+    log.header("Field I encoding", "1,1,1");
+    // motor[0] at 2047, 2047, 1500 and 2030, motor[1] holding 1000
+    log.extend_from_slice(&[b'I', 0, 0xff, 0x0f, 0xe8, 0x07]);
+    log.extend_from_slice(&[b'I', 1, 0xff, 0x0f, 0xe8, 0x07]);
+    log.extend_from_slice(&[b'I', 2, 0xdc, 0x0b, 0xe8, 0x07]);
+    log.extend_from_slice(&[b'I', 3, 0xee, 0x0f, 0xe8, 0x07]);
+
+    let options = DecodeOptions {
+        detect_clipping: true,
+        ..DecodeOptions::default()
+    };
+    let stats = bbe_reader::decode_to_writer(&log[..], std::io::sink(), options)?;
+
+    assert_eq!(stats.clipped_samples, vec![3, 0]);
+    assert_eq!(stats.clipping_fractions(), vec![0.75, 0.0]);
+    Ok(())
+}
+
+#[test]
+fn report_anomalies() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("minthrottle", "1070");
+    log.header("maxthrottle", "2000");
+    log.header("Field I name", "loopIteration,motor[0],motor[1]");
+    log.header("Field I encoding", "1,1,1");
+    // motor[0] at 1500, then 9000, motor[1] holding 1070
+    log.extend_from_slice(&[b'I', 0, 0xdc, 0x0b, 0xae, 0x08]);
+    log.extend_from_slice(&[b'I', 1, 0xa8, 0x46, 0xae, 0x08]);
+
+    let options = DecodeOptions {
+        report_anomalies: true,
+        ..DecodeOptions::default()
+    };
+    let stats = bbe_reader::decode_to_writer(&log[..], std::io::sink(), options)?;
+
+    assert_eq!(stats.motor_samples, 2);
+    assert_eq!(stats.anomalous_samples, vec![1, 0]);
+    Ok(())
+}
+
+#[test]
+fn dump_frame_bytes() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    let body: &[u8] = &[b'I', 0, 100, b'E', 0, 50, b'I', 1, 0xc8, 0x01, b'E', 255];
+    log.extend_from_slice(body);
+    log.extend_from_slice(b"End of log\0");
+
+    let path = temp_path("frame_bytes.bin");
+    let options = DecodeOptions {
+        dump_frame_bytes: Some(path.clone()),
+        ..DecodeOptions::default()
+    };
+    bbe_reader::decode_to_writer(&log[..], std::io::sink(), options)?;
+
+    let dump = std::fs::read(&path)?;
+    std::fs::remove_file(&path)?;
+    let mut rest = &dump[..];
+    let mut lengths = Vec::new();
+    while let Some((length, frame)) = rest.split_first_chunk::<4>() {
+        let length = u32::from_le_bytes(*length) as usize;
+        lengths.push(length);
+        rest = &frame[length..];
+    }
+
+    assert_eq!(lengths, [3, 3, 4, 13]);
+    assert_eq!(
+        lengths.iter().sum::<usize>(),
+        body.len() + b"End of log\0".len()
+    );
+    Ok(())
+}
+
+#[test]
+fn encoding_stats() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header(
+        "Field I name",
+        "loopIteration,time,axisD[0],axisD[1],axisD[2]",
+    );
+    log.header("Field I encoding", "1,1,7,7,7");
+    log.header("Field P predictor", "6,1,1,1,1");
+    log.header("Field P encoding", "0,0,6,6,6");
+    // time takes 2 bytes, the TAG2_3S32 group 1
+    log.extend_from_slice(&[b'I', 0, 0xe8, 0x07, 0x1e]);
+    // The TAG8_8SVB group: a tag byte flagging the first field, then its value
+    log.extend_from_slice(&[b'P', 0, 0x14, 0b001, 0x02]);
+
+    let options = DecodeOptions {
+        encoding_stats: true,
+        ..DecodeOptions::default()
+    };
+    let stats = bbe_reader::decode_to_writer(&log[..], std::io::sink(), options)?;
+
+    let usage = |fields, bytes| EncodingUsage { fields, bytes };
+    assert_eq!(
+        stats.encodings.into_iter().collect::<Vec<_>>(),
+        [
+            (0, usage(2, 2)),
+            (1, usage(2, 3)),
+            (6, usage(3, 2)),
+            (7, usage(3, 1))
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn column_stats() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time,axisP[0]");
+    log.header("Field I signed", "0,0,1");
+    log.header("Field I encoding", "1,1,0");
+    // axisP[0] is 2, -4 and 8
+    log.extend_from_slice(&[b'I', 0, 100, 0x04, b'I', 1, 0xc8, 0x01, 0x07]);
+    log.extend_from_slice(&[b'I', 2, 0xac, 0x02, 0x10]);
+
+    let options = DecodeOptions {
+        fields: vec!["time".to_string(), "axisP[0]".to_string()],
+        column_stats: true,
+        ..DecodeOptions::default()
+    };
+    let stats = bbe_reader::decode_to_writer(&log[..], std::io::sink(), options)?;
+
+    let [time, axis_p] = &stats.columns[..] else {
+        panic!("expected two columns");
+    };
+    assert_eq!((time.name.as_str(), time.count), ("time", 3));
+    assert_eq!((axis_p.min, axis_p.max, axis_p.mean), (-4.0, 8.0, 2.0));
+    assert_eq!(axis_p.std_dev(), 24f64.sqrt());
+    Ok(())
+}
+
+#[test]
+fn preview() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration");
+    log.header("Field I encoding", "1");
+    for iteration in 0..5 {
+        log.extend_from_slice(&[b'I', iteration]);
+    }
+
+    let path = temp_path("preview.csv");
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string()],
+        preview: Some(path.clone()),
+        preview_decimate: 2,
+        ..DecodeOptions::default()
+    };
+    let mut csv = Vec::new();
+    bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+
+    assert_eq!(String::from_utf8(csv)?, "loopIteration\n0\n1\n2\n3\n4\n");
+    let preview = std::fs::read_to_string(&path)?;
+    std::fs::remove_file(&path)?;
+    assert_eq!(preview, "loopIteration\n0\n2\n4\n");
+    Ok(())
+}
+
+#[test]
+fn flatten_arrays() {
+    let mut log = Log::new();
+    log.header("Field I name", "time,axisP[0],axisP[1]");
+    log.header("Field I encoding", "1,0,0");
+    log.extend_from_slice(&[b'I', 100, 2, 4]);
+
+    let header = |flatten_arrays| {
+        let options = DecodeOptions {
+            fields: vec!["time".into(), "axisP[0]".into(), "axisP[1]".into()],
+            flatten_arrays,
+            ..DecodeOptions::default()
+        };
+        let mut csv = Vec::new();
+        bbe_reader::decode_to_writer(&log[..], &mut csv, options).unwrap();
+        String::from_utf8(csv)
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap()
+            .to_string()
+    };
+
+    assert_eq!(header(false), "time,axisP[0],axisP[1]");
+    assert_eq!(header(true), "time,axisP_0,axisP_1");
+}
+
+#[test]
+fn renames() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time,axisP[0]");
+    log.header("Field I encoding", "1,1,0");
+    log.extend_from_slice(&[b'I', 0, 100, 2]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".into(), "time".into(), "axisP[0]".into()],
+        renames: [("time", "time_us"), ("axisP[0]", "roll_p")]
+            .into_iter()
+            .map(|(source, target)| (source.to_string(), target.to_string()))
+            .collect(),
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "loopIteration,time_us,roll_p\n0,100,1\n");
+    Ok(())
+}
+
+#[test]
+fn include_empty_fields() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".into(), "rssi".into(), "time".into()],
+        include_empty_fields: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "loopIteration,rssi,time\n0,,100\n1,,200\n");
+    Ok(())
+}
+
+#[test]
+fn preserve_order() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time,axisP[0]");
+    log.header("Field I encoding", "1,1,1");
+    log.extend_from_slice(&[b'I', 0, 100, 7]);
+
+    let options = DecodeOptions {
+        fields: vec!["axisP[0]".into(), "time".into(), "loopIteration".into()],
+        preserve_order: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "loopIteration,time,axisP[0]\n0,100,7\n");
+    Ok(())
+}
+
+#[test]
+fn byte_range() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01, b'I', 2, 0xac, 0x02]);
+
+    // Start inside the first frame
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        byte_range: Some(1..usize::MAX),
+        ..DecodeOptions::default()
+    };
+    let mut csv = Vec::new();
+    let stats = bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "loopIteration,time\n1,200\n2,300\n"
+    );
+    assert_eq!(stats.skipped_bytes, 2);
+    Ok(())
+}
+
+#[test]
+fn resume_offset() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01]);
+    // An earlier run decoded the log up to here, then more frames were written
+    let resume_offset = log.len();
+    log.extend_from_slice(&[b'I', 2, 0xac, 0x02, b'I', 3, 0x90, 0x03]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        resume_offset: Some(resume_offset),
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(csv, "loopIteration,time\n2,300\n3,400\n");
+    Ok(())
+}
+
+#[test]
+fn noise_prep() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.extend_from_slice(b"H Field I name:time,gyroADC[0],gyroADC[1],gyroADC[2],");
+    log.extend_from_slice(b"motor[0],motor[1],motor[2],motor[3]\n");
+    log.header("Field I encoding", "1,0,0,0,1,1,1,1");
+    // Motors 1200, 1300, 1400 and 1500
+    log.extend_from_slice(&[b'I', 100, 2, 4, 6]);
+    log.extend_from_slice(&[0xb0, 0x09, 0x94, 0x0a, 0xf8, 0x0a, 0xdc, 0x0b]);
+
+    let options = DecodeOptions {
+        noise_prep: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(
+        csv,
+        "time,gyroADC[0],gyroADC[1],gyroADC[2],throttle\n100,1,2,3,1350\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn motors() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("motorOutput", "48,2047");
+    log.header(
+        "Field I name",
+        "loopIteration,motor[0],motor[1],motor[2],motor[3]",
+    );
+    log.header("Field I signed", "0,0,0,0,0");
+    // motor[0] from the low end of motorOutput, the others from motor[0]
+    log.header("Field I predictor", "0,11,5,5,5");
+    log.header("Field I encoding", "1,1,0,0,0");
+    log.header("Field P predictor", "6,1,1,1,1");
+    log.header("Field P encoding", "0,0,0,0,0");
+    // Motors 1000, 1047, 990 and 1000, then 1010, 1040, 990 and 1020
+    log.extend_from_slice(&[b'I', 0, 0xb8, 0x07, 0x5e, 0x13, 0x00]);
+    log.extend_from_slice(&[b'P', 0, 0x14, 0x0d, 0x00, 0x28]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string()],
+        motors: true,
+        ..DecodeOptions::default()
+    };
+    let mut csv = Vec::new();
+    bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "loopIteration,motor[0],motor[1],motor[2],motor[3],\
+         motor[0]_pct,motor[1]_pct,motor[2]_pct,motor[3]_pct\n\
+         0,1000,1047,990,1000,47.6,50.0,47.1,47.6\n\
+         1,1010,1040,990,1020,48.1,49.6,47.1,48.6\n"
+    );
+
+    // DSHOT600, whose digital range replaces minthrottle and maxthrottle in
+    // clipping and anomaly detection too
+    let mut log = Log::new();
+    log.header("motor_pwm_protocol", "6");
+    log.header("minthrottle", "1070");
+    log.header("maxthrottle", "2000");
+    log.header("Field I name", "loopIteration,motor[0],motor[1]");
+    log.header("Field I encoding", "1,1,1");
+    // motor[0] stopped, then at full throttle, motor[1] holding 1048
+    log.extend_from_slice(&[b'I', 0, 0, 0x98, 0x08]);
+    log.extend_from_slice(&[b'I', 1, 0xff, 0x0f, 0x98, 0x08]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string()],
+        motors: true,
+        detect_clipping: true,
+        report_anomalies: true,
+        ..DecodeOptions::default()
+    };
+    let mut csv = Vec::new();
+    let stats = bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "loopIteration,motor[0],motor[1],motor[0]_pct,motor[1]_pct\n\
+         0,0,1048,0.0,50.0\n\
+         1,2047,1048,100.0,50.0\n"
+    );
+    assert_eq!(stats.clipped_samples, vec![1, 0]);
+    assert_eq!(stats.anomalous_samples, vec![0, 0]);
+    Ok(())
+}
+
+#[test]
+fn long_format() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.extend_from_slice(b"H Field I name:time,axisP[0],axisP[1],axisP[2],gyroADC[0],");
+    log.extend_from_slice(b"gyroADC[1],gyroADC[2],setpoint[3]\n");
+    log.header("Field I encoding", "1,1,1,1,1,1,1,1");
+    log.extend_from_slice(&[b'I', 100, 1, 2, 3, 4, 5, 6, 50]);
+
+    let options = DecodeOptions {
+        fields: [
+            "time",
+            "axisP[0]",
+            "axisP[1]",
+            "axisP[2]",
+            "gyroADC[0]",
+            "gyroADC[1]",
+            "gyroADC[2]",
+            "setpoint[3]",
+        ]
+        .map(String::from)
+        .into(),
+        long_format: true,
+        ..DecodeOptions::default()
+    };
+    let csv = decode_csv(&log, options)?;
+
+    assert_eq!(
+        csv,
+        "time,setpoint[3],axis,axisP,gyroADC\n\
+         100,50,0,1,4\n\
+         100,50,1,2,5\n\
+         100,50,2,3,6\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn pretty_json() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Craft name", "Quad \"A\", 5 inch");
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.extend_from_slice(&[b'I', 0, 100, b'I', 1, 0xc8, 0x01]);
+
+    let options = DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        format: OutputFormat::JsonLines,
+        ..DecodeOptions::default()
+    };
+    let mut compact = Vec::new();
+    bbe_reader::decode_to_writer(&log[..], &mut compact, options.clone())?;
+    let options = DecodeOptions {
+        pretty_json: true,
+        ..options
+    };
+    let mut pretty = Vec::new();
+    bbe_reader::decode_to_writer(&log[..], &mut pretty, options)?;
+    let pretty = String::from_utf8(pretty).unwrap();
+
+    assert!(pretty.ends_with("{\n  \"loopIteration\": 1,\n  \"time\": 200\n}\n"));
+
+    // Without the whitespace between tokens, both hold the same JSON
+    let minify = |json: &str| {
+        let (mut minified, mut in_string, mut escaped) = (String::new(), false, false);
+        for c in json.chars() {
+            if in_string || !c.is_whitespace() {
+                minified.push(c);
+            }
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = in_string,
+                '"' => in_string = !in_string,
+                _ => {}
+            }
+        }
+        minified
+    };
+    assert_eq!(
+        minify(&pretty),
+        minify(&String::from_utf8(compact).unwrap())
+    );
+    assert!(minify(&pretty).contains("\"craft_name\":\"Quad \\\"A\\\", 5 inch\""));
+    Ok(())
+}
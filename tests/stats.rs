@@ -0,0 +1,87 @@
+//! Checks the counters `DecodeStats` collects while decoding small logs written with
+//! `common::Log`.
+
+use common::Log;
+
+mod common;
+
+#[test]
+fn resync_skips_corrupt_bytes_to_the_next_keyframe() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    // Three corrupt bytes between two keyframes
+    log.extend_from_slice(&[b'I', 0, 100, b'Z', 7, 7, b'I', 2, 0xac, 0x02]);
+
+    let options = bbe_reader::DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        ..Default::default()
+    };
+    let mut csv = Vec::new();
+    let stats = bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+
+    assert_eq!((stats.resyncs, stats.skipped_bytes), (1, 3));
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "loopIteration,time\n0,100\n2,300\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn p_frames_before_the_first_keyframe_are_skipped() -> Result<(), Box<dyn std::error::Error>> {
+    let mut log = Log::new();
+    log.header("Field I name", "loopIteration,time");
+    log.header("Field I encoding", "1,1");
+    log.header("Field P predictor", "6,1");
+    log.header("Field P encoding", "0,0");
+    // The log starts on a P-frame, 10 µs after a keyframe that was not logged
+    log.extend_from_slice(&[b'P', 0, 0x14, b'I', 5, 100, b'P', 0, 0x14]);
+
+    let options = bbe_reader::DecodeOptions {
+        fields: vec!["loopIteration".to_string(), "time".to_string()],
+        ..Default::default()
+    };
+    let mut csv = Vec::new();
+    let stats = bbe_reader::decode_to_writer(&log[..], &mut csv, options)?;
+
+    assert_eq!(stats.unanchored_frames, 1);
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "loopIteration,time\n5,100\n6,110\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn keyframes_off_the_i_interval_are_counted() -> Result<(), Box<dyn std::error::Error>> {
+    // A keyframe every 4 frames, each P-frame 10 µs after the frame before it
+    let deviations = |frames: &[u8]| -> Result<usize, bbe_reader::BblError> {
+        let mut log = Log::new();
+        log.header("I interval", "4");
+        log.header("Field I name", "loopIteration,time");
+        log.header("Field I encoding", "1,1");
+        log.header("Field P predictor", "6,1");
+        log.header("Field P encoding", "0,0");
+        let mut time = 0;
+        for (iteration, &marker) in frames.iter().enumerate() {
+            time += 10;
+            match marker {
+                b'I' => log.extend_from_slice(&[b'I', iteration as u8, time]),
+                _ => log.extend_from_slice(&[b'P', 0, 0x14]),
+            }
+        }
+        let stats = bbe_reader::decode_to_writer(&log[..], std::io::sink(), Default::default())?;
+        assert_eq!(stats.frames_per_interval, Some(4));
+        Ok(stats.i_interval_deviations)
+    };
+
+    assert_eq!(deviations(b"IPPPIPPPI")?, 0);
+    // A fourth P-frame where the keyframe was due
+    assert_eq!(deviations(b"IPPPPIPPP")?, 1);
+    // A keyframe after two P-frames instead of three
+    assert_eq!(deviations(b"IPPIPPPI")?, 1);
+    // Both, in one log
+    assert_eq!(deviations(b"IPPPPIPPIPPP")?, 2);
+    Ok(())
+}
@@ -0,0 +1,156 @@
+//! Streams a log of a million main frames, generated as it is read, through
+//! `decode_to_writer` and checks every row against the values the frames encode, with
+//! the heap usage of the whole decode tracked by a counting allocator. Only the input
+//! window and the two previous frames a predictor refers to may be held, so the peak
+//! stays far below the size of either the log or its CSV.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bbe_reader::DecodeOptions;
+
+use common::{gyro, push_signed, push_unsigned};
+
+mod common;
+
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(allocated, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const FRAMES: i64 = 1_000_000;
+
+const HEADERS: &str = "\
+H Product:Blackbox flight data recorder by Nicholas Sherlock
+H Field I name:loopIteration,time,gyroADC[0]
+H Field I signed:0,0,1
+H Field I encoding:1,1,0
+H Field P predictor:6,1,2
+H Field P encoding:0,0,0
+";
+
+/// Produces the headers, an I-frame and then P-frames one at a time as they are read,
+/// each frame 125 µs and one iteration after the one before it.
+struct GeneratedLog {
+    pending: Vec<u8>,
+    position: usize,
+    next_frame: i64,
+}
+
+impl GeneratedLog {
+    fn new() -> Self {
+        GeneratedLog {
+            pending: HEADERS.as_bytes().to_vec(),
+            position: 0,
+            next_frame: 0,
+        }
+    }
+
+    fn push_frame(&mut self) {
+        let k = self.next_frame;
+        self.next_frame += 1;
+        if k == 0 {
+            self.pending.push(b'I');
+            push_unsigned(&mut self.pending, 0);
+            push_unsigned(&mut self.pending, 1000);
+            push_signed(&mut self.pending, gyro(0));
+        } else {
+            // After the I-frame, both previous frames are the I-frame
+            let previous2 = gyro((k - 2).max(0));
+            let line = 2 * gyro(k - 1) - previous2;
+            self.pending.push(b'P');
+            push_signed(&mut self.pending, 0);
+            push_signed(&mut self.pending, 125);
+            push_signed(&mut self.pending, gyro(k) - line);
+        }
+    }
+}
+
+impl Read for GeneratedLog {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.pending.len() && self.next_frame < FRAMES {
+            self.pending.clear();
+            self.position = 0;
+            self.push_frame();
+        }
+        let len = buf.len().min(self.pending.len() - self.position);
+        buf[..len].copy_from_slice(&self.pending[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// Checks each CSV row as it is written, keeping only the incomplete last line.
+struct CheckedRows {
+    line: Vec<u8>,
+    rows: i64,
+}
+
+impl Write for CheckedRows {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            if byte != b'\n' {
+                self.line.push(byte);
+                continue;
+            }
+            let line = std::str::from_utf8(&self.line).unwrap();
+            if self.rows == -1 {
+                assert_eq!(line, "loopIteration,time,gyroADC[0]");
+            } else {
+                let k = self.rows;
+                let expected = format!("{},{},{}", k, 1000 + 125 * k, gyro(k));
+                assert_eq!(line, expected, "row {}", k);
+            }
+            self.rows += 1;
+            self.line.clear();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn long_log_streams_in_bounded_memory() {
+    let options = DecodeOptions {
+        fields: vec![
+            "loopIteration".to_string(),
+            "time".to_string(),
+            "gyroADC[0]".to_string(),
+        ],
+        ..DecodeOptions::default()
+    };
+    let mut rows = CheckedRows {
+        line: Vec::new(),
+        rows: -1,
+    };
+
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let stats = bbe_reader::decode_to_writer(GeneratedLog::new(), &mut rows, options).unwrap();
+    let peak = PEAK.load(Ordering::Relaxed) - before;
+
+    assert_eq!(stats.rows_written, FRAMES as usize);
+    assert_eq!(rows.rows, FRAMES);
+    // The log is over 5 MB and its CSV over 20 MB
+    assert!(peak < 1 << 20, "peak heap usage of {} bytes", peak);
+}